@@ -0,0 +1,26 @@
+//! CPU-side bus throughput over the regions games hit most: WRAM, HRAM, VRAM, IF and TMA.
+//! Run with `cargo bench --bench bus`, the figure is the best of a few runs.
+
+use std::hint::black_box;
+use std::time::Instant;
+use gbc_rs::bus::BUS;
+
+const ADDRESSES: [u16; 8] = [0xC000, 0xD123, 0xFF80, 0xFFFE, 0x8000, 0x9FFF, 0xFF0F, 0xFF06];
+const ACCESSES: usize = 10_000_000;
+const RUNS: usize = 5;
+
+fn main() {
+    let mut bus = BUS::new();
+    let mut best = f64::MAX;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        for i in 0..ACCESSES {
+            let address = ADDRESSES[i % ADDRESSES.len()];
+            bus.write(address, i as u8).unwrap();
+            black_box(bus.read(address).unwrap());
+        }
+        best = best.min(start.elapsed().as_secs_f64());
+    }
+
+    println!("bus read/write: {:.1}M accesses/s", (ACCESSES * 2) as f64 / best / 1e6);
+}
@@ -0,0 +1,39 @@
+#!/usr/bin/env bash
+# Runs mooneye-test-suite ROMs headless and prints one line per ROM.
+#
+#   scripts/mooneye.sh path/to/mooneye-test-suite/acceptance/timer [frames]
+#
+# The ROMs report over serial: the Fibonacci bytes 3, 5, 8, 13, 21, 34 when they pass,
+# six 0x42 bytes ("BBBBBB") when they fail. Each ROM gets `frames` frames (default 600)
+# to do either. Exits with status 1 if any ROM did not pass.
+
+set -u
+
+dir=${1:?usage: scripts/mooneye.sh <rom directory> [frames]}
+frames=${2:-600}
+pass=$'\x03\x05\x08\x0d\x15\x22'
+fail='BBBBBB'
+
+cd "$(dirname "$0")/.." || exit 3
+if ! build=$(cargo build --release --quiet --no-default-features --features log 2>&1); then
+    echo "$build" >&2
+    exit 3
+fi
+binary=target/release/gbc-rs
+
+total=0
+passed=0
+for rom in $(find "$dir" -name '*.gb' | sort); do
+    total=$((total + 1))
+    "$binary" "$rom" --headless "$frames" --until "serial:$pass" --fail-if "serial:$fail" > /dev/null 2>&1
+    case $? in
+        0) result=pass; passed=$((passed + 1)) ;;
+        1) result=FAIL ;;
+        2) result=TIMEOUT ;;
+        *) result=ERROR ;;
+    esac
+    printf '%-8s %s\n' "$result" "${rom#"$dir"/}"
+done
+
+echo "$passed/$total passed"
+[ "$passed" -eq "$total" ]
@@ -0,0 +1,561 @@
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1],
+    [1, 0, 0, 0, 0, 0, 0, 1],
+    [1, 0, 0, 0, 0, 1, 1, 1],
+    [0, 1, 1, 1, 1, 1, 1, 0],
+];
+
+const NOISE_DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+pub struct LengthCounter {
+    pub enabled: bool,
+    pub counter: u16,
+    max: u16,
+}
+
+impl LengthCounter {
+    pub fn new(max: u16) -> LengthCounter {
+        LengthCounter {
+            enabled: false,
+            counter: 0,
+            max,
+        }
+    }
+
+    pub fn load(&mut self, value: u16) {
+        self.counter = self.max - value;
+    }
+
+    pub fn trigger(&mut self) {
+        if self.counter == 0 {
+            self.counter = self.max;
+        }
+    }
+
+    /// Returns false once the counter expires and the channel must be disabled.
+    pub fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter != 0;
+        }
+
+        true
+    }
+}
+
+pub struct Envelope {
+    pub initial_volume: u8,
+    pub increase: bool,
+    pub period: u8,
+    pub volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    pub fn new() -> Envelope {
+        Envelope {
+            initial_volume: 0,
+            increase: false,
+            period: 0,
+            volume: 0,
+            timer: 0,
+        }
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.initial_volume = data >> 4;
+        self.increase = (data & 0x08) != 0;
+        self.period = data & 0x07;
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.initial_volume != 0 || self.increase
+    }
+
+    pub fn trigger(&mut self) {
+        self.volume = self.initial_volume;
+        self.timer = self.period;
+    }
+
+    pub fn clock(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.increase && self.volume < 15 {
+                self.volume += 1;
+            } else if !self.increase && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+}
+
+pub struct SquareChannel {
+    pub enabled: bool,
+    pub duty: u8,
+    pub frequency: u16,
+    pub length: LengthCounter,
+    pub envelope: Envelope,
+    has_sweep: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_timer: u8,
+    sweep_enabled: bool,
+    shadow_frequency: u16,
+    freq_timer: u32,
+    duty_pos: u8,
+}
+
+impl SquareChannel {
+    pub fn new(has_sweep: bool) -> SquareChannel {
+        SquareChannel {
+            enabled: false,
+            duty: 0,
+            frequency: 0,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+            has_sweep,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_timer: 0,
+            sweep_enabled: false,
+            shadow_frequency: 0,
+            freq_timer: 0,
+            duty_pos: 0,
+        }
+    }
+
+    pub fn write_sweep(&mut self, data: u8) {
+        self.sweep_period = (data >> 4) & 0x07;
+        self.sweep_negate = (data & 0x08) != 0;
+        self.sweep_shift = data & 0x07;
+    }
+
+    pub fn write_length_duty(&mut self, data: u8) {
+        self.duty = data >> 6;
+        self.length.load((data & 0x3F) as u16);
+    }
+
+    pub fn write_envelope(&mut self, data: u8) {
+        self.envelope.write(data);
+        if !self.envelope.dac_enabled() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn write_frequency_low(&mut self, data: u8) {
+        self.frequency = (self.frequency & 0x700) | data as u16;
+    }
+
+    pub fn write_control(&mut self, data: u8) {
+        self.frequency = (self.frequency & 0xFF) | (((data & 0x07) as u16) << 8);
+        self.length.enabled = (data & 0x40) != 0;
+        if (data & 0x80) != 0 {
+            self.trigger();
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.length.trigger();
+        self.envelope.trigger();
+        self.freq_timer = (2048 - self.frequency as u32) * 4;
+
+        if self.has_sweep {
+            self.shadow_frequency = self.frequency;
+            self.sweep_timer = if self.sweep_period == 0 { 8 } else { self.sweep_period };
+            self.sweep_enabled = self.sweep_period != 0 || self.sweep_shift != 0;
+            if self.sweep_shift != 0 && self.sweep_frequency() > 0x7FF {
+                self.enabled = false;
+            }
+        }
+    }
+
+    fn sweep_frequency(&self) -> u16 {
+        let delta = self.shadow_frequency >> self.sweep_shift;
+        if self.sweep_negate {
+            self.shadow_frequency.wrapping_sub(delta)
+        } else {
+            self.shadow_frequency + delta
+        }
+    }
+
+    pub fn clock_sweep(&mut self) {
+        if !self.has_sweep {
+            return;
+        }
+
+        if self.sweep_timer > 0 {
+            self.sweep_timer -= 1;
+        }
+
+        if self.sweep_timer != 0 {
+            return;
+        }
+
+        self.sweep_timer = if self.sweep_period == 0 { 8 } else { self.sweep_period };
+        if !self.sweep_enabled || self.sweep_period == 0 {
+            return;
+        }
+
+        let new_frequency = self.sweep_frequency();
+        if new_frequency > 0x7FF {
+            self.enabled = false;
+            return;
+        }
+
+        if self.sweep_shift != 0 {
+            self.shadow_frequency = new_frequency;
+            self.frequency = new_frequency;
+            if self.sweep_frequency() > 0x7FF {
+                self.enabled = false;
+            }
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if !self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles > 0 {
+            if self.freq_timer > cycles {
+                self.freq_timer -= cycles;
+                return;
+            }
+
+            cycles -= self.freq_timer;
+            self.freq_timer = (2048 - self.frequency as u32) * 4;
+            self.duty_pos = (self.duty_pos + 1) & 0x07;
+        }
+    }
+
+    /// Digital output of the channel, 0 to 15.
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+
+        DUTY_TABLE[self.duty as usize][self.duty_pos as usize] * self.envelope.volume
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.envelope.dac_enabled()
+    }
+}
+
+pub struct WaveChannel {
+    pub enabled: bool,
+    pub dac_enabled: bool,
+    pub output_level: u8,
+    pub frequency: u16,
+    pub length: LengthCounter,
+    pub wave_ram: [u8; 16],
+    freq_timer: u32,
+    position: u8,
+    sample: u8,
+}
+
+impl WaveChannel {
+    pub fn new() -> WaveChannel {
+        WaveChannel {
+            enabled: false,
+            dac_enabled: false,
+            output_level: 0,
+            frequency: 0,
+            length: LengthCounter::new(256),
+            wave_ram: [0; 16],
+            freq_timer: 0,
+            position: 0,
+            sample: 0,
+        }
+    }
+
+    pub fn write_dac(&mut self, data: u8) {
+        self.dac_enabled = (data & 0x80) != 0;
+        if !self.dac_enabled {
+            self.enabled = false;
+        }
+    }
+
+    pub fn write_length(&mut self, data: u8) {
+        self.length.load(data as u16);
+    }
+
+    pub fn write_output_level(&mut self, data: u8) {
+        self.output_level = (data >> 5) & 0x03;
+    }
+
+    pub fn write_frequency_low(&mut self, data: u8) {
+        self.frequency = (self.frequency & 0x700) | data as u16;
+    }
+
+    pub fn write_control(&mut self, data: u8) {
+        self.frequency = (self.frequency & 0xFF) | (((data & 0x07) as u16) << 8);
+        self.length.enabled = (data & 0x40) != 0;
+        if (data & 0x80) != 0 {
+            self.enabled = self.dac_enabled;
+            self.length.trigger();
+            self.freq_timer = (2048 - self.frequency as u32) * 2;
+            self.position = 0;
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if !self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles > 0 {
+            if self.freq_timer > cycles {
+                self.freq_timer -= cycles;
+                return;
+            }
+
+            cycles -= self.freq_timer;
+            self.freq_timer = (2048 - self.frequency as u32) * 2;
+            self.position = (self.position + 1) & 0x1F;
+            let byte = self.wave_ram[(self.position / 2) as usize];
+            self.sample = if self.position & 1 == 0 { byte >> 4 } else { byte & 0x0F };
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+
+        match self.output_level {
+            0 => 0,
+            1 => self.sample,
+            2 => self.sample >> 1,
+            _ => self.sample >> 2,
+        }
+    }
+}
+
+pub struct NoiseChannel {
+    pub enabled: bool,
+    pub length: LengthCounter,
+    pub envelope: Envelope,
+    clock_shift: u8,
+    short_mode: bool,
+    divisor_code: u8,
+    lfsr: u16,
+    freq_timer: u32,
+}
+
+impl NoiseChannel {
+    pub fn new() -> NoiseChannel {
+        NoiseChannel {
+            enabled: false,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+            clock_shift: 0,
+            short_mode: false,
+            divisor_code: 0,
+            lfsr: 0x7FFF,
+            freq_timer: 0,
+        }
+    }
+
+    pub fn write_length(&mut self, data: u8) {
+        self.length.load((data & 0x3F) as u16);
+    }
+
+    pub fn write_envelope(&mut self, data: u8) {
+        self.envelope.write(data);
+        if !self.envelope.dac_enabled() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn write_polynomial(&mut self, data: u8) {
+        self.clock_shift = data >> 4;
+        self.short_mode = (data & 0x08) != 0;
+        self.divisor_code = data & 0x07;
+    }
+
+    pub fn write_control(&mut self, data: u8) {
+        self.length.enabled = (data & 0x40) != 0;
+        if (data & 0x80) != 0 {
+            self.enabled = self.envelope.dac_enabled();
+            self.length.trigger();
+            self.envelope.trigger();
+            self.lfsr = 0x7FFF;
+            self.freq_timer = self.period();
+        }
+    }
+
+    fn period(&self) -> u32 {
+        NOISE_DIVISORS[self.divisor_code as usize] << self.clock_shift
+    }
+
+    pub fn clock_length(&mut self) {
+        if !self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles > 0 {
+            if self.freq_timer > cycles {
+                self.freq_timer -= cycles;
+                return;
+            }
+
+            cycles -= self.freq_timer;
+            self.freq_timer = self.period();
+
+            let xor = (self.lfsr & 0x01) ^ ((self.lfsr >> 1) & 0x01);
+            self.lfsr = (self.lfsr >> 1) | (xor << 14);
+            if self.short_mode {
+                self.lfsr = (self.lfsr & !(1 << 6)) | (xor << 6);
+            }
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+
+        ((!self.lfsr & 0x01) as u8) * self.envelope.volume
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.envelope.dac_enabled()
+    }
+}
+
+/*
+    Channel state for the APU's save state section, written field by field in
+    declaration order. Register values the channels derive from are saved by the APU.
+*/
+
+impl LengthCounter {
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.bool(self.enabled);
+        writer.u16(self.counter);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.enabled = reader.bool()?;
+        self.counter = reader.u16()?;
+        Ok(())
+    }
+}
+
+impl Envelope {
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.bytes(&[self.initial_volume, self.increase as u8, self.period, self.volume, self.timer]);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.initial_volume = reader.u8()?;
+        self.increase = reader.bool()?;
+        self.period = reader.u8()?;
+        self.volume = reader.u8()?;
+        self.timer = reader.u8()?;
+        Ok(())
+    }
+}
+
+impl SquareChannel {
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.bool(self.enabled);
+        writer.u8(self.duty);
+        writer.u16(self.frequency);
+        self.length.save_state(writer);
+        self.envelope.save_state(writer);
+        writer.bytes(&[self.sweep_period, self.sweep_negate as u8, self.sweep_shift, self.sweep_timer, self.sweep_enabled as u8]);
+        writer.u16(self.shadow_frequency);
+        writer.u32(self.freq_timer);
+        writer.u8(self.duty_pos);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.enabled = reader.bool()?;
+        self.duty = reader.u8()? & 0x03;
+        self.frequency = reader.u16()? & 0x7FF;
+        self.length.load_state(reader)?;
+        self.envelope.load_state(reader)?;
+        self.sweep_period = reader.u8()? & 0x07;
+        self.sweep_negate = reader.bool()?;
+        self.sweep_shift = reader.u8()? & 0x07;
+        self.sweep_timer = reader.u8()?;
+        self.sweep_enabled = reader.bool()?;
+        self.shadow_frequency = reader.u16()?;
+        self.freq_timer = reader.u32()?;
+        self.duty_pos = reader.u8()? & 0x07;
+        Ok(())
+    }
+}
+
+impl WaveChannel {
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.bool(self.enabled);
+        writer.bool(self.dac_enabled);
+        writer.u8(self.output_level);
+        writer.u16(self.frequency);
+        self.length.save_state(writer);
+        writer.bytes(&self.wave_ram);
+        writer.u32(self.freq_timer);
+        writer.u8(self.position);
+        writer.u8(self.sample);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.enabled = reader.bool()?;
+        self.dac_enabled = reader.bool()?;
+        self.output_level = reader.u8()? & 0x03;
+        self.frequency = reader.u16()? & 0x7FF;
+        self.length.load_state(reader)?;
+        reader.bytes(&mut self.wave_ram)?;
+        self.freq_timer = reader.u32()?;
+        self.position = reader.u8()? & 0x1F;
+        self.sample = reader.u8()?;
+        Ok(())
+    }
+}
+
+impl NoiseChannel {
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.bool(self.enabled);
+        self.length.save_state(writer);
+        self.envelope.save_state(writer);
+        writer.bytes(&[self.clock_shift, self.short_mode as u8, self.divisor_code]);
+        writer.u16(self.lfsr);
+        writer.u32(self.freq_timer);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.enabled = reader.bool()?;
+        self.length.load_state(reader)?;
+        self.envelope.load_state(reader)?;
+        self.clock_shift = reader.u8()? & 0x0F;
+        self.short_mode = reader.bool()?;
+        self.divisor_code = reader.u8()? & 0x07;
+        self.lfsr = reader.u16()?;
+        self.freq_timer = reader.u32()?;
+        Ok(())
+    }
+}
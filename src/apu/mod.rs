@@ -0,0 +1,395 @@
+use crate::apu::channels::{NoiseChannel, SquareChannel, WaveChannel};
+use crate::apu::wav::AudioCapture;
+use crate::debug::log::{Logger, LoggerTrait};
+use crate::emulator::Model;
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+
+mod channels;
+pub mod wav;
+
+pub const CPU_FREQUENCY: u32 = 4_194_304;
+pub const SAMPLE_RATE: u32 = 44_100;
+
+const FRAME_SEQUENCER_PERIOD: u32 = 8192;
+
+/*
+    Bits that always read back as 1 for 0xFF10 - 0xFF2F.
+*/
+const READ_MASKS: [u8; 0x20] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, 0xFF, 0x3F, 0x00,
+    0xFF, 0xBF, 0x7F, 0xFF, 0x9F, 0xFF, 0xBF, 0xFF,
+    0xFF, 0x00, 0x00, 0xBF, 0x00, 0x00, 0x70, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+];
+
+#[derive(Debug)]
+pub enum ApuError {
+    AlreadyCapturing,
+    NotCapturing,
+    IoError(std::io::Error),
+}
+
+impl From<std::io::Error> for ApuError {
+    fn from(e: std::io::Error) -> ApuError {
+        ApuError::IoError(e)
+    }
+}
+
+pub struct APU {
+    /// DMG-family APUs keep their length counters while powered off.
+    pub model: Model,
+    enabled: bool,
+    registers: [u8; 0x20],
+    ch1: SquareChannel,
+    ch2: SquareChannel,
+    ch3: WaveChannel,
+    ch4: NoiseChannel,
+    frame_sequencer_step: u8,
+    frame_sequencer_cycles: u32,
+    sample_clock: u32,
+    capture: Option<AudioCapture>,
+    /// Interleaved stereo samples kept until the frontend takes them, `None` when off.
+    samples: Option<Vec<i16>>,
+}
+
+impl APU {
+    pub fn new() -> APU {
+        APU {
+            model: Model::Dmg,
+            enabled: true,
+            registers: [0; 0x20],
+            ch1: SquareChannel::new(true),
+            ch2: SquareChannel::new(false),
+            ch3: WaveChannel::new(),
+            ch4: NoiseChannel::new(),
+            frame_sequencer_step: 0,
+            frame_sequencer_cycles: 0,
+            sample_clock: 0,
+            capture: None,
+            samples: None,
+        }
+    }
+
+    pub fn read(&self, address: u8) -> u8 {
+        match address {
+            0x26 => {
+                let mut status = READ_MASKS[0x16] | ((self.enabled as u8) << 7);
+                status |= self.ch1.enabled as u8;
+                status |= (self.ch2.enabled as u8) << 1;
+                status |= (self.ch3.enabled as u8) << 2;
+                status |= (self.ch4.enabled as u8) << 3;
+                status
+            }
+            0x10..=0x2F => {
+                let offset = (address - 0x10) as usize;
+                self.registers[offset] | READ_MASKS[offset]
+            }
+            0x30..=0x3F => self.ch3.wave_ram[(address - 0x30) as usize],
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, address: u8, data: u8) {
+        if let 0x30..=0x3F = address {
+            self.ch3.wave_ram[(address - 0x30) as usize] = data;
+            return;
+        }
+
+        if address == 0x26 {
+            self.set_power((data & 0x80) != 0);
+            return;
+        }
+
+        if !self.enabled {
+            if !self.model.is_cgb() {
+                self.write_length_powered_off(address, data);
+            }
+            return;
+        }
+        if !(0x10..=0x2F).contains(&address) {
+            return;
+        }
+
+        self.registers[(address - 0x10) as usize] = data;
+        match address {
+            0x10 => self.ch1.write_sweep(data),
+            0x11 => self.ch1.write_length_duty(data),
+            0x12 => self.ch1.write_envelope(data),
+            0x13 => self.ch1.write_frequency_low(data),
+            0x14 => self.ch1.write_control(data),
+            0x16 => self.ch2.write_length_duty(data),
+            0x17 => self.ch2.write_envelope(data),
+            0x18 => self.ch2.write_frequency_low(data),
+            0x19 => self.ch2.write_control(data),
+            0x1A => self.ch3.write_dac(data),
+            0x1B => self.ch3.write_length(data),
+            0x1C => self.ch3.write_output_level(data),
+            0x1D => self.ch3.write_frequency_low(data),
+            0x1E => self.ch3.write_control(data),
+            0x20 => self.ch4.write_length(data),
+            0x21 => self.ch4.write_envelope(data),
+            0x22 => self.ch4.write_polynomial(data),
+            0x23 => self.ch4.write_control(data),
+            _ => {}
+        }
+    }
+
+    /// Only the length part of NRx1 is written, the duty stays cleared.
+    fn write_length_powered_off(&mut self, address: u8, data: u8) {
+        match address {
+            0x11 => self.ch1.length.load((data & 0x3F) as u16),
+            0x16 => self.ch2.length.load((data & 0x3F) as u16),
+            0x1B => self.ch3.length.load(data as u16),
+            0x20 => self.ch4.length.load((data & 0x3F) as u16),
+            _ => {}
+        }
+    }
+
+    fn set_power(&mut self, on: bool) {
+        if self.enabled && !on {
+            let wave_ram = self.ch3.wave_ram;
+            let lengths = [self.ch1.length.counter, self.ch2.length.counter, self.ch3.length.counter, self.ch4.length.counter];
+            self.registers = [0; 0x20];
+            self.ch1 = SquareChannel::new(true);
+            self.ch2 = SquareChannel::new(false);
+            self.ch3 = WaveChannel::new();
+            self.ch3.wave_ram = wave_ram;
+            self.ch4 = NoiseChannel::new();
+            if !self.model.is_cgb() {
+                self.ch1.length.counter = lengths[0];
+                self.ch2.length.counter = lengths[1];
+                self.ch3.length.counter = lengths[2];
+                self.ch4.length.counter = lengths[3];
+            }
+        }
+
+        if !self.enabled && on {
+            self.frame_sequencer_step = 0;
+        }
+
+        self.enabled = on;
+    }
+
+    fn frame_sequencer_tick(&mut self) {
+        match self.frame_sequencer_step {
+            0 | 4 => self.clock_lengths(),
+            2 | 6 => {
+                self.clock_lengths();
+                self.ch1.clock_sweep();
+            }
+            7 => {
+                self.ch1.envelope.clock();
+                self.ch2.envelope.clock();
+                self.ch4.envelope.clock();
+            }
+            _ => {}
+        }
+
+        self.frame_sequencer_step = (self.frame_sequencer_step + 1) & 0x07;
+    }
+
+    fn clock_lengths(&mut self) {
+        self.ch1.clock_length();
+        self.ch2.clock_length();
+        self.ch3.clock_length();
+        self.ch4.clock_length();
+    }
+
+    pub fn apu_tick(&mut self, cycles: u32) {
+        if self.enabled {
+            self.ch1.tick(cycles);
+            self.ch2.tick(cycles);
+            self.ch3.tick(cycles);
+            self.ch4.tick(cycles);
+
+            self.frame_sequencer_cycles += cycles;
+            if self.frame_sequencer_cycles >= FRAME_SEQUENCER_PERIOD {
+                self.frame_sequencer_cycles -= FRAME_SEQUENCER_PERIOD;
+                self.frame_sequencer_tick();
+            }
+        }
+
+        self.sample_clock += SAMPLE_RATE * cycles;
+        if self.sample_clock >= CPU_FREQUENCY {
+            self.sample_clock -= CPU_FREQUENCY;
+            self.emit_sample();
+        }
+    }
+
+    /// Runs `cycles` T-cycles in as few steps as possible, each one ending at the latest
+    /// on a sample or a frame sequencer step.
+    pub fn advance(&mut self, mut cycles: u32) {
+        while cycles > 0 {
+            let to_sample = (CPU_FREQUENCY - self.sample_clock).div_ceil(SAMPLE_RATE);
+            let to_step = FRAME_SEQUENCER_PERIOD - self.frame_sequencer_cycles;
+            let step = cycles.min(to_sample).min(to_step);
+            self.apu_tick(step);
+            cycles -= step;
+        }
+    }
+
+    /// Converts a channel's digital output to the DAC's analog level in -1.0..=1.0.
+    fn dac(output: u8, dac_enabled: bool) -> f32 {
+        if !dac_enabled {
+            return 0.0;
+        }
+
+        1.0 - (output as f32 / 7.5)
+    }
+
+    fn channel_levels(&self) -> [f32; 4] {
+        [
+            Self::dac(self.ch1.output(), self.ch1.dac_enabled()),
+            Self::dac(self.ch2.output(), self.ch2.dac_enabled()),
+            Self::dac(self.ch3.output(), self.ch3.dac_enabled),
+            Self::dac(self.ch4.output(), self.ch4.dac_enabled()),
+        ]
+    }
+
+    fn emit_sample(&mut self) {
+        if self.capture.is_none() && self.samples.is_none() {
+            return;
+        }
+
+        let levels = match self.enabled {
+            true => self.channel_levels(),
+            false => [0.0; 4],
+        };
+
+        let panning = self.registers[0x15];
+        let master = self.registers[0x14];
+        let mut left = 0.0;
+        let mut right = 0.0;
+        for (i, level) in levels.iter().enumerate() {
+            if (panning & (0x10 << i)) != 0 {
+                left += level;
+            }
+            if (panning & (0x01 << i)) != 0 {
+                right += level;
+            }
+        }
+
+        left *= (((master >> 4) & 0x07) + 1) as f32 / 8.0;
+        right *= ((master & 0x07) + 1) as f32 / 8.0;
+
+        let to_pcm = |value: f32| (value * i16::MAX as f32) as i16;
+        let (left, right) = (to_pcm(left / 4.0), to_pcm(right / 4.0));
+        if let Some(samples) = self.samples.as_mut() {
+            samples.extend_from_slice(&[left, right]);
+        }
+
+        let Some(capture) = self.capture.as_mut() else {
+            return;
+        };
+        if let Err(e) = capture.push(left, right, &levels.map(to_pcm)) {
+            // A failing sink should not bring the emulation down, end the capture instead.
+            Logger::log(format!("Audio capture failed: {:?} \n", e));
+            if let Some(Err(e)) = self.capture.take().map(AudioCapture::finish) {
+                Logger::log(format!("Failed to finish the audio capture: {:?} \n", e));
+            }
+        }
+    }
+
+    pub fn start_capture(&mut self, path: &str, per_channel: bool) -> Result<(), ApuError> {
+        if self.capture.is_some() {
+            return Err(ApuError::AlreadyCapturing);
+        }
+
+        self.capture = Some(AudioCapture::start(path, SAMPLE_RATE, per_channel)?);
+        Ok(())
+    }
+
+    pub fn stop_capture(&mut self) -> Result<(), ApuError> {
+        match self.capture.take() {
+            Some(capture) => Ok(capture.finish()?),
+            None => Err(ApuError::NotCapturing),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn is_capturing(&self) -> bool {
+        self.capture.is_some()
+    }
+
+    /// Starts or stops keeping samples for `take_samples`.
+    pub fn set_sample_buffer(&mut self, enabled: bool) {
+        self.samples = enabled.then(Vec::new);
+    }
+
+    /// Samples emitted since the last call, interleaved left and right at `SAMPLE_RATE`.
+    pub fn take_samples(&mut self) -> Vec<i16> {
+        self.samples.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+}
+
+impl SaveState for APU {
+    const TAG: [u8; 4] = *b"APU ";
+    const VERSION: u16 = 1;
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.bool(self.enabled);
+        writer.bytes(&self.registers);
+        self.ch1.save_state(writer);
+        self.ch2.save_state(writer);
+        self.ch3.save_state(writer);
+        self.ch4.save_state(writer);
+        writer.u8(self.frame_sequencer_step);
+        writer.u32(self.frame_sequencer_cycles);
+        writer.u32(self.sample_clock);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.enabled = reader.bool()?;
+        reader.bytes(&mut self.registers)?;
+        self.ch1.load_state(reader)?;
+        self.ch2.load_state(reader)?;
+        self.ch3.load_state(reader)?;
+        self.ch4.load_state(reader)?;
+        self.frame_sequencer_step = reader.u8()? & 0x07;
+        self.frame_sequencer_cycles = reader.u32()?.min(FRAME_SEQUENCER_PERIOD - 1);
+        self.sample_clock = reader.u32()?.min(CPU_FREQUENCY - 1);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register_read_back_and_power() {
+        let mut apu = APU::new();
+        assert_eq!(apu.read(0x26), 0xF0);
+        assert_eq!(apu.read(0x10), 0x80);
+        assert_eq!(apu.read(0x15), 0xFF);
+        assert_eq!(apu.read(0x2F), 0xFF);
+
+        // Only the duty of NRx1 reads back, the frequency registers are write-only.
+        apu.write(0x11, 0x40 | 0x12);
+        assert_eq!(apu.read(0x11), 0x7F);
+        apu.write(0x13, 0x42);
+        assert_eq!(apu.read(0x13), 0xFF);
+        apu.write(0x24, 0x77);
+        assert_eq!(apu.read(0x24), 0x77);
+
+        // Triggering a channel with its DAC on sets its NR52 bit.
+        apu.write(0x12, 0xF0);
+        apu.write(0x14, 0x80 | 0x40);
+        assert_eq!(apu.read(0x14), 0xFF);
+        assert_eq!(apu.read(0x26), 0xF1);
+
+        // Powering off clears the registers and ignores writes, except wave RAM.
+        apu.write(0x26, 0x00);
+        assert_eq!(apu.read(0x26), 0x70);
+        assert_eq!(apu.read(0x24), 0x00);
+        apu.write(0x24, 0x77);
+        assert_eq!(apu.read(0x24), 0x00);
+        apu.write(0x30, 0x12);
+        assert_eq!(apu.read(0x30), 0x12);
+
+        apu.write(0x26, 0x80);
+        assert_eq!(apu.read(0x26), 0xF0);
+        apu.write(0x24, 0x77);
+        assert_eq!(apu.read(0x24), 0x77);
+    }
+}
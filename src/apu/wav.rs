@@ -0,0 +1,172 @@
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+
+const WAV_HEADER_SIZE: u32 = 44;
+
+/// Streams 16-bit PCM samples into a RIFF/WAVE file. The chunk sizes are
+/// patched in when the writer is finished.
+pub struct WavWriter {
+    writer: BufWriter<File>,
+    channels: u16,
+    data_size: u32,
+}
+
+impl WavWriter {
+    pub fn create(path: &str, channels: u16, sample_rate: u32) -> std::io::Result<WavWriter> {
+        let mut wav = WavWriter {
+            writer: BufWriter::new(File::create(path)?),
+            channels,
+            data_size: 0,
+        };
+        wav.write_header(sample_rate)?;
+        Ok(wav)
+    }
+
+    fn write_header(&mut self, sample_rate: u32) -> std::io::Result<()> {
+        let block_align = self.channels * 2;
+        let byte_rate = sample_rate * block_align as u32;
+
+        self.writer.write_all(b"RIFF")?;
+        self.writer.write_all(&0u32.to_le_bytes())?;
+        self.writer.write_all(b"WAVE")?;
+        self.writer.write_all(b"fmt ")?;
+        self.writer.write_all(&16u32.to_le_bytes())?;
+        self.writer.write_all(&1u16.to_le_bytes())?;
+        self.writer.write_all(&self.channels.to_le_bytes())?;
+        self.writer.write_all(&sample_rate.to_le_bytes())?;
+        self.writer.write_all(&byte_rate.to_le_bytes())?;
+        self.writer.write_all(&block_align.to_le_bytes())?;
+        self.writer.write_all(&16u16.to_le_bytes())?;
+        self.writer.write_all(b"data")?;
+        self.writer.write_all(&0u32.to_le_bytes())?;
+        Ok(())
+    }
+
+    pub fn write_sample(&mut self, sample: i16) -> std::io::Result<()> {
+        self.writer.write_all(&sample.to_le_bytes())?;
+        self.data_size += 2;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> std::io::Result<()> {
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_all(&(WAV_HEADER_SIZE - 8 + self.data_size).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(40))?;
+        self.writer.write_all(&self.data_size.to_le_bytes())?;
+        self.writer.flush()
+    }
+}
+
+/// Captures the mixed stereo output and, optionally, each channel to its
+/// own mono file named `<base>_ch<n>.wav`.
+pub struct AudioCapture {
+    mixed: WavWriter,
+    channels: Option<Vec<WavWriter>>,
+}
+
+impl AudioCapture {
+    pub fn start(path: &str, sample_rate: u32, per_channel: bool) -> std::io::Result<AudioCapture> {
+        let mixed = WavWriter::create(path, 2, sample_rate)?;
+        let channels = match per_channel {
+            true => {
+                let base = path.strip_suffix(".wav").unwrap_or(path);
+                let mut writers = Vec::new();
+                for n in 1..=4 {
+                    writers.push(WavWriter::create(&format!("{}_ch{}.wav", base, n), 1, sample_rate)?);
+                }
+                Some(writers)
+            }
+            false => None,
+        };
+
+        Ok(AudioCapture { mixed, channels })
+    }
+
+    pub fn push(&mut self, left: i16, right: i16, channels: &[i16; 4]) -> std::io::Result<()> {
+        self.mixed.write_sample(left)?;
+        self.mixed.write_sample(right)?;
+        if let Some(writers) = self.channels.as_mut() {
+            for (writer, sample) in writers.iter_mut().zip(channels.iter()) {
+                writer.write_sample(*sample)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> std::io::Result<()> {
+        self.mixed.finish()?;
+        if let Some(writers) = self.channels {
+            for writer in writers {
+                writer.finish()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir().join(name).to_string_lossy().to_string()
+    }
+
+    fn le_u16(data: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes([data[offset], data[offset + 1]])
+    }
+
+    fn le_u32(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_finish_patches_chunk_sizes() {
+        let path = temp_path("gbc_rs_wav_test.wav");
+        let mut wav = WavWriter::create(&path, 2, 44_100).unwrap();
+        for sample in [1, -1, 0x1234, -0x1234, 7, 8] {
+            wav.write_sample(sample).unwrap();
+        }
+        wav.finish().unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(data.len(), WAV_HEADER_SIZE as usize + 12);
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(le_u32(&data, 4), WAV_HEADER_SIZE - 8 + 12);
+        assert_eq!(&data[8..16], b"WAVEfmt ");
+        assert_eq!(le_u16(&data, 22), 2);
+        assert_eq!(le_u32(&data, 24), 44_100);
+        assert_eq!(le_u32(&data, 28), 44_100 * 4);
+        assert_eq!(le_u16(&data, 32), 4);
+        assert_eq!(&data[36..40], b"data");
+        assert_eq!(le_u32(&data, 40), 12);
+        assert_eq!(le_u16(&data, 48), 0x1234);
+    }
+
+    #[test]
+    fn test_per_channel_files() {
+        let path = temp_path("gbc_rs_capture_test.wav");
+        let mut capture = AudioCapture::start(&path, 22_050, true).unwrap();
+        capture.push(100, -100, &[1, 2, 3, 4]).unwrap();
+        capture.push(200, -200, &[5, 6, 7, 8]).unwrap();
+        capture.finish().unwrap();
+
+        let mixed = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(le_u16(&mixed, 22), 2);
+        assert_eq!(le_u32(&mixed, 40), 8);
+
+        for n in 1..=4 {
+            let channel_path = temp_path(&format!("gbc_rs_capture_test_ch{}.wav", n));
+            let data = std::fs::read(&channel_path).unwrap();
+            std::fs::remove_file(&channel_path).unwrap();
+            assert_eq!(le_u16(&data, 22), 1);
+            assert_eq!(le_u32(&data, 28), 22_050 * 2);
+            assert_eq!(le_u16(&data, 32), 2);
+            assert_eq!(le_u32(&data, 40), 4);
+            assert_eq!(le_u16(&data, 44), n as u16);
+            assert_eq!(le_u16(&data, 46), n as u16 + 4);
+        }
+    }
+}
@@ -0,0 +1,8 @@
+#[cfg(feature = "sdl")]
+pub(crate) mod sdl;
+
+/// Plays what the core produced, a frame's worth of samples at a time.
+pub trait AudioSink {
+    /// Interleaved stereo at `emulator::SAMPLE_RATE`, left first.
+    fn queue(&mut self, samples: &[i16]);
+}
@@ -0,0 +1,38 @@
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+use gbc_rs::emulator::SAMPLE_RATE;
+use crate::audio::AudioSink;
+
+/// Audio queued beyond this is dropped rather than played late, about 100 ms.
+const MAX_QUEUED_BYTES: u32 = SAMPLE_RATE / 10 * 2 * 2;
+
+pub struct SdlAudio {
+    queue: AudioQueue<i16>,
+    _sdl_context: sdl2::Sdl,
+}
+
+impl SdlAudio {
+    pub fn new() -> Result<SdlAudio, String> {
+        let sdl_context = sdl2::init()?;
+        let audio = sdl_context.audio()?;
+        let spec = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE as i32),
+            channels: Some(2),
+            samples: Some(1024),
+        };
+        let queue = audio.open_queue::<i16, _>(None, &spec)?;
+        queue.resume();
+        Ok(SdlAudio { queue, _sdl_context: sdl_context })
+    }
+}
+
+impl AudioSink for SdlAudio {
+    fn queue(&mut self, samples: &[i16]) {
+        if self.queue.size() > MAX_QUEUED_BYTES {
+            return;
+        }
+
+        if let Err(e) = self.queue.queue_audio(samples) {
+            println!("Failed to queue audio: {}", e);
+        }
+    }
+}
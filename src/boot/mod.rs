@@ -0,0 +1,212 @@
+/*
+    Boot ROM mapping:
+    DMG: 0x0000 - 0x00FF, 256 bytes
+    CGB: 0x0000 - 0x00FF and 0x0200 - 0x08FF, 2304 bytes. 0x0100 - 0x01FF stays on the
+         cartridge so the header can be read.
+
+    Writing a non-zero value to 0xFF50 unmaps it until the next power cycle.
+*/
+
+use crate::cartridge::RomHeader;
+use crate::cpu::CpuRegisters;
+use crate::emulator::Model;
+
+pub const DMG_BOOT_ROM_SIZE: usize = 0x100;
+pub const CGB_BOOT_ROM_SIZE: usize = 0x900;
+
+/// Licensee code Nintendo titles use, in the old and the new header field.
+const NINTENDO_OLD_LICENSEE: u8 = 0x01;
+const NINTENDO_NEW_LICENSEE: u16 = u16::from_be_bytes(*b"01");
+/// Old licensee value that points at the new licensee field.
+const USE_NEW_LICENSEE: u8 = 0x33;
+
+#[derive(Debug)]
+pub enum BootRomError {
+    InvalidSize(usize),
+    /// A DMG boot ROM given for a CGB model or the other way around.
+    WrongModel,
+}
+
+pub struct BootRom {
+    data: Vec<u8>,
+}
+
+impl BootRom {
+    pub fn new(data: Vec<u8>) -> Result<BootRom, BootRomError> {
+        match data.len() {
+            DMG_BOOT_ROM_SIZE | CGB_BOOT_ROM_SIZE => Ok(BootRom { data }),
+            size => Err(BootRomError::InvalidSize(size)),
+        }
+    }
+
+    pub fn is_cgb(&self) -> bool {
+        self.data.len() == CGB_BOOT_ROM_SIZE
+    }
+
+    /// DMG, MGB and SGB boot ROMs are 256 bytes, the CGB and AGB ones 2304.
+    pub fn check_model(&self, model: Model) -> Result<(), BootRomError> {
+        match self.is_cgb() == model.is_cgb() {
+            true => Ok(()),
+            false => Err(BootRomError::WrongModel),
+        }
+    }
+
+    /// Whether the boot ROM shadows the cartridge at `address`.
+    pub fn maps(&self, address: u16) -> bool {
+        match address {
+            0x0000..=0x00FF => true,
+            0x0200..=0x08FF => self.is_cgb(),
+            _ => false,
+        }
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        self.data[address as usize]
+    }
+}
+
+/// Title checksum the CGB boot ROM picks a compatibility palette with when running a
+/// DMG cartridge. Only Nintendo titles are hashed, everything else gets the default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompatPalette {
+    /// Sum of the 16 title bytes.
+    pub checksum: u8,
+    /// Tells apart titles whose checksums collide.
+    pub fourth_letter: u8,
+}
+
+impl CompatPalette {
+    pub fn from_header(header: &RomHeader) -> Option<CompatPalette> {
+        let nintendo = match header.lic_code {
+            NINTENDO_OLD_LICENSEE => true,
+            USE_NEW_LICENSEE => header.new_lic_code == NINTENDO_NEW_LICENSEE,
+            _ => false,
+        };
+        if !nintendo {
+            return None;
+        }
+
+        Some(CompatPalette {
+            checksum: header.title.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)),
+            fourth_letter: header.title[3],
+        })
+    }
+}
+
+/// Whether the cartridge asks for CGB mode, 0x80 or 0xC0 in the last title byte.
+pub fn cgb_cartridge(header: &RomHeader) -> bool {
+    header.title[15] & 0x80 != 0
+}
+
+/// CPU registers right after power-on, the boot ROM starts from here.
+pub fn power_on_registers() -> CpuRegisters {
+    CpuRegisters {
+        a: 0,
+        f: 0,
+        b: 0,
+        c: 0,
+        d: 0,
+        e: 0,
+        h: 0,
+        l: 0,
+        pc: 0,
+        sp: 0,
+    }
+}
+
+/// CPU registers as the boot ROM of `model` leaves them when it jumps to 0x100.
+pub fn post_boot_registers(model: Model, header: &RomHeader) -> CpuRegisters {
+    // H and C are left over from the header checksum loop on the DMG boot ROM.
+    let dmg_flags = if header.checksum == 0 { 0x80 } else { 0xB0 };
+    let (a, f, b, c, d, e, h, l) = match model {
+        Model::Dmg0 => (0x01, 0x00, 0xFF, 0x13, 0x00, 0xC1, 0x84, 0x03),
+        Model::Dmg => (0x01, dmg_flags, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D),
+        Model::Mgb => (0xFF, dmg_flags, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D),
+        Model::Sgb => (0x01, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60),
+        Model::Sgb2 => (0xFF, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60),
+        Model::Cgb0 | Model::Cgb | Model::Agb => {
+            let (b, d, e, h, l) = match cgb_cartridge(header) {
+                true => (0x00, 0xFF, 0x56, 0x00, 0x0D),
+                false => {
+                    // B keeps the title checksum, and two titles take a longer path
+                    // through the palette lookup.
+                    let checksum = CompatPalette::from_header(header).map_or(0, |palette| palette.checksum);
+                    let (h, l) = match checksum {
+                        0x43 | 0x58 => (0x99, 0x1A),
+                        _ => (0x00, 0x7C),
+                    };
+                    (checksum, 0x00, 0x08, h, l)
+                }
+            };
+            match model {
+                // The AGB boot ROM ends with an extra INC B.
+                Model::Agb => {
+                    let b = b.wrapping_add(1);
+                    let f = if b == 0 { 0x80 } else { 0x00 } | if b & 0x0F == 0 { 0x20 } else { 0x00 };
+                    (0x11, f, b, 0x00, d, e, h, l)
+                }
+                _ => (0x11, 0x80, b, 0x00, d, e, h, l),
+            }
+        }
+    };
+
+    CpuRegisters {
+        a,
+        f,
+        b,
+        c,
+        d,
+        e,
+        h,
+        l,
+        pc: 0x100,
+        sp: 0xFFFE,
+    }
+}
+
+/// IO writes for the registers the boot ROM of `model` leaves somewhere else than where
+/// the components start, the sound registers touched by the start-up chime.
+pub fn post_boot_io(model: Model) -> [(u16, u8); 4] {
+    // The SGB boot ROM plays no chime, channel 1 is left with its DAC off.
+    let nr12 = match model {
+        Model::Sgb | Model::Sgb2 => 0x00,
+        Model::Dmg0 | Model::Dmg | Model::Mgb | Model::Cgb0 | Model::Cgb | Model::Agb => 0xF3,
+    };
+    [(0xFF11, 0x80), (0xFF12, nr12), (0xFF24, 0x77), (0xFF25, 0xF3)]
+}
+
+/// IO writes that undo the post-boot values the components start with, for a run that
+/// goes through the boot ROM.
+pub const POWER_ON_IO: [(u16, u8); 3] = [
+    (0xFF26, 0x00),
+    (0xFF40, 0x00),
+    (0xFF47, 0x00),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{Emulator, EmulatorOptions};
+
+    #[test]
+    fn test_boot_rom_unmaps_on_ff50() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0000] = 0xAA;
+        rom[0x0100..0x0102].copy_from_slice(&[0x18, 0xFE]); // JR -2
+        let mut boot_rom = vec![0; DMG_BOOT_ROM_SIZE];
+        boot_rom[0x00..0x03].copy_from_slice(&[0xC3, 0xFC, 0x00]); // JP 0x00FC
+        boot_rom[0xFC..0x100].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x50]); // LD A, 1; LDH (0x50), A
+
+        let options = EmulatorOptions { boot_rom: Some(boot_rom), ..Default::default() };
+        let mut emulator = Emulator::new(Model::Dmg, rom, options).unwrap();
+        assert_eq!(emulator.cpu.registers.pc, 0x0000);
+        assert_eq!(emulator.peek(0x0000).unwrap(), 0xC3);
+
+        for _ in 0..3 {
+            emulator.step_instruction().unwrap();
+        }
+        assert_eq!(emulator.cpu.registers.pc, 0x0100);
+        assert_eq!(emulator.peek(0x0000).unwrap(), 0xAA);
+        assert!(BootRom::new(vec![0; 0x200]).is_err());
+    }
+}
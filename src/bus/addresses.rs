@@ -76,11 +76,4 @@ impl AddrSpace {
 
         Ok(region)
     }
-
-
-    pub fn get_region_offset(address: u16) -> Result<u16, BusError> {
-        let region = AddrSpace::from_address(&address)?;
-        let (start, _) = region.get_region();
-        Ok(address - start)
-    }
 }
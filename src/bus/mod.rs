@@ -1,15 +1,18 @@
 mod addresses;
-mod writers;
 
-use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use crate::boot::BootRom;
 use crate::bus::addresses::AddrSpace;
-use crate::cartridge::{Cartridge, CartridgeError};
+use crate::cartridge::{Cartridge, CartridgeError, RomHeader};
 use crate::cpu::interrupts::IFlagsRegister;
 use crate::dma::DMA;
-use crate::emu::GlobalContext;
+use crate::emulator::Model;
 use crate::io::{IO, IoError};
-use crate::ppu::PPU;
+use crate::ppu::{PPU, TICKS_PER_FRAME};
 use crate::ram::{Ram, RamError};
+use crate::savestate::{SaveStateError, StateFile};
+use crate::savestate::bess::BessState;
+use crate::sgb::SGB;
+use crate::tick::TickManager;
 
 #[derive(Debug)]
 pub enum BusError {
@@ -17,7 +20,6 @@ pub enum BusError {
     CartridgeError(CartridgeError),
     InvalidAddress,
     RamError(RamError),
-    MutexError,
     IoError(IoError),
 }
 
@@ -33,232 +35,329 @@ impl From<RamError> for BusError {
     }
 }
 
-impl From<PoisonError<MutexGuard<'_, BUS>>> for BusError {
-    fn from(_: PoisonError<MutexGuard<'_, BUS>>) -> BusError {
-        BusError::MutexError
-    }
-}
-
 impl From<IoError> for BusError {
     fn from(e: IoError) -> BusError {
         BusError::IoError(e)
     }
 }
 
-#[derive(Clone)]
-pub struct BusMutex {
-    pub bus: Arc<Mutex<BUS>>,
+/// The whole machine behind the CPU. Every component is owned here, so an access is a
+/// plain match on the address.
+pub struct BUS {
+    /// Components with per-model quirks branch on this.
+    pub model: Model,
+    cartridge: Option<Cartridge>,
+    /// Shadows the start of the cartridge until 0xFF50 is written.
+    pub boot_rom: Option<BootRom>,
+    pub ram: Ram,
+    pub ppu: PPU,
+    pub dma: DMA,
+    pub io: IO,
+    /// Only on SGB models.
+    pub sgb: Option<SGB>,
+    pub ie_register: IFlagsRegister,
+    pub tm: TickManager,
 }
 
-impl BusMutex {
-    pub fn new(global_context: GlobalContext) -> BusMutex {
-        BusMutex {
-            bus: Arc::new(Mutex::new(BUS::new(global_context))),
+impl BUS {
+    pub fn new() -> BUS {
+        BUS {
+            model: Model::Dmg,
+            cartridge: None,
+            boot_rom: None,
+            ram: Ram::new(),
+            ppu: PPU::new(),
+            dma: DMA::new(),
+            io: IO::new(),
+            sgb: None,
+            ie_register: IFlagsRegister::new(),
+            tm: TickManager::new(),
         }
     }
 
-    pub fn load_game(&self, rom: Vec<u8>) -> Result<(), BusError> {
-        let mut bus = self.bus.lock()?;
-        bus.load_game(rom)
+    pub fn set_model(&mut self, model: Model) {
+        self.model = model;
+        self.io.apu.model = model;
+        self.io.serial.model = model;
+        self.sgb = model.is_sgb().then(SGB::new);
     }
 
-    pub fn read(&self, address: u16) -> Result<u8, BusError> {
-        let mut bus = self.bus.lock()?;
-        bus.read(address)
+    pub fn load_game(&mut self, rom: Vec<u8>) -> Result<(), BusError> {
+        let cartridge = Cartridge::new(rom)?;
+        self.cartridge = Some(cartridge);
+        Ok(())
     }
 
-    #[allow(dead_code)]
-    pub fn read_16(&self, address: u16) -> Result<u16, BusError> {
-        let mut bus = self.bus.lock()?;
-        bus.read_16(address)
+    pub fn rom_header(&self) -> Option<&RomHeader> {
+        self.cartridge.as_ref().map(|cartridge| &cartridge.rom_header)
     }
 
-    pub fn write(&self, address: u16, data: u8) -> Result<(), BusError> {
-        let mut bus = self.bus.lock()?;
-        bus.write(address, data)
+    pub fn reset_ram(&mut self) {
+        self.ram = Ram::new();
     }
 
-    pub fn write_16(&self, address: u16, data: u16) -> Result<(), BusError> {
-        let mut bus = self.bus.lock()?;
-        bus.write_16(address, data)
+    pub fn set_camera_source(&mut self, path: &str) -> Result<(), BusError> {
+        let cartridge = self.cartridge.as_mut().ok_or(BusError::NoCartridgeLoaded)?;
+        Ok(cartridge.set_camera_source(path)?)
     }
-}
-
-pub struct BUS {
-    cartridge: Option<Cartridge>,
-    ram: Ram,
-    ppu: Arc<Mutex<PPU>>,
-    dma: Arc<Mutex<DMA>>,
-    io: Arc<Mutex<IO>>,
-    interrupt_register: Arc<Mutex<IFlagsRegister>>,
-}
 
-impl BUS {
-    pub fn new(global_context: GlobalContext) -> BUS {
-        BUS {
-            cartridge: None,
-            ram: Ram::new(),
-            io: global_context.io.unwrap(),
-            ppu: global_context.ppu.unwrap(),
-            dma: global_context.dma.unwrap(),
-            interrupt_register: global_context.ie_register,
-        }
+    pub fn has_camera_source(&self) -> bool {
+        self.cartridge.as_ref().is_some_and(|cartridge| cartridge.has_camera_source())
     }
 
-    pub fn load_game(&mut self, rom: Vec<u8>) -> Result<(), BusError> {
-        let cartridge = Cartridge::new(rom)?;
-        self.cartridge = Some(cartridge);
+    pub fn save_state(&self, file: &mut StateFile) -> Result<(), SaveStateError> {
+        let cartridge = self.cartridge.as_ref().ok_or(SaveStateError::NoRomLoaded)?;
+        file.add(&self.ram);
+        file.add(cartridge);
         Ok(())
     }
 
-    pub fn read(&mut self, address: u16) -> Result<u8, BusError> {
-        let region = AddrSpace::from_address(&address)?;
-        let reader = writers::get_writer_by_region(region)?;
-        reader.read(self, address)
+    pub fn load_state(&mut self, file: &StateFile) -> Result<(), SaveStateError> {
+        let cartridge = self.cartridge.as_mut().ok_or(SaveStateError::NoRomLoaded)?;
+        file.load(cartridge)?;
+        file.load(&mut self.ram)
     }
 
-    pub fn write(&mut self, address: u16, data: u8) -> Result<(), BusError> {
-        let region = AddrSpace::from_address(&address)?;
-        let writer = writers::get_writer_by_region(region)?;
-        writer.write(self, address, data)
+    /// Fills in the cartridge and RAM parts of a BESS state.
+    pub fn save_bess(&self, state: &mut BessState) -> Result<(), SaveStateError> {
+        let cartridge = self.cartridge.as_ref().ok_or(SaveStateError::NoRomLoaded)?;
+        state.title = Some(cartridge.rom_header.title);
+        state.global_checksum = Some(cartridge.rom_header.global_checksum.to_be_bytes());
+        state.mbc_writes = cartridge.mbc.register_writes();
+        state.mbc_ram = cartridge.ram.clone();
+        state.wram = self.ram.wram.to_vec();
+        state.hram = self.ram.hram[..0x7F].to_vec();
+        Ok(())
     }
 
-    pub fn write_16(&mut self, address: u16, data: u16) -> Result<(), BusError> {
-        self.write(address, (data & 0xFF) as u8)?;
-        self.write(address + 1, (data >> 8) as u8)?;
+    /// Replays the mapper writes and copies the memory buffers, truncated to our sizes.
+    pub fn load_bess(&mut self, state: &BessState) -> Result<(), SaveStateError> {
+        let cartridge = self.cartridge.as_mut().ok_or(SaveStateError::NoRomLoaded)?;
+        if let Some(checksum) = state.global_checksum {
+            if u16::from_be_bytes(checksum) != cartridge.rom_header.global_checksum {
+                return Err(SaveStateError::RomMismatch);
+            }
+        }
+
+        for &(address, data) in &state.mbc_writes {
+            if address < 0x8000 {
+                cartridge.mbc.write(address, data);
+            }
+        }
+        let len = cartridge.ram.len().min(state.mbc_ram.len());
+        cartridge.ram[..len].copy_from_slice(&state.mbc_ram[..len]);
+        let len = self.ram.wram.len().min(state.wram.len());
+        self.ram.wram[..len].copy_from_slice(&state.wram[..len]);
+        let len = self.ram.hram.len().min(state.hram.len());
+        self.ram.hram[..len].copy_from_slice(&state.hram[..len]);
         Ok(())
     }
 
-    #[allow(dead_code)]
-    pub fn read_16(&mut self, address: u16) -> Result<u16, BusError> {
-        let low = self.read(address)? as u16;
-        let high = self.read(address + 1)? as u16;
-        Ok((high << 8) | low)
+    /// Counts `cycles` M-cycles, catching the components up once an event is due.
+    pub fn cycle(&mut self, cycles: u32) {
+        if self.tm.cycle(cycles) {
+            self.sync();
+        }
     }
 
-    fn read_from_cartridge(&mut self, address: u16) -> Result<u8, BusError> {
-        if self.cartridge.is_none() {
-            return Err(BusError::NoCartridgeLoaded);
+    /// Catches the components up to the current tick and schedules the next event.
+    pub fn sync(&mut self) {
+        let lag = self.tm.lag();
+        if lag > 0 {
+            let io = &mut self.io;
+            io.timer.advance(lag, &mut io.int_flags);
+            let div = io.timer.get_divider();
+            let frame = self.ppu.current_frame();
+            self.ppu.advance(lag, &mut io.int_flags);
+            if let Some(sgb) = self.sgb.as_mut().filter(|_| self.ppu.current_frame() != frame) {
+                sgb.frame_done(&self.ppu);
+            }
+            io.apu.advance(lag);
+            io.serial.advance(lag, div, &mut io.int_flags);
+            self.dma_advance(lag);
+            if let Some(cartridge) = self.cartridge.as_mut() {
+                cartridge.tick(lag);
+            }
         }
 
-        let cartridge = self.cartridge.as_mut().unwrap();
+        let ticks_to_event = self.ticks_to_event();
+        self.tm.synced(ticks_to_event);
+    }
 
-        cartridge
-            .read(address)
-            .map_err(|e| BusError::CartridgeError(e))
+    /// T-cycles until the first component event, at least once a frame.
+    fn ticks_to_event(&self) -> u32 {
+        let dma = match self.dma.dma_transferring() {
+            true => 4,
+            false => u32::MAX,
+        };
+        let cartridge = self.cartridge.as_ref().map_or(u32::MAX, |cartridge| cartridge.ticks_to_event());
+
+        [
+            self.io.timer.ticks_to_event(),
+            self.ppu.ticks_to_next_mode(),
+            self.io.serial.ticks_to_event(),
+            dma,
+            cartridge,
+            TICKS_PER_FRAME as u32,
+        ]
+        .into_iter()
+        .min()
+        .unwrap()
     }
 
-    fn write_to_cartridge(&mut self, address: u16, data: u8) -> Result<(), BusError> {
-        if self.cartridge.is_none() {
-            return Err(BusError::NoCartridgeLoaded);
+    /// One OAM DMA step per M-cycle.
+    fn dma_advance(&mut self, cycles: u32) {
+        for _ in 0..cycles / 4 {
+            if !self.dma.dma_transferring() {
+                return;
+            }
+            if let Some((source, offset)) = self.dma.dma_tick() {
+                let region = AddrSpace::from_address(&source);
+                let data = region.and_then(|region| self.read_region(region, source)).unwrap_or(0xFF);
+                self.ppu.oam_write(offset, data);
+            }
         }
-
-        let cartridge = self.cartridge.as_mut().unwrap();
-
-        cartridge
-            .write(address, data)
-            .map_err(|e| BusError::CartridgeError(e))
     }
 
-    fn read_from_ram(&self, address: u16) -> Result<u8, BusError> {
-        let region = AddrSpace::from_address(&address)?;
+    /// Writes that can start or move a component event: the IO registers, and the camera
+    /// registers in the cartridge RAM area.
+    fn reschedules(&self, region: &AddrSpace) -> bool {
         match region {
-            AddrSpace::RAM0 | AddrSpace::RAM1 =>  {
-                let (start, _) = AddrSpace::RAM0.get_region();
-                Ok(self.ram.read_wram(address - start)?)
-            },
-            AddrSpace::ZP => Ok(self.ram.read_hram(AddrSpace::get_region_offset(address)?)?),
-            _ => Err(BusError::InvalidAddress),
+            AddrSpace::IO => true,
+            AddrSpace::CRAM => self.cartridge.as_ref().is_some_and(|cartridge| cartridge.has_camera()),
+            _ => false,
         }
     }
 
-    fn write_to_ram(&mut self, address: u16, data: u8) -> Result<(), BusError> {
+    pub fn read(&mut self, address: u16) -> Result<u8, BusError> {
         let region = AddrSpace::from_address(&address)?;
-        match region {
-            AddrSpace::RAM0 | AddrSpace::RAM1 =>  {
-                let (start, _ ) = AddrSpace::RAM0.get_region();
-                self.ram.write_wram(address - start, data)?
-            },
-            AddrSpace::ZP => self.ram.write_hram(AddrSpace::get_region_offset(address)?, data)?,
-            _ => return Err(BusError::InvalidAddress),
+        if let AddrSpace::IO = region {
+            self.sync();
         }
+        self.read_region(region, address)
+    }
 
+    pub fn write(&mut self, address: u16, data: u8) -> Result<(), BusError> {
+        let region = AddrSpace::from_address(&address)?;
+        let reschedules = self.reschedules(&region);
+        if reschedules {
+            self.sync();
+        }
+        self.write_region(region, address, data)?;
+        if reschedules {
+            self.sync();
+        }
         Ok(())
     }
 
-    fn read_from_master_interruption_register(&self) -> u8 {
-        self.interrupt_register.lock().unwrap().int_flags
+    pub fn write_16(&mut self, address: u16, data: u16) -> Result<(), BusError> {
+        self.write(address, (data & 0xFF) as u8)?;
+        self.write(address.wrapping_add(1), (data >> 8) as u8)
     }
 
-    fn write_to_master_interruption_register(&mut self, data: u8) {
-        self.interrupt_register.lock().unwrap().int_flags = data;
+    #[allow(dead_code)]
+    pub fn read_16(&mut self, address: u16) -> Result<u16, BusError> {
+        let low = self.read(address)? as u16;
+        let high = self.read(address.wrapping_add(1))? as u16;
+        Ok((high << 8) | low)
     }
 
-    fn read_from_io(&self, address: u16) -> Result<u8, BusError> {
-        let region = AddrSpace::from_address(&address)?;
-        let address = AddrSpace::get_region_offset(address)?;
+    fn read_region(&mut self, region: AddrSpace, address: u16) -> Result<u8, BusError> {
+        let offset = address - region.get_region().0;
         match region {
-            AddrSpace::IO => Ok(self.io.lock().unwrap().read((address & 0xFF) as u8)?),
-            _ => Err(BusError::InvalidAddress),
+            AddrSpace::ROM0 | AddrSpace::ROM1 | AddrSpace::CRAM => self.read_from_cartridge(address),
+            AddrSpace::RAM0 | AddrSpace::RAM1 => Ok(self.ram.read_wram(address - 0xC000)?),
+            AddrSpace::ZP => Ok(self.ram.read_hram(offset)?),
+            AddrSpace::IO => self.read_from_io(offset as u8),
+            AddrSpace::INTERRUPT => Ok(self.ie_register.int_flags),
+            AddrSpace::VRAM => Ok(self.ppu.vram_read(offset)),
+            AddrSpace::OAM => Ok(self.read_from_oam(offset)),
+            AddrSpace::BG1 | AddrSpace::BG2 | AddrSpace::ECHO | AddrSpace::UNUSABLE => Ok(0),
         }
     }
 
-    fn write_to_io(&mut self, address: u16, data: u8) -> Result<(), BusError> {
-        let region = AddrSpace::from_address(&address)?;
-        let address = AddrSpace::get_region_offset(address)?;
+    fn write_region(&mut self, region: AddrSpace, address: u16, data: u8) -> Result<(), BusError> {
+        let offset = address - region.get_region().0;
         match region {
-            AddrSpace::IO => self.io.lock().unwrap().write((address &0xFF) as u8, data)?,
-            _ => return Err(BusError::InvalidAddress),
+            AddrSpace::ROM0 | AddrSpace::ROM1 | AddrSpace::CRAM => self.write_to_cartridge(address, data)?,
+            AddrSpace::RAM0 | AddrSpace::RAM1 => self.ram.write_wram(address - 0xC000, data)?,
+            AddrSpace::ZP => self.ram.write_hram(offset, data)?,
+            AddrSpace::IO => self.write_to_io(offset as u8, data)?,
+            AddrSpace::INTERRUPT => self.ie_register.int_flags = data,
+            AddrSpace::VRAM => self.ppu.vram_write(offset, data),
+            AddrSpace::OAM => self.write_to_oam(offset, data),
+            AddrSpace::BG1 | AddrSpace::BG2 | AddrSpace::ECHO | AddrSpace::UNUSABLE => {}
         }
 
         Ok(())
     }
 
-    fn write_to_oam(&mut self, address: u16, data: u8) -> Result<(), BusError> {
-        if self.dma.lock().unwrap().dma_transferring() {
-            return Ok(());
-        }
-
-        let region = AddrSpace::from_address(&address)?;
-        let address = AddrSpace::get_region_offset(address)?;
-        match region {
-            AddrSpace::OAM => self.ppu.lock().unwrap().oam_write(address, data),
-            _ => return Err(BusError::InvalidAddress),
+    fn read_from_cartridge(&mut self, address: u16) -> Result<u8, BusError> {
+        if let Some(boot_rom) = self.boot_rom.as_ref().filter(|boot_rom| boot_rom.maps(address)) {
+            return Ok(boot_rom.read(address));
         }
+        let cartridge = self.cartridge.as_mut().ok_or(BusError::NoCartridgeLoaded)?;
+        Ok(cartridge.read(address)?)
+    }
 
-        Ok(())
+    fn write_to_cartridge(&mut self, address: u16, data: u8) -> Result<(), BusError> {
+        let cartridge = self.cartridge.as_mut().ok_or(BusError::NoCartridgeLoaded)?;
+        Ok(cartridge.write(address, data)?)
     }
 
-    fn read_from_oam(&self, address: u16) -> Result<u8, BusError> {
-        if self.dma.lock().unwrap().dma_transferring() {
-            return Ok(0xFF);
+    /// The LCD registers live in the PPU, writing 0xFF46 also starts the OAM DMA and
+    /// writing 0xFF50 unmaps the boot ROM.
+    fn read_from_io(&mut self, address: u8) -> Result<u8, BusError> {
+        match address {
+            0x40..=0x4B => Ok(self.ppu.lcd.lcd_read(address as u16)),
+            0x50 => Ok(0xFF),
+            0x00 => {
+                let value = self.io.read(address)?;
+                Ok(self.sgb.as_ref().map_or(value, |sgb| sgb.read_p1(value)))
+            }
+            _ => Ok(self.io.read(address)?),
         }
+    }
 
-        let region = AddrSpace::from_address(&address)?;
-        let address = AddrSpace::get_region_offset(address)?;
-        match region {
-            AddrSpace::OAM => Ok(self.ppu.lock().unwrap().oam_read(address)),
-            _ => return Err(BusError::InvalidAddress),
+    fn write_to_io(&mut self, address: u8, data: u8) -> Result<(), BusError> {
+        match address {
+            0x40..=0x4B => {
+                self.ppu.lcd.lcd_write(address as u16, data);
+                match address {
+                    0x41 if !self.model.is_cgb() => self.ppu.stat_write_quirk(&mut self.io.int_flags),
+                    0x46 => self.dma.dma_start(data),
+                    _ => {}
+                }
+            }
+            0x50 if data != 0 => self.boot_rom = None,
+            0x00 => {
+                self.io.write(address, data)?;
+                if let Some(sgb) = self.sgb.as_mut() {
+                    sgb.write_p1(data);
+                }
+            }
+            _ => self.io.write(address, data)?,
         }
+
+        Ok(())
     }
 
-    fn write_to_vram(&mut self, address: u16, data: u8) -> Result<(), BusError> {
-        let region = AddrSpace::from_address(&address)?;
-        let address = AddrSpace::get_region_offset(address)?;
-        match region {
-            AddrSpace::VRAM => self.ppu.lock().unwrap().vram_write(address, data),
-            _ => return Err(BusError::InvalidAddress),
+    fn write_to_oam(&mut self, address: u16, data: u8) {
+        // The DMA runs in the scheduled catch-up, bring it to the current cycle first.
+        self.sync();
+        if self.dma.dma_transferring() {
+            return;
         }
 
-        Ok(())
+        self.ppu.oam_write(address, data);
     }
 
-    fn read_from_vram(&self, address: u16) -> Result<u8, BusError> {
-        let region = AddrSpace::from_address(&address)?;
-        let address = AddrSpace::get_region_offset(address)?;
-        match region {
-            AddrSpace::VRAM => Ok(self.ppu.lock().unwrap().vram_read(address)),
-            _ => return Err(BusError::InvalidAddress),
+    fn read_from_oam(&mut self, address: u16) -> u8 {
+        self.sync();
+        if self.dma.dma_transferring() {
+            return 0xFF;
         }
+
+        self.ppu.oam_read(address)
     }
 }
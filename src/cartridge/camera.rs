@@ -0,0 +1,309 @@
+/*
+    Pocket Camera registers, mapped at 0xA000 when RAM bank 0x10 is selected:
+    0xA000: Bit 0 starts a capture and reads 1 while it runs
+    0xA001: Bit 7 N, bits 6-5 VH edge mode (0 none, 1 horizontal, 2 vertical, 3 both), bits 4-0 gain
+    0xA002 - 0xA003: Exposure time (MSB first), in steps of 16 CPU clocks
+    0xA004: Bits 6-4 edge enhancement ratio, bit 3 invert output, bits 2-0 output bias
+    0xA005: Zero point and output reference voltage
+    0xA006 - 0xA035: 4x4 dither matrix, three thresholds per entry
+    Only 0xA000 can be read back, the other registers read 0x00.
+*/
+
+use std::path::Path;
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+use crate::util::png::{self, PngError};
+
+pub const SENSOR_WIDTH: usize = 128;
+pub const SENSOR_HEIGHT: usize = 112;
+
+const REGISTER_COUNT: usize = 0x36;
+const REG_CONTROL: usize = 0x00;
+const REG_EDGE_MODE: usize = 0x01;
+const REG_EXPOSURE_HIGH: usize = 0x02;
+const REG_EXPOSURE_LOW: usize = 0x03;
+const REG_EDGE_RATIO: usize = 0x04;
+const REG_DITHER_MATRIX: usize = 0x06;
+
+const CONTROL_CAPTURE: u8 = 1 << 0;
+const EDGE_EXCLUSIVE: u8 = 1 << 7;
+const OUTPUT_INVERT: u8 = 1 << 3;
+
+/// Exposure at which the sensor reports the source image's luminance unchanged.
+const EXPOSURE_REFERENCE: u32 = 0x0800;
+/// Edge enhancement ratios in quarters: 50%, 75%, 100%, 125%, 200%, 300%, 400%, 500%.
+const EDGE_RATIOS: [i32; 8] = [2, 3, 4, 5, 8, 12, 16, 20];
+
+/// The captured picture is stored as 16x14 tiles at the start of RAM bank 0.
+const IMAGE_RAM_OFFSET: usize = 0x100;
+
+/// What the sensor sees: greyscale frames at sensor resolution.
+pub struct CameraSource {
+    frames: Vec<Vec<u8>>,
+    next: usize,
+}
+
+impl CameraSource {
+    /// Loads a still PNG, or every PNG in a directory (in file name order) as a looping sequence.
+    pub fn load(path: &str) -> Result<CameraSource, PngError> {
+        let mut files = Vec::new();
+        if Path::new(path).is_dir() {
+            for entry in std::fs::read_dir(path)? {
+                let file = entry?.path();
+                if file.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("png")) {
+                    files.push(file);
+                }
+            }
+            files.sort();
+        } else {
+            files.push(path.into());
+        }
+
+        let mut frames = Vec::with_capacity(files.len());
+        for file in files {
+            let (width, height, rgb) = png::load_rgb(&file.to_string_lossy())?;
+            frames.push(Self::to_sensor(width as usize, height as usize, &rgb));
+        }
+        if frames.is_empty() {
+            return Err(PngError::Unsupported(format!("no PNG frames in {}", path)));
+        }
+
+        Ok(CameraSource { frames, next: 0 })
+    }
+
+    /// Scales an RGB image to the sensor size and converts it to luminance.
+    fn to_sensor(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(SENSOR_WIDTH * SENSOR_HEIGHT);
+        for y in 0..SENSOR_HEIGHT {
+            for x in 0..SENSOR_WIDTH {
+                let i = ((y * height / SENSOR_HEIGHT) * width + x * width / SENSOR_WIDTH) * 3;
+                let luma = (rgb[i] as u32 * 299 + rgb[i + 1] as u32 * 587 + rgb[i + 2] as u32 * 114) / 1000;
+                frame.push(luma as u8);
+            }
+        }
+        frame
+    }
+
+    fn next_frame(&mut self) -> &[u8] {
+        let frame = self.next;
+        self.next = (self.next + 1) % self.frames.len();
+        &self.frames[frame]
+    }
+}
+
+pub struct Camera {
+    registers: [u8; REGISTER_COUNT],
+    capture_cycles: u32,
+    source: Option<CameraSource>,
+}
+
+impl Camera {
+    pub fn new() -> Camera {
+        Camera {
+            registers: [0; REGISTER_COUNT],
+            capture_cycles: 0,
+            source: None,
+        }
+    }
+
+    pub fn set_source(&mut self, source: CameraSource) {
+        self.source = Some(source);
+    }
+
+    pub fn has_source(&self) -> bool {
+        self.source.is_some()
+    }
+
+    pub fn read_register(&self, address: u16) -> u8 {
+        match (address as usize - 0xA000) & 0x7F {
+            REG_CONTROL => self.registers[REG_CONTROL],
+            _ => 0x00,
+        }
+    }
+
+    pub fn write_register(&mut self, address: u16, data: u8) {
+        let register = (address as usize - 0xA000) & 0x7F;
+        if register >= REGISTER_COUNT {
+            return;
+        }
+
+        if register == REG_CONTROL {
+            // A running capture can be cancelled, but not restarted.
+            let start = (data & CONTROL_CAPTURE) != 0 && self.capture_cycles == 0;
+            if (data & CONTROL_CAPTURE) == 0 {
+                self.capture_cycles = 0;
+            } else if start {
+                self.capture_cycles = self.capture_duration();
+            }
+            self.registers[REG_CONTROL] = data & 0x07;
+            return;
+        }
+
+        self.registers[register] = data;
+    }
+
+    /// T-cycles a capture takes: 32446 CPU clocks, 512 more without the N bit, and the exposure time.
+    fn capture_duration(&self) -> u32 {
+        let n_delay = match (self.registers[REG_EDGE_MODE] & EDGE_EXCLUSIVE) != 0 {
+            true => 0,
+            false => 512,
+        };
+        (32446 + n_delay + 16 * self.exposure()) * 4
+    }
+
+    fn exposure(&self) -> u32 {
+        u16::from_be_bytes([self.registers[REG_EXPOSURE_HIGH], self.registers[REG_EXPOSURE_LOW]]) as u32
+    }
+
+    pub fn ticks_to_event(&self) -> u32 {
+        match self.capture_cycles {
+            0 => u32::MAX,
+            cycles => cycles,
+        }
+    }
+
+    /// Advances a running capture. When it finishes the picture is written to `ram`.
+    pub fn tick(&mut self, cycles: u32, ram: &mut [u8]) {
+        if self.capture_cycles == 0 {
+            return;
+        }
+
+        self.capture_cycles = self.capture_cycles.saturating_sub(cycles);
+        if self.capture_cycles == 0 {
+            self.capture(ram);
+            self.registers[REG_CONTROL] &= !CONTROL_CAPTURE;
+        }
+    }
+
+    fn capture(&mut self, ram: &mut [u8]) {
+        let sensor = self.expose();
+        let processed = self.enhance_edges(&sensor);
+
+        let invert = (self.registers[REG_EDGE_RATIO] & OUTPUT_INVERT) != 0;
+        for y in 0..SENSOR_HEIGHT {
+            for x in 0..SENSOR_WIDTH {
+                let mut value = processed[y * SENSOR_WIDTH + x];
+                if invert {
+                    value = 255 - value;
+                }
+                let color = self.dither(x, y, value);
+
+                let tile = (y / 8) * (SENSOR_WIDTH / 8) + x / 8;
+                let offset = IMAGE_RAM_OFFSET + tile * 16 + (y % 8) * 2;
+                if offset + 1 >= ram.len() {
+                    return;
+                }
+                let bit = 7 - (x % 8);
+                ram[offset] = (ram[offset] & !(1 << bit)) | ((color & 1) << bit);
+                ram[offset + 1] = (ram[offset + 1] & !(1 << bit)) | (((color >> 1) & 1) << bit);
+            }
+        }
+    }
+
+    /// Sensor output for the current frame, scaled by the exposure time. Without a source the
+    /// lens is covered and the sensor reads black.
+    fn expose(&mut self) -> Vec<i32> {
+        let exposure = self.exposure();
+        match self.source.as_mut() {
+            Some(source) => source
+                .next_frame()
+                .iter()
+                .map(|&luma| (luma as u32 * exposure / EXPOSURE_REFERENCE).min(255) as i32)
+                .collect(),
+            None => vec![0; SENSOR_WIDTH * SENSOR_HEIGHT],
+        }
+    }
+
+    /// Adds the selected multiple of the difference to the neighbours along the VH directions.
+    fn enhance_edges(&self, sensor: &[i32]) -> Vec<i32> {
+        let mode = (self.registers[REG_EDGE_MODE] >> 5) & 0x03;
+        if mode == 0 {
+            return sensor.to_vec();
+        }
+
+        let ratio = EDGE_RATIOS[((self.registers[REG_EDGE_RATIO] >> 4) & 0x07) as usize];
+        let at = |x: isize, y: isize| -> i32 {
+            let x = x.clamp(0, SENSOR_WIDTH as isize - 1) as usize;
+            let y = y.clamp(0, SENSOR_HEIGHT as isize - 1) as usize;
+            sensor[y * SENSOR_WIDTH + x]
+        };
+
+        let mut out = Vec::with_capacity(sensor.len());
+        for y in 0..SENSOR_HEIGHT as isize {
+            for x in 0..SENSOR_WIDTH as isize {
+                let center = at(x, y);
+                let mut edge = 0;
+                if (mode & 0x01) != 0 {
+                    edge += 2 * center - at(x - 1, y) - at(x + 1, y);
+                }
+                if (mode & 0x02) != 0 {
+                    edge += 2 * center - at(x, y - 1) - at(x, y + 1);
+                }
+                out.push((center + edge * ratio / 4).clamp(0, 255));
+            }
+        }
+        out
+    }
+
+    /// Picks the shade by comparing against the three thresholds of the matrix entry for this pixel.
+    fn dither(&self, x: usize, y: usize, value: i32) -> u8 {
+        let entry = REG_DITHER_MATRIX + ((y % 4) * 4 + x % 4) * 3;
+        let thresholds = &self.registers[entry..entry + 3];
+        match value {
+            v if v < thresholds[0] as i32 => 3,
+            v if v < thresholds[1] as i32 => 2,
+            v if v < thresholds[2] as i32 => 1,
+            _ => 0,
+        }
+    }
+}
+
+/// Stored inside the cartridge section. The sensor source is not part of the state.
+impl SaveState for Camera {
+    const TAG: [u8; 4] = *b"CAM ";
+    const VERSION: u16 = 1;
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.bytes(&self.registers);
+        writer.u32(self.capture_cycles);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        reader.bytes(&mut self.registers)?;
+        self.capture_cycles = reader.u32()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capture_dithers_into_ram() {
+        let mut camera = Camera::new();
+        camera.source = Some(CameraSource { frames: vec![vec![0x80; SENSOR_WIDTH * SENSOR_HEIGHT]], next: 0 });
+        camera.write_register(0xA003, 0x00);
+        camera.write_register(0xA002, (EXPOSURE_REFERENCE >> 8) as u8);
+        for entry in 0..16 {
+            camera.write_register(0xA006 + entry * 3, 0x40);
+            camera.write_register(0xA007 + entry * 3, 0x90);
+            camera.write_register(0xA008 + entry * 3, 0xC0);
+        }
+
+        camera.write_register(0xA000, 0x01);
+        assert_eq!(camera.read_register(0xA000), 0x01);
+        assert_eq!(camera.read_register(0xA002), 0x00);
+
+        let mut ram = vec![0; 0x2000];
+        let duration = camera.capture_duration();
+        camera.tick(duration - 4, &mut ram);
+        assert_eq!(camera.read_register(0xA000), 0x01);
+        camera.tick(4, &mut ram);
+        assert_eq!(camera.read_register(0xA000), 0x00);
+
+        // 0x80 falls between the first two thresholds: shade 2 everywhere.
+        assert_eq!(ram[IMAGE_RAM_OFFSET], 0x00);
+        assert_eq!(ram[IMAGE_RAM_OFFSET + 1], 0xFF);
+        assert_eq!(ram[IMAGE_RAM_OFFSET + 16 * 16 * 14 - 1], 0xFF);
+    }
+}
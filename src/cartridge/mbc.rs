@@ -0,0 +1,127 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MbcType {
+    RomOnly,
+    Mbc1,
+    Mbc3,
+    Mbc5,
+    PocketCamera,
+}
+
+impl MbcType {
+    pub fn from_cart_type(cart_type: u8) -> MbcType {
+        match cart_type {
+            0x01..=0x03 => MbcType::Mbc1,
+            0x0F..=0x13 => MbcType::Mbc3,
+            0x19..=0x1E => MbcType::Mbc5,
+            0xFC => MbcType::PocketCamera,
+            _ => MbcType::RomOnly,
+        }
+    }
+}
+
+pub fn ram_size_from_header(ram_size: u8) -> usize {
+    match ram_size {
+        0x01 => 0x800,
+        0x02 => 0x2000,
+        0x03 => 0x8000,
+        0x04 => 0x20000,
+        0x05 => 0x10000,
+        _ => 0,
+    }
+}
+
+/// Bank registers of the memory bank controller.
+pub struct Mbc {
+    pub mbc_type: MbcType,
+    pub rom_bank: u16,
+    pub ram_bank: u8,
+    pub ram_enabled: bool,
+    pub banking_mode: u8,
+}
+
+impl Mbc {
+    pub fn new(mbc_type: MbcType) -> Mbc {
+        Mbc {
+            mbc_type,
+            rom_bank: 1,
+            ram_bank: 0,
+            ram_enabled: mbc_type == MbcType::RomOnly,
+            banking_mode: 0,
+        }
+    }
+
+    pub fn write(&mut self, address: u16, data: u8) {
+        match (self.mbc_type, address) {
+            (MbcType::RomOnly, _) => {}
+            (_, 0x0000..=0x1FFF) => self.ram_enabled = (data & 0x0F) == 0x0A,
+            (MbcType::Mbc1, 0x2000..=0x3FFF) => {
+                let bank = (data & 0x1F).max(1) as u16;
+                self.rom_bank = (self.rom_bank & 0x60) | bank;
+            }
+            (MbcType::Mbc1, 0x4000..=0x5FFF) => {
+                self.ram_bank = data & 0x03;
+                self.rom_bank = (self.rom_bank & 0x1F) | (((data & 0x03) as u16) << 5);
+            }
+            (MbcType::Mbc1, 0x6000..=0x7FFF) => self.banking_mode = data & 0x01,
+            (MbcType::Mbc3, 0x2000..=0x3FFF) => self.rom_bank = (data & 0x7F).max(1) as u16,
+            (MbcType::Mbc3, 0x4000..=0x5FFF) => self.ram_bank = data & 0x03,
+            (MbcType::Mbc5, 0x2000..=0x2FFF) => self.rom_bank = (self.rom_bank & 0x100) | data as u16,
+            (MbcType::Mbc5, 0x3000..=0x3FFF) => {
+                self.rom_bank = (self.rom_bank & 0xFF) | (((data & 0x01) as u16) << 8)
+            }
+            (MbcType::Mbc5, 0x4000..=0x5FFF) => self.ram_bank = data & 0x0F,
+            (MbcType::PocketCamera, 0x2000..=0x3FFF) => self.rom_bank = (data & 0x3F) as u16,
+            // Bank 0x10 maps the camera registers instead of RAM.
+            (MbcType::PocketCamera, 0x4000..=0x5FFF) => self.ram_bank = data & 0x1F,
+            _ => {}
+        }
+    }
+
+    /// Writes that bring a fresh mapper of the same type to the current register values.
+    pub fn register_writes(&self) -> Vec<(u16, u8)> {
+        let ram_enable = match self.ram_enabled {
+            true => 0x0A,
+            false => 0x00,
+        };
+        match self.mbc_type {
+            MbcType::RomOnly => vec![],
+            MbcType::Mbc1 => vec![
+                (0x0000, ram_enable),
+                (0x2000, (self.rom_bank & 0x1F) as u8),
+                (0x4000, ((self.rom_bank >> 5) & 0x03) as u8),
+                (0x6000, self.banking_mode),
+            ],
+            MbcType::Mbc3 | MbcType::PocketCamera => vec![
+                (0x0000, ram_enable),
+                (0x2000, self.rom_bank as u8),
+                (0x4000, self.ram_bank),
+            ],
+            MbcType::Mbc5 => vec![
+                (0x0000, ram_enable),
+                (0x2000, self.rom_bank as u8),
+                (0x3000, (self.rom_bank >> 8) as u8),
+                (0x4000, self.ram_bank),
+            ],
+        }
+    }
+
+    pub fn rom_offset(&self, address: u16) -> usize {
+        match address {
+            0x0000..=0x3FFF => address as usize,
+            _ => self.rom_bank as usize * 0x4000 + (address as usize - 0x4000),
+        }
+    }
+
+    pub fn camera_registers_mapped(&self) -> bool {
+        self.mbc_type == MbcType::PocketCamera && (self.ram_bank & 0x10) != 0
+    }
+
+    pub fn ram_offset(&self, address: u16) -> usize {
+        let bank = match (self.mbc_type, self.banking_mode) {
+            (MbcType::Mbc1, 0) => 0,
+            (MbcType::PocketCamera, _) => (self.ram_bank & 0x0F) as usize,
+            _ => self.ram_bank as usize,
+        };
+        bank * 0x2000 + (address as usize - 0xA000)
+    }
+}
@@ -1,3 +1,11 @@
+use crate::cartridge::camera::{Camera, CameraSource};
+use crate::cartridge::mbc::{Mbc, MbcType};
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+use crate::util::png::PngError;
+
+pub mod camera;
+pub mod mbc;
+
 pub const ROM_HEADER_START: usize = 0x100;
 
 #[allow(dead_code)]
@@ -9,6 +17,8 @@ const ROM_CHECKSUM_END: usize = 0x14C;
 pub enum CartridgeError {
     InvalidRomData,
     ReadFromInvalidAddress,
+    NoCamera,
+    CameraSourceError(PngError),
 }
 
 pub struct RomReader<'a> {
@@ -95,19 +105,59 @@ impl RomHeader {
 pub struct Cartridge {
     pub rom_header: RomHeader,
     pub rom_data: Vec<u8>,
+    pub ram: Vec<u8>,
+    pub mbc: Mbc,
+    pub camera: Option<Camera>,
 }
 
 impl Cartridge {
     pub fn new(content: Vec<u8>) -> Result<Self, CartridgeError> {
         let rom_data = content;
         let rom_header = RomHeader::from_rom(&rom_data)?;
+        let mbc = Mbc::new(MbcType::from_cart_type(rom_header.cart_type));
+        let ram = vec![0; mbc::ram_size_from_header(rom_header.ram_size)];
+        let camera = match mbc.mbc_type {
+            MbcType::PocketCamera => Some(Camera::new()),
+            _ => None,
+        };
 
         Ok(Cartridge {
             rom_header,
             rom_data,
+            ram,
+            mbc,
+            camera,
         })
     }
 
+    /// Feeds the Pocket Camera sensor from a PNG file or a directory of PNG frames.
+    pub fn set_camera_source(&mut self, path: &str) -> Result<(), CartridgeError> {
+        let camera = self.camera.as_mut().ok_or(CartridgeError::NoCamera)?;
+        let source = CameraSource::load(path).map_err(CartridgeError::CameraSourceError)?;
+        camera.set_source(source);
+        Ok(())
+    }
+
+    /// Advances cartridge hardware that runs on the system clock.
+    pub fn tick(&mut self, cycles: u32) {
+        if let Some(camera) = self.camera.as_mut() {
+            camera.tick(cycles, &mut self.ram);
+        }
+    }
+
+    pub fn has_camera(&self) -> bool {
+        self.camera.is_some()
+    }
+
+    pub fn has_camera_source(&self) -> bool {
+        self.camera.as_ref().is_some_and(|camera| camera.has_source())
+    }
+
+    /// T-cycles until a running camera capture completes.
+    pub fn ticks_to_event(&self) -> u32 {
+        self.camera.as_ref().map_or(u32::MAX, |camera| camera.ticks_to_event())
+    }
+
     #[allow(dead_code)]
     pub fn validate_checksum(&self) -> bool {
         let mut sum: u16 = 0;
@@ -126,19 +176,87 @@ impl Cartridge {
     }
 
     pub fn read(&self, address: u16) -> Result<u8, CartridgeError> {
-        // Using only rom for now
-        if address as usize >= self.rom_data.len() {
-            return Err(CartridgeError::ReadFromInvalidAddress);
-        }
+        match address {
+            0x0000..=0x7FFF => {
+                if self.rom_data.is_empty() {
+                    return Err(CartridgeError::ReadFromInvalidAddress);
+                }
+
+                // Bank numbers past the end of the ROM wrap around like the unconnected address lines.
+                let offset = self.mbc.rom_offset(address) % self.rom_data.len();
+                Ok(self.rom_data[offset])
+            }
+            0xA000..=0xBFFF if self.mbc.camera_registers_mapped() => {
+                Ok(self.camera.as_ref().map_or(0x00, |camera| camera.read_register(address)))
+            }
+            0xA000..=0xBFFF => {
+                if !self.mbc.ram_enabled || self.ram.is_empty() {
+                    return Ok(0xFF);
+                }
 
-        Ok(self.rom_data[address as usize])
+                let offset = self.mbc.ram_offset(address) % self.ram.len();
+                Ok(self.ram[offset])
+            }
+            _ => Err(CartridgeError::ReadFromInvalidAddress),
+        }
     }
 
-    pub fn write(&mut self, _address: u16, _data: u8) -> Result<(), CartridgeError> {
+    pub fn write(&mut self, address: u16, data: u8) -> Result<(), CartridgeError> {
+        match address {
+            0x0000..=0x7FFF => self.mbc.write(address, data),
+            0xA000..=0xBFFF if self.mbc.camera_registers_mapped() => {
+                if let Some(camera) = self.camera.as_mut() {
+                    camera.write_register(address, data);
+                }
+            }
+            0xA000..=0xBFFF => {
+                if self.mbc.ram_enabled && !self.ram.is_empty() {
+                    let offset = self.mbc.ram_offset(address) % self.ram.len();
+                    self.ram[offset] = data;
+                }
+            }
+            _ => return Err(CartridgeError::ReadFromInvalidAddress),
+        }
+
         Ok(())
     }
 }
 
+impl SaveState for Cartridge {
+    const TAG: [u8; 4] = *b"CART";
+    const VERSION: u16 = 1;
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.u16(self.rom_header.global_checksum);
+        writer.u16(self.mbc.rom_bank);
+        writer.u8(self.mbc.ram_bank);
+        writer.bool(self.mbc.ram_enabled);
+        writer.u8(self.mbc.banking_mode);
+        writer.block(&self.ram);
+        writer.bool(self.camera.is_some());
+        if let Some(camera) = self.camera.as_ref() {
+            camera.save_state(writer);
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        if reader.u16()? != self.rom_header.global_checksum {
+            return Err(SaveStateError::RomMismatch);
+        }
+
+        self.mbc.rom_bank = reader.u16()?;
+        self.mbc.ram_bank = reader.u8()?;
+        self.mbc.ram_enabled = reader.bool()?;
+        self.mbc.banking_mode = reader.u8()?;
+        reader.block(&mut self.ram)?;
+        match (reader.bool()?, self.camera.as_mut()) {
+            (true, Some(camera)) => camera.load_state(reader),
+            (false, None) => Ok(()),
+            _ => Err(SaveStateError::RomMismatch),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
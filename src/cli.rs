@@ -0,0 +1,359 @@
+use gbc_rs::debug::log::LogLevel;
+use gbc_rs::lcd::palette::Palette;
+use gbc_rs::Model;
+use crate::emu::headless::StopCondition;
+
+pub const HELP: &str = "\
+Usage: gbc-rs [OPTIONS] <ROM>
+
+ROM is a .gb/.gbc cartridge image or a .gbs sound file.
+
+Options:
+  --model <MODEL>                dmg0, dmg, mgb, sgb, sgb2, cgb0, cgb or agb, picked from
+                                 the ROM header by default
+  --boot-rom <PATH>              Boot ROM to run before the cartridge
+  --palette <PALETTE>            DMG colours: grey, green, pocket, a CGB set such as
+                                 cgb-left-a, or 4 or 12 comma-separated RRGGBB values
+  --scale <N>                    Window size as a multiple of 160x144 (default 4)
+  --fullscreen                   Start in fullscreen
+  --paused                       Start paused, P toggles pause
+  --state <PATH>                 Load a save state on start
+  --debug                        Show the tile viewer and print the CPU trace on exit
+  --headless <FRAMES>            Run FRAMES frames without a window, then exit
+  --until <CONDITION>            End a headless run early with status 0
+  --fail-if <CONDITION>          End a headless run early with status 1
+  --screenshot <PATH>            Save the last frame as a PNG on exit, F12 saves one
+                                 next to the ROM
+  --tile-screenshot <PATH>       Save the tile viewer as a PNG on exit, F11 saves one
+                                 next to the ROM
+  --screenshot-scale <N>         Size multiplier for screenshots (default 1)
+  --audio <on|off>               Sound output (default on)
+  --log-level <LEVEL>            off, error, info, debug or trace (default info)
+  --config <PATH>                Read options from a file, one `name = value` per line
+  --camera <PATH>                PNG file or directory of frames for the Pocket Camera
+  --printer <DIR>                Attach a Game Boy Printer saving into DIR
+  --link-listen <ADDRESS>        Wait for a link cable peer
+  --link-connect <ADDRESS>       Connect a link cable to a peer
+  --rewind-speed <N>             Snapshots stepped back per frame while rewinding
+  --record-movie <PATH>          Record the inputs to a movie
+  --record-video <PATH>          Record a Y4M video, with the sound in a WAV file next
+                                 to it, F10 starts and stops one next to the ROM
+  --record-gif <PATH>            Capture an animated GIF, F7 starts one next to the ROM
+                                 and F8 saves it
+  --gif-frames <N|N..M>          Frames to put in the GIF: the first N, or N to M
+  --play-movie <PATH>            Play a movie back
+  --gbs-track <N>                Track of a .gbs file to start on (default the first song)
+  --gbs-export <PATH>            Render the .gbs track to a WAV file and exit
+  --gbs-seconds <N>              Length of the exported track (default 120)
+  -h, --help                     Print this help
+
+CONDITION is serial:TEXT or memory:ADDR=VALUE with hex numbers. Only one of --printer,
+--link-listen and --link-connect can be given.";
+
+const DEFAULT_SCALE: u32 = 4;
+const DEFAULT_GBS_SECONDS: u32 = 120;
+
+#[derive(Debug)]
+pub enum CliError {
+    Help,
+    UnknownArgument(String),
+    MissingValue(String),
+    InvalidValue(String, String),
+    MissingRom,
+    NeedsGbs(String),
+    ConflictingOptions(String, String),
+    ConfigError(String, std::io::Error),
+}
+
+impl std::fmt::Display for CliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CliError::Help => write!(f, "Help requested"),
+            CliError::UnknownArgument(arg) => write!(f, "Unknown argument: {}", arg),
+            CliError::MissingValue(arg) => write!(f, "Missing value for {}", arg),
+            CliError::InvalidValue(arg, value) => write!(f, "Invalid value for {}: {}", arg, value),
+            CliError::MissingRom => write!(f, "No ROM given"),
+            CliError::NeedsGbs(arg) => write!(f, "{} needs a .gbs file", arg),
+            CliError::ConflictingOptions(first, second) => write!(f, "{} cannot be used with {}", first, second),
+            CliError::ConfigError(path, e) => write!(f, "Failed to read config {}: {}", path, e),
+        }
+    }
+}
+
+pub struct Options {
+    pub rom: String,
+    /// `None` picks the model from the cartridge header.
+    pub model: Option<Model>,
+    pub boot_rom: Option<String>,
+    /// `None` keeps the default greys.
+    pub palette: Option<Palette>,
+    pub scale: u32,
+    pub fullscreen: bool,
+    pub paused: bool,
+    pub state: Option<String>,
+    pub debug: bool,
+    pub headless: Option<u32>,
+    pub until: Option<StopCondition>,
+    pub fail_if: Option<StopCondition>,
+    pub screenshot: Option<String>,
+    pub tile_screenshot: Option<String>,
+    pub screenshot_scale: u32,
+    pub audio: bool,
+    pub log_level: LogLevel,
+    pub camera: Option<String>,
+    pub printer: Option<String>,
+    pub link_listen: Option<String>,
+    pub link_connect: Option<String>,
+    pub rewind_speed: Option<u32>,
+    pub record_movie: Option<String>,
+    pub record_video: Option<String>,
+    pub record_gif: Option<String>,
+    /// First frame and the one after the last, `None` records until exit.
+    pub gif_frames: (u32, Option<u32>),
+    pub play_movie: Option<String>,
+    /// 1-based like the track numbers shown by the player.
+    pub gbs_track: Option<u8>,
+    pub gbs_export: Option<String>,
+    pub gbs_seconds: u32,
+}
+
+impl Options {
+    fn new() -> Options {
+        Options {
+            rom: String::new(),
+            model: None,
+            boot_rom: None,
+            palette: None,
+            scale: DEFAULT_SCALE,
+            fullscreen: false,
+            paused: false,
+            state: None,
+            debug: false,
+            headless: None,
+            until: None,
+            fail_if: None,
+            screenshot: None,
+            tile_screenshot: None,
+            screenshot_scale: 1,
+            audio: true,
+            log_level: LogLevel::Info,
+            camera: None,
+            printer: None,
+            link_listen: None,
+            link_connect: None,
+            rewind_speed: None,
+            record_movie: None,
+            record_video: None,
+            record_gif: None,
+            gif_frames: (0, None),
+            play_movie: None,
+            gbs_track: None,
+            gbs_export: None,
+            gbs_seconds: DEFAULT_GBS_SECONDS,
+        }
+    }
+
+    /// Parses the arguments after the program name. A config file is applied first, so
+    /// the command line overrides it.
+    pub fn parse(args: &[String]) -> Result<Options, CliError> {
+        let mut options = Options::new();
+        if let Some(path) = config_path(args)? {
+            let config = std::fs::read_to_string(&path).map_err(|e| CliError::ConfigError(path.clone(), e))?;
+            options.apply(&config_args(&config))?;
+        }
+        options.apply(args)?;
+
+        if options.rom.is_empty() {
+            return Err(CliError::MissingRom);
+        }
+        // The serial port takes a single cable.
+        let cables = [
+            ("--printer", options.printer.is_some()),
+            ("--link-listen", options.link_listen.is_some()),
+            ("--link-connect", options.link_connect.is_some()),
+        ];
+        let mut given = cables.iter().filter(|(_, set)| *set).map(|(arg, _)| arg.to_string());
+        if let (Some(first), Some(second)) = (given.next(), given.next()) {
+            return Err(CliError::ConflictingOptions(first, second));
+        }
+        if !options.rom.ends_with(".gbs") {
+            if options.gbs_track.is_some() {
+                return Err(CliError::NeedsGbs("--gbs-track".to_string()));
+            }
+            if options.gbs_export.is_some() {
+                return Err(CliError::NeedsGbs("--gbs-export".to_string()));
+            }
+        }
+        Ok(options)
+    }
+
+    fn apply(&mut self, args: &[String]) -> Result<(), CliError> {
+        let mut positional_rom = false;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().cloned().ok_or_else(|| CliError::MissingValue(arg.clone()));
+            match arg.as_str() {
+                "-h" | "--help" => return Err(CliError::Help),
+                "--fullscreen" => self.fullscreen = true,
+                "--paused" => self.paused = true,
+                "--debug" => self.debug = true,
+                "--model" => self.model = Some(parse_model(arg, &value()?)?),
+                "--boot-rom" => self.boot_rom = Some(value()?),
+                "--palette" => {
+                    let palette = value()?;
+                    self.palette = Some(Palette::from_name(&palette).ok_or(CliError::InvalidValue(arg.clone(), palette))?);
+                }
+                "--scale" => self.scale = parse_number(arg, &value()?)?,
+                "--state" => self.state = Some(value()?),
+                "--headless" => self.headless = Some(parse_number(arg, &value()?)?),
+                "--until" => self.until = Some(parse_condition(arg, &value()?)?),
+                "--fail-if" => self.fail_if = Some(parse_condition(arg, &value()?)?),
+                "--screenshot" => self.screenshot = Some(value()?),
+                "--tile-screenshot" => self.tile_screenshot = Some(value()?),
+                "--screenshot-scale" => self.screenshot_scale = parse_number(arg, &value()?)?,
+                "--audio" => self.audio = parse_switch(arg, &value()?)?,
+                "--log-level" => {
+                    let level = value()?;
+                    self.log_level = LogLevel::from_name(&level).ok_or(CliError::InvalidValue(arg.clone(), level))?;
+                }
+                // Only meaningful on the command line, read before everything else.
+                "--config" => {
+                    value()?;
+                }
+                "--camera" => self.camera = Some(value()?),
+                "--printer" => self.printer = Some(value()?),
+                "--link-listen" => self.link_listen = Some(value()?),
+                "--link-connect" => self.link_connect = Some(value()?),
+                "--rewind-speed" => self.rewind_speed = Some(parse_number(arg, &value()?)?),
+                "--record-movie" => self.record_movie = Some(value()?),
+                "--record-video" => self.record_video = Some(value()?),
+                "--record-gif" => self.record_gif = Some(value()?),
+                "--gif-frames" => self.gif_frames = parse_frame_range(arg, &value()?)?,
+                "--play-movie" => self.play_movie = Some(value()?),
+                "--gbs-track" => self.gbs_track = Some(parse_number(arg, &value()?)?),
+                "--gbs-export" => self.gbs_export = Some(value()?),
+                "--gbs-seconds" => self.gbs_seconds = parse_number(arg, &value()?)?,
+                "--rom" => self.rom = value()?,
+                _ if arg.starts_with('-') => return Err(CliError::UnknownArgument(arg.clone())),
+                // Replaces a `rom` from the config file.
+                _ if !positional_rom => {
+                    self.rom = arg.clone();
+                    positional_rom = true;
+                }
+                _ => return Err(CliError::UnknownArgument(arg.clone())),
+            }
+        }
+
+        if self.scale == 0 {
+            return Err(CliError::InvalidValue("--scale".to_string(), "0".to_string()));
+        }
+        if self.screenshot_scale == 0 {
+            return Err(CliError::InvalidValue("--screenshot-scale".to_string(), "0".to_string()));
+        }
+        if self.gbs_track == Some(0) {
+            return Err(CliError::InvalidValue("--gbs-track".to_string(), "0".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// `--config` is looked up ahead of the other options.
+fn config_path(args: &[String]) -> Result<Option<String>, CliError> {
+    match args.iter().position(|arg| arg == "--config") {
+        Some(index) => match args.get(index + 1) {
+            Some(path) => Ok(Some(path.clone())),
+            None => Err(CliError::MissingValue("--config".to_string())),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Turns `name = value` lines into the matching `--name value` arguments. Switches take
+/// `true` or `false`, `#` starts a comment.
+fn config_args(config: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    for line in config.lines() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let (name, value) = match line.split_once('=') {
+            Some((name, value)) => (name.trim(), Some(value.trim())),
+            None => (line, None),
+        };
+        match (name, value) {
+            ("fullscreen" | "paused" | "debug", Some("false")) => {}
+            ("fullscreen" | "paused" | "debug", _) => args.push(format!("--{}", name)),
+            (_, Some(value)) => {
+                args.push(format!("--{}", name));
+                args.push(value.to_string());
+            }
+            (_, None) => args.push(format!("--{}", name)),
+        }
+    }
+    args
+}
+
+fn parse_number<T: std::str::FromStr>(arg: &str, value: &str) -> Result<T, CliError> {
+    value.parse().map_err(|_| CliError::InvalidValue(arg.to_string(), value.to_string()))
+}
+
+fn parse_switch(arg: &str, value: &str) -> Result<bool, CliError> {
+    match value {
+        "on" | "true" => Ok(true),
+        "off" | "false" => Ok(false),
+        _ => Err(CliError::InvalidValue(arg.to_string(), value.to_string())),
+    }
+}
+
+fn parse_condition(arg: &str, value: &str) -> Result<StopCondition, CliError> {
+    StopCondition::parse(value).map_err(|_| CliError::InvalidValue(arg.to_string(), value.to_string()))
+}
+
+/// `N` for frames 0 to N, `N..M` for N to M.
+fn parse_frame_range(arg: &str, value: &str) -> Result<(u32, Option<u32>), CliError> {
+    let (start, end) = match value.split_once("..") {
+        Some((start, end)) => (parse_number(arg, start)?, parse_number(arg, end)?),
+        None => (0, parse_number(arg, value)?),
+    };
+    match start < end {
+        true => Ok((start, Some(end))),
+        false => Err(CliError::InvalidValue(arg.to_string(), value.to_string())),
+    }
+}
+
+fn parse_model(arg: &str, value: &str) -> Result<Model, CliError> {
+    Model::from_name(value).ok_or_else(|| CliError::InvalidValue(arg.to_string(), value.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_parse_options_and_config() {
+        let options = Options::parse(&args("game.gb --scale 2 --fullscreen --audio off --headless 60")).unwrap();
+        assert_eq!(options.rom, "game.gb");
+        assert_eq!((options.scale, options.fullscreen, options.audio, options.headless), (2, true, false, Some(60)));
+
+        let config = config_args("# defaults\nscale = 3\npaused = false\ndebug\nlog-level = trace\n");
+        assert_eq!(config, args("--scale 3 --debug --log-level trace"));
+
+        assert!(matches!(Options::parse(&args("game.gb --volume 3")), Err(CliError::UnknownArgument(_))));
+        assert!(matches!(Options::parse(&args("game.gb --scale")), Err(CliError::MissingValue(_))));
+        assert!(matches!(Options::parse(&args("game.gb --audio maybe")), Err(CliError::InvalidValue(_, _))));
+        assert!(matches!(Options::parse(&args("--debug")), Err(CliError::MissingRom)));
+        assert!(matches!(Options::parse(&args("game.gb --gbs-export song.wav")), Err(CliError::NeedsGbs(_))));
+        assert!(matches!(
+            Options::parse(&args("game.gb --printer out --link-connect 127.0.0.1:5000")),
+            Err(CliError::ConflictingOptions(_, _))
+        ));
+        assert!(matches!(Options::parse(&args("music.gbs --gbs-track 0")), Err(CliError::InvalidValue(_, _))));
+        assert!(matches!(Options::parse(&args("-h")), Err(CliError::Help)));
+    }
+}
@@ -1,5 +1,4 @@
 use crate::bus::BusError;
-use crate::tick::TickError;
 
 #[derive(Debug)]
 pub enum CpuError {
@@ -7,7 +6,6 @@ pub enum CpuError {
     InvalidInstruction(u32),
     InvalidRegister,
     InvalidCb(u8),
-    TickError(TickError),
 }
 
 impl From<BusError> for CpuError {
@@ -15,9 +13,3 @@ impl From<BusError> for CpuError {
         CpuError::BusError(error)
     }
 }
-
-impl From<TickError> for CpuError {
-    fn from(error: TickError) -> Self {
-        CpuError::TickError(error)
-    }
-}
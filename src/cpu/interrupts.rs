@@ -16,7 +16,7 @@ impl InterruptType {
             InterruptType::LcdStat => (value & 0x02) != 0,
             InterruptType::Timer => (value & 0x04) != 0,
             InterruptType::Serial => (value & 0x08) != 0,
-            InterruptType::JoyPad => (value & 0x16) != 0,
+            InterruptType::JoyPad => (value & 0x10) != 0,
         }
     }
 
@@ -27,7 +27,7 @@ impl InterruptType {
             InterruptType::LcdStat => value | 0x02,
             InterruptType::Timer => value | 0x04,
             InterruptType::Serial => value | 0x08,
-            InterruptType::JoyPad => value | 0x16,
+            InterruptType::JoyPad => value | 0x10,
         }
     }
 
@@ -79,12 +79,12 @@ impl CPU {
         addr: u16,
         interrupt_type: InterruptType,
     ) -> Result<bool, CpuError> {
-        let int_flags = self.int_flags.lock().unwrap().int_flags;
-        let ie_flags = self.ie_register.lock().unwrap().int_flags;
+        let int_flags = self.bus.io.int_flags.int_flags;
+        let ie_flags = self.bus.ie_register.int_flags;
 
         if interrupt_type.value_has_interrupt(int_flags as u32) && interrupt_type.value_has_interrupt(ie_flags as u32) {
             self.interrupt_handler(addr)?;
-            self.int_flags.lock().unwrap().remove_interrupt(interrupt_type);
+            self.bus.io.int_flags.remove_interrupt(interrupt_type);
             self.interrupt_master_enable = false;
             self.halted = false;
             return Ok(true);
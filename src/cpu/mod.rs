@@ -1,4 +1,4 @@
-mod error;
+pub mod error;
 mod fetch;
 mod flags;
 pub mod interrupts;
@@ -6,15 +6,12 @@ mod processors;
 mod stack;
 
 
-use std::sync::{Arc, Mutex};
-use crate::bus::{BusMutex};
+use crate::bus::BUS;
 use crate::cartridge::ROM_HEADER_START;
 use crate::cpu::error::CpuError;
-use crate::cpu::interrupts::IFlagsRegister;
 use crate::debug::{formatter, trace};
-use crate::emu::GlobalContext;
 use crate::instructions::{Instruction, RegType};
-use crate::tick::TickManager;
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
 
 pub struct CpuRegisters {
     pub a: u8,
@@ -56,17 +53,14 @@ pub struct CPU {
     pub dest_is_mem: bool,
     pub current_instruction: Instruction,
     pub enable_ime: bool,
-    pub int_flags: Arc<Mutex<IFlagsRegister>>,
-    pub ie_register: Arc<Mutex<IFlagsRegister>>,
     pub interrupt_master_enable: bool,
     pub stopped: bool,
-    pub bus: BusMutex,
-    pub tm: TickManager,
+    pub bus: BUS,
     pub previous_pc: u16,
 }
 
 impl CPU {
-    pub fn new(global: GlobalContext) -> CPU {
+    pub fn new(bus: BUS) -> CPU {
         CPU {
             registers: CpuRegisters::new(),
             fetch_data: 0,
@@ -78,16 +72,12 @@ impl CPU {
             current_instruction: Instruction::new(),
             enable_ime: false,
             stopped: false,
-            int_flags: global.int_flags.clone(),
-            ie_register: global.ie_register.clone(),
             interrupt_master_enable: false,
             previous_pc: ROM_HEADER_START as u16,
-            tm: global.tick_manager.clone().unwrap(),
-            bus: global.bus.unwrap(),
+            bus,
         }
     }
 
-    #[allow(dead_code)]
     pub fn reset(&mut self) {
         //Create a new cpu and clone its fields to self
         self.registers = CpuRegisters::new();
@@ -105,7 +95,7 @@ impl CPU {
     }
 
     pub fn cycle(&mut self, cycles: u32) {
-        self.tm.cycle(cycles);
+        self.bus.cycle(cycles);
     }
 
     pub fn read_register(&self, reg_type: Option<RegType>) -> Result<u16, CpuError> {
@@ -277,13 +267,14 @@ impl CPU {
             self.cycle(1);
             self.fetch_data()?;
             //Logger::log_cpu_state_with_instruction(&self);
-            let log = formatter::format_cpu_state(&self);
-            trace::Trace::log_static(log.clone());
+            if trace::Trace::recording() {
+                trace::Trace::log_static(formatter::format_cpu_state(self));
+            }
            // println!("{}", log);
             self.execute()?;
         } else {
-            self.tm.cycle(1);
-            if self.int_flags.lock().unwrap().int_flags != 0 {
+            self.bus.cycle(1);
+            if self.bus.io.int_flags.int_flags != 0 {
                 self.halted = false;
             }
         }
@@ -302,3 +293,32 @@ impl CPU {
     }
 
 }
+
+impl SaveState for CPU {
+    const TAG: [u8; 4] = *b"CPU ";
+    const VERSION: u16 = 1;
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        let r = &self.registers;
+        writer.bytes(&[r.a, r.f, r.b, r.c, r.d, r.e, r.h, r.l]);
+        writer.u16(r.pc);
+        writer.u16(r.sp);
+        writer.bool(self.interrupt_master_enable);
+        writer.bool(self.enable_ime);
+        writer.bool(self.halted);
+        writer.bool(self.stopped);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        let mut regs = [0; 8];
+        reader.bytes(&mut regs)?;
+        let [a, f, b, c, d, e, h, l] = regs;
+        self.registers = CpuRegisters { a, f, b, c, d, e, h, l, pc: reader.u16()?, sp: reader.u16()? };
+        self.interrupt_master_enable = reader.bool()?;
+        self.enable_ime = reader.bool()?;
+        self.halted = reader.bool()?;
+        self.stopped = reader.bool()?;
+        self.previous_pc = self.registers.pc;
+        Ok(())
+    }
+}
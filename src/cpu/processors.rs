@@ -80,10 +80,8 @@ impl CPU {
 
     fn process_ldh(&mut self) -> Result<u32, CpuError> {
         if self.current_instruction.reg_1 == Some(RegType::RtA) {
-            self.write_register(
-                self.current_instruction.reg_1,
-                self.bus.read(0xFF00 | self.fetch_data)? as u16,
-            )?;
+            let value = self.bus.read(0xFF00 | self.fetch_data)? as u16;
+            self.write_register(self.current_instruction.reg_1, value)?;
         } else {
             self.bus.write(0xFF00 | self.mem_dest, self.registers.a)?;
         }
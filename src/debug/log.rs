@@ -1,7 +1,41 @@
+use std::sync::atomic::{AtomicU8, Ordering};
 use crate::cpu::CPU;
 use crate::debug::formatter;
 use crate::instructions::{Instruction};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Off,
+    Error,
+    Info,
+    Debug,
+    /// Also records every executed instruction for `Trace`.
+    Trace,
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+
+impl LogLevel {
+    pub fn from_name(name: &str) -> Option<LogLevel> {
+        match name {
+            "off" => Some(LogLevel::Off),
+            "error" => Some(LogLevel::Error),
+            "info" => Some(LogLevel::Info),
+            "debug" => Some(LogLevel::Debug),
+            "trace" => Some(LogLevel::Trace),
+            _ => None,
+        }
+    }
+
+    pub fn set(level: LogLevel) {
+        LEVEL.store(level as u8, Ordering::Relaxed);
+    }
+
+    pub fn enabled(level: LogLevel) -> bool {
+        LEVEL.load(Ordering::Relaxed) >= level as u8
+    }
+}
+
 pub trait LoggerTrait {
     fn log(message: String);
     fn log_cpu(cpu: &CPU);
@@ -29,7 +63,9 @@ impl LoggerTrait for Logger {
 #[cfg(feature = "log")]
 impl LoggerTrait for Logger {
     fn log(message: String) {
-        print!("{}", message);
+        if LogLevel::enabled(LogLevel::Info) {
+            print!("{}", message);
+        }
     }
 
     fn log_cpu(cpu: &CPU) {
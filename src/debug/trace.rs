@@ -1,7 +1,9 @@
 use std::sync::Mutex;
-use crate::debug::log::{Logger, LoggerTrait};
+use std::sync::atomic::{AtomicBool, Ordering};
+use crate::debug::log::{LogLevel, Logger, LoggerTrait};
 
 static TRACE: Mutex<Trace> = Mutex::new(Trace{trace: Vec::new()});
+static RECORDING: AtomicBool = AtomicBool::new(false);
 
 pub struct Trace {
     trace: Vec<String>
@@ -62,6 +64,16 @@ impl Trace {
         }
     }
     //Static trace functions
+    /// Records executed instructions even below the trace log level.
+    pub fn set_recording(recording: bool) {
+        RECORDING.store(recording, Ordering::Relaxed);
+    }
+
+    /// Formatting every instruction is slow, so it only happens when someone will look.
+    pub fn recording() -> bool {
+        RECORDING.load(Ordering::Relaxed) || LogLevel::enabled(LogLevel::Trace)
+    }
+
     pub fn log_static(message: String) {
         let mut trace = TRACE.lock().unwrap();
         trace.log(message);
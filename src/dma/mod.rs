@@ -1,37 +1,22 @@
-use std::sync::{Arc, Mutex};
-use crate::bus::{BUS, BusMutex};
-use crate::emu::GlobalContext;
-use crate::ppu::PPU;
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
 
 pub struct DMA {
     active: bool,
     byte: u8,
     value: u8,
     start_delay: u8,
-    ppu: Option<Arc<Mutex<PPU>>>,
-    bus : Option<BusMutex>,
 }
 
 impl DMA {
-    pub fn new(global_context: GlobalContext) -> DMA {
+    pub fn new() -> DMA {
         DMA {
             active: false,
             byte: 0,
             value: 0,
             start_delay: 0,
-            ppu: global_context.ppu,
-            bus: global_context.bus,
         }
     }
 
-    pub fn attach_bus(&mut self, bus: BusMutex) {
-        self.bus = Some(bus);
-    }
-
-    pub fn attach_ppu(&mut self, ppu: Arc<Mutex<PPU>>) {
-        self.ppu = Some(ppu);
-    }
-
     pub fn dma_start(&mut self, value: u8) {
         self.active = true;
         self.value = value;
@@ -39,14 +24,16 @@ impl DMA {
         self.start_delay = 2;
     }
 
-    pub fn dma_tick(&mut self) {
+    /// Advances one M-cycle. Returns the source address and the OAM offset of the byte
+    /// the bus has to copy.
+    pub fn dma_tick(&mut self) -> Option<(u16, u16)> {
         if !self.active {
-            return;
+            return None;
         }
 
         if self.start_delay > 0 {
             self.start_delay -= 1;
-            return;
+            return None;
         }
 
         if self.active {
@@ -56,12 +43,31 @@ impl DMA {
             }
         }
 
-        self.ppu.as_mut().unwrap().lock().unwrap().oam_write(self.byte as u16, self.bus.as_mut().unwrap().read((self.value as u16) * 0x100 + self.byte as u16).unwrap());
+        let copy = ((self.value as u16) * 0x100 + self.byte as u16, self.byte as u16);
         self.byte += 1;
         self.active = self.byte < 0xA0;
+        Some(copy)
     }
 
     pub fn dma_transferring(&self) -> bool {
         self.active
     }
-}
\ No newline at end of file
+}
+
+impl SaveState for DMA {
+    const TAG: [u8; 4] = *b"DMA ";
+    const VERSION: u16 = 1;
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.bool(self.active);
+        writer.bytes(&[self.byte, self.value, self.start_delay]);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.active = reader.bool()?;
+        self.byte = reader.u8()?;
+        self.value = reader.u8()?;
+        self.start_delay = reader.u8()?;
+        Ok(())
+    }
+}
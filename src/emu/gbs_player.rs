@@ -0,0 +1,191 @@
+use gbc_rs::apu::CPU_FREQUENCY;
+use crate::emu::{EMU, FramePacer};
+use gbc_rs::gbs::{Gbs, GbsError};
+use crate::gfx::color::Color;
+use crate::gfx::font;
+
+const TEXT_SCALE: u32 = 4;
+const LINE_HEIGHT: u32 = (font::GLYPH_HEIGHT + 4) * TEXT_SCALE;
+
+impl EMU {
+    pub fn load_gbs(&mut self, gbs: Gbs) -> Result<(), GbsError> {
+        let first_song = gbs.header.first_song - 1;
+        self.gbs = Some(gbs);
+        self.gbs_play_track(first_song)
+    }
+
+    /// Maps the synthetic cartridge for `track` (0 based) and restarts the CPU at its driver.
+    pub fn gbs_play_track(&mut self, track: u8) -> Result<(), GbsError> {
+        let gbs = self.gbs.as_ref().ok_or(GbsError::NoGbsLoaded)?;
+        if track >= gbs.header.song_count {
+            return Err(GbsError::InvalidTrack(track));
+        }
+
+        let cpu = &mut self.core.cpu;
+        cpu.bus.load_game(gbs.build_rom(track))?;
+        cpu.bus.reset_ram();
+        // Power cycle the APU so the previous track does not keep ringing.
+        cpu.bus.write(0xFF26, 0x00)?;
+        cpu.bus.write(0xFF26, 0x80)?;
+        cpu.bus.write(0xFF24, 0x77)?;
+        cpu.bus.write(0xFF25, 0xFF)?;
+        cpu.reset();
+        cpu.bus.io.int_flags.int_flags = 0;
+
+        self.gbs_track = track;
+        Ok(())
+    }
+
+    /// Moves to the next or previous track, wrapping around. A running WAV capture ends
+    /// with the old track and starts again in a file for the new one.
+    fn gbs_change_track(&mut self, forward: bool) {
+        let song_count = match self.gbs.as_ref() {
+            Some(gbs) => gbs.header.song_count as u16,
+            None => return,
+        };
+
+        let current = self.gbs_track as u16;
+        let track = match forward {
+            true => (current + 1) % song_count,
+            false => (current + song_count - 1) % song_count,
+        };
+
+        let was_recording = self.core.cpu.bus.io.apu.is_capturing();
+        if was_recording {
+            self.gbs_toggle_recording();
+        }
+
+        if let Err(e) = self.gbs_play_track(track as u8) {
+            println!("Failed to switch track: {:?}", e);
+            return;
+        }
+
+        if was_recording {
+            self.gbs_toggle_recording();
+        }
+    }
+
+    fn gbs_track_wav_path(&self) -> String {
+        let title = match self.gbs.as_ref() {
+            Some(gbs) if !gbs.header.title.is_empty() => gbs.header.title.clone(),
+            _ => "gbs".to_string(),
+        };
+        let title: String = title
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        format!("{}_track{:02}.wav", title, self.gbs_track + 1)
+    }
+
+    fn gbs_toggle_recording(&mut self) {
+        let path = self.gbs_track_wav_path();
+        let apu = &mut self.core.cpu.bus.io.apu;
+        if apu.is_capturing() {
+            match apu.stop_capture() {
+                Ok(_) => println!("Stopped recording"),
+                Err(e) => println!("Failed to stop recording: {:?}", e),
+            }
+            return;
+        }
+
+        match apu.start_capture(&path, false) {
+            Ok(_) => println!("Recording to {}", path),
+            Err(e) => println!("Failed to start recording: {:?}", e),
+        }
+    }
+
+    /// Renders `track` (0 based) for `seconds` of emulated time into a WAV file without
+    /// running the UI. The CPU is stepped on the calling thread.
+    pub fn export_gbs_track(&mut self, track: u8, seconds: u32, path: &str) -> Result<(), GbsError> {
+        self.gbs_play_track(track)?;
+        let cpu = &mut self.core.cpu;
+        cpu.bus.io.apu.start_capture(path, false)?;
+
+        let start = cpu.bus.tm.get_ticks();
+        let end = start + seconds as u64 * CPU_FREQUENCY as u64;
+        while cpu.bus.tm.get_ticks() < end {
+            if let Err(e) = cpu.step_cpu() {
+                let _ = cpu.bus.io.apu.stop_capture();
+                return Err(e.into());
+            }
+        }
+
+        cpu.bus.sync();
+        cpu.bus.io.apu.stop_capture()?;
+        Ok(())
+    }
+
+    fn draw_gbs_screen(&mut self) {
+        let (title, author, copyright, song_count) = match self.gbs.as_ref() {
+            Some(gbs) => (
+                gbs.header.title.clone(),
+                gbs.header.author.clone(),
+                gbs.header.copyright.clone(),
+                gbs.header.song_count,
+            ),
+            None => return,
+        };
+
+        let recording = self.core.cpu.bus.io.apu.is_capturing();
+        let track = format!("TRACK {:02}/{:02}", self.gbs_track + 1, song_count);
+        let white = Color::new(255, 255, 255);
+        let grey = Color::new(160, 160, 160);
+
+        self.gfx.clear(Color::new(0, 0, 0));
+        font::draw_text(&mut self.gfx, &track, 16, 16, TEXT_SCALE, white);
+        font::draw_text(&mut self.gfx, &title, 16, 16 + LINE_HEIGHT, TEXT_SCALE, white);
+        font::draw_text(&mut self.gfx, &author, 16, 16 + LINE_HEIGHT * 2, TEXT_SCALE, grey);
+        font::draw_text(&mut self.gfx, &copyright, 16, 16 + LINE_HEIGHT * 3, TEXT_SCALE, grey);
+        font::draw_text(&mut self.gfx, "<- PREV  NEXT ->", 16, 16 + LINE_HEIGHT * 5, TEXT_SCALE, grey);
+        let record_hint = match recording {
+            true => "R: STOP WAV (REC)",
+            false => "R: EXPORT WAV",
+        };
+        font::draw_text(&mut self.gfx, record_hint, 16, 16 + LINE_HEIGHT * 6, TEXT_SCALE, grey);
+        self.gfx.present();
+    }
+
+    fn gbs_ui_step(&mut self) {
+        let event_pump = self.gfx.get_user_events();
+        for event in &event_pump {
+            match event {
+                crate::gfx::UserEvents::Quit => {
+                    println!("Quitting the player");
+                    self.stop();
+                }
+                crate::gfx::UserEvents::KeyPressed(key) => match key.as_str() {
+                    "Right" | "N" => self.gbs_change_track(true),
+                    "Left" | "P" => self.gbs_change_track(false),
+                    "R" => self.gbs_toggle_recording(),
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+
+        self.draw_gbs_screen();
+    }
+
+    /// Runs the loaded GBS with the track selection UI instead of the game screen.
+    pub fn run_gbs(&mut self) {
+        println!("Running the GBS player");
+        self.running = true;
+
+        self.init_window();
+        self.core.cpu.bus.tm.set_ticks(0);
+
+        let mut pacer = FramePacer::new();
+        while !self.die {
+            self.gbs_ui_step();
+            if let Err(e) = self.run_frame() {
+                println!("Emulation failed: {:?}", e);
+                break;
+            }
+            pacer.wait();
+        }
+
+        if self.core.cpu.bus.io.apu.is_capturing() {
+            let _ = self.core.cpu.bus.io.apu.stop_capture();
+        }
+    }
+}
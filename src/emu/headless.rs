@@ -0,0 +1,107 @@
+use crate::emu::EMU;
+use gbc_rs::Emulator;
+
+/// Ends a headless run before its frame limit.
+pub enum StopCondition {
+    /// The serial output contains the text, as with Blargg's test ROMs.
+    SerialContains(String),
+    /// The byte at the address holds the value.
+    Memory(u16, u8),
+}
+
+impl StopCondition {
+    /// `serial:TEXT`, or `memory:ADDR=VALUE` in hex.
+    pub fn parse(value: &str) -> Result<StopCondition, String> {
+        let invalid = || format!("Invalid condition: {}", value);
+        match value.split_once(':') {
+            Some(("serial", text)) => Ok(StopCondition::SerialContains(text.to_string())),
+            Some(("memory", assignment)) => {
+                let (address, data) = assignment.split_once('=').ok_or_else(invalid)?;
+                let address = u16::from_str_radix(address.trim_start_matches("0x"), 16).map_err(|_| invalid())?;
+                let data = u8::from_str_radix(data.trim_start_matches("0x"), 16).map_err(|_| invalid())?;
+                Ok(StopCondition::Memory(address, data))
+            }
+            _ => Err(invalid()),
+        }
+    }
+
+    fn met(&self, core: &mut Emulator) -> bool {
+        match self {
+            StopCondition::SerialContains(text) => core.serial_output().contains(text.as_str()),
+            StopCondition::Memory(address, data) => core.peek(*address).is_ok_and(|value| value == *data),
+        }
+    }
+}
+
+/// How a headless run ended, `code` is the process exit status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeadlessExit {
+    /// Ran every frame, or the `until` condition was met.
+    Passed,
+    /// The `fail_if` condition was met.
+    Failed,
+    /// Ran every frame without meeting the `until` condition.
+    TimedOut,
+    /// The core stopped on an error.
+    Error,
+}
+
+impl HeadlessExit {
+    pub fn code(self) -> i32 {
+        match self {
+            HeadlessExit::Passed => 0,
+            HeadlessExit::Failed => 1,
+            HeadlessExit::TimedOut => 2,
+            HeadlessExit::Error => 3,
+        }
+    }
+}
+
+impl EMU {
+    /// Runs up to `frames` frames as fast as possible, without input or pacing. The game
+    /// screen is still drawn into `gfx` every frame.
+    pub fn run_headless(&mut self, frames: u32, until: Option<StopCondition>, fail_if: Option<StopCondition>) -> HeadlessExit {
+        self.running = true;
+        let exit = self.run_headless_frames(frames, until, fail_if);
+        self.running = false;
+
+        if let Err(e) = self.finish_movie() {
+            println!("Failed to save movie: {:?}", e);
+        }
+        if let Err(e) = self.finish_recording() {
+            println!("Failed to save recording: {}", e);
+        }
+        if let Err(e) = self.finish_gif() {
+            println!("Failed to save GIF: {}", e);
+        }
+        exit
+    }
+
+    fn run_headless_frames(&mut self, frames: u32, until: Option<StopCondition>, fail_if: Option<StopCondition>) -> HeadlessExit {
+        for _ in 0..frames {
+            let result = match self.movie.is_some() {
+                true => self.movie_step(),
+                false => self.run_frame(),
+            };
+            if let Err(e) = result {
+                println!("Emulation failed: {:?}", e);
+                return HeadlessExit::Error;
+            }
+            self.record_frame();
+            self.frame_hook();
+            self.update_window();
+
+            if fail_if.as_ref().is_some_and(|condition| condition.met(&mut self.core)) {
+                return HeadlessExit::Failed;
+            }
+            if until.as_ref().is_some_and(|condition| condition.met(&mut self.core)) {
+                return HeadlessExit::Passed;
+            }
+        }
+
+        match until {
+            Some(_) => HeadlessExit::TimedOut,
+            None => HeadlessExit::Passed,
+        }
+    }
+}
@@ -1,140 +1,136 @@
 
-use std::sync::{Arc, Mutex};
-use std::thread;
-use crate::bus::{BusMutex};
-use crate::cpu::{CPU};
+mod gbs_player;
+pub mod headless;
+mod movie_mode;
+mod recording;
+mod save_slots;
+mod screenshot;
+
+use gbc_rs::apu::CPU_FREQUENCY;
+use gbc_rs::{Emulator, EmulatorError};
+use gbc_rs::emulator::{SCREEN_HEIGHT, SCREEN_WIDTH, SGB_HEIGHT, SGB_WIDTH};
 use crate::gfx::color::Color;
 use crate::gfx::Gfx;
-use std::time::Duration;
-use crate::cpu::interrupts::IFlagsRegister;
-use crate::dma::DMA;
-use crate::io::IO;
-use crate::lcd::LCD;
-use crate::ppu::{PPU};
-use crate::tick::TickManager;
-use crate::timer::Timer;
+use std::time::{Duration, Instant};
+use crate::audio::AudioSink;
+use crate::emu::movie_mode::MovieSession;
+use crate::emu::recording::{GifCapture, Recording};
+use gbc_rs::gbs::Gbs;
+use gbc_rs::joypad::Joypad;
+use gbc_rs::ppu::{PPU, TICKS_PER_FRAME};
+use gbc_rs::savestate::rewind::RewindBuffer;
 
 const SCALE: u32 = 4;
 
-const WIDTH: u32 = 240 * SCALE;
+#[cfg_attr(not(feature = "sdl"), allow(dead_code))]
+pub const DEBUG_H: u32 = 32 * 8 * SCALE;
 
-const HEIGHT: u32 = 160 * SCALE;
+#[cfg_attr(not(feature = "sdl"), allow(dead_code))]
+pub const DEBUG_W: u32 = 16 * 8 * SCALE;
 
-const DEBUG_H: u32 = 32 * 8 * SCALE;
+/// Size of the tile viewer before scaling.
+pub const TILES_W: u32 = 16 * 8;
+pub const TILES_H: u32 = 24 * 8;
 
-const DEBUG_W: u32 = 16 * 8 * SCALE;
+/// Snapshots kept for rewinding, one every `REWIND_INTERVAL` frames: about 40 seconds.
+const REWIND_CAPACITY: usize = 600;
+const REWIND_INTERVAL: u32 = 4;
+const REWIND_KEYFRAME_INTERVAL: usize = 30;
 
 pub struct EMU {
     pub paused: bool,
     pub running: bool,
-    pub tm: TickManager,
-    pub bus: BusMutex,
-    pub cpu: Arc<Mutex<CPU>>,
-    pub ppu: Arc<Mutex<PPU>>,
+    pub core: Emulator,
     pub gfx: Box<dyn Gfx>,
-    pub debug_gfx: Box<dyn Gfx>,
+    /// Tile viewer, only shown with the debugger enabled.
+    pub debug_gfx: Option<Box<dyn Gfx>>,
+    pub audio: Option<Box<dyn AudioSink>>,
     pub die: bool,
+    pub gbs: Option<Gbs>,
+    pub gbs_track: u8,
+    pub rom_path: Option<String>,
+    pub state_slot: u8,
+    pub rewind: RewindBuffer,
+    pub rewinding: bool,
+    /// Snapshots stepped back per emulated frame while rewinding.
+    pub rewind_speed: u32,
+    last_frame: u32,
+    /// Buttons held on the keyboard.
+    pub input: u8,
+    /// Size multiplier for screenshots.
+    pub screenshot_scale: u32,
+    pub movie: Option<MovieSession>,
+    /// Video and sound being recorded, see `start_recording`.
+    pub recording: Option<Recording>,
+    /// GIF being captured, see `start_gif`.
+    pub gif: Option<GifCapture>,
 }
 
-#[derive(Clone)]
-pub struct GlobalContext {
-    pub int_flags: Arc<Mutex<IFlagsRegister>>,
-    pub ie_register: Arc<Mutex<IFlagsRegister>>,
-    pub timer: Arc<Mutex<Timer>>,
-    pub ppu: Option<Arc<Mutex<PPU>>>,
-    pub lcd: Option<Arc<Mutex<LCD>>>,
-    pub io: Option<Arc<Mutex<IO>>>,
-    pub bus: Option<BusMutex>,
-    pub dma: Option<Arc<Mutex<DMA>>>,
-    pub tick_manager: Option<TickManager>,
+/// Sleeps until the next frame is due, at the Game Boy's 59.7 Hz.
+pub(crate) struct FramePacer {
+    next_frame: Instant,
 }
 
-impl GlobalContext {
-    pub fn new() -> GlobalContext {
-        let int_flags = Arc::new(Mutex::new(IFlagsRegister::new()));
-        let ie_register = Arc::new(Mutex::new(IFlagsRegister::new()));
-        let timer = Arc::new(Mutex::new(Timer::new(int_flags.clone())));
-        let mut ctx = GlobalContext {
-            int_flags,
-            ie_register,
-            timer: timer.clone(),
-            io: None,
-            bus: None,
-            dma: None,
-            tick_manager: None,
-            ppu: None,
-            lcd: None,
-        };
-
-        let dma = Arc::new(Mutex::new(DMA::new(ctx.clone())));
-        ctx.dma = Some(dma.clone());
-
-        let lcd = Arc::new(Mutex::new(LCD::new(dma.clone())));
-        ctx.lcd = Some(lcd.clone());
-
-        let ppu = Arc::new(Mutex::new(PPU::new(ctx.clone())));
-        ctx.ppu = Some(ppu.clone());
+impl FramePacer {
+    /// Frames the host can fall behind before the pacer stops catching up.
+    const MAX_LAG: u32 = 4;
 
-        let tick_manager = TickManager::new(timer.clone(), ctx.clone());
-        ctx.tick_manager = Some(tick_manager.clone());
-
-        let io = Arc::new(Mutex::new(IO::new(ctx.clone())));
-        ctx.io = Some(io.clone());
-
-        let bus = BusMutex::new(ctx.clone());
-        ctx.bus = Some(bus.clone());
+    pub(crate) fn new() -> FramePacer {
+        FramePacer { next_frame: Instant::now() }
+    }
 
-        dma.lock().unwrap().attach_bus(bus.clone());
-        dma.lock().unwrap().attach_ppu(ppu.clone());
+    fn frame_time() -> Duration {
+        Duration::from_nanos(1_000_000_000 * TICKS_PER_FRAME / CPU_FREQUENCY as u64)
+    }
 
-        ctx
+    pub(crate) fn wait(&mut self) {
+        self.next_frame += Self::frame_time();
+        let now = Instant::now();
+        if self.next_frame > now {
+            ::std::thread::sleep(self.next_frame - now);
+        } else if now - self.next_frame > Self::frame_time() * Self::MAX_LAG {
+            self.next_frame = now;
+        }
     }
 }
 
 impl EMU {
-    pub fn default() -> EMU {
-        let gfx = Box::new(crate::gfx::sdl::SDL::new(WIDTH, HEIGHT, false).unwrap());
-        let debug_gfx = Box::new(crate::gfx::sdl::SDL::new(DEBUG_W, DEBUG_H, true).unwrap());
-
-        let ctx = GlobalContext::new();
-        let cpu = Arc::new(Mutex::new(CPU::new(ctx.clone())));
-
-        let emu = EMU {
+    /// Frontend around `core`, `rom_path` names the state slots and identifies movies.
+    /// `debug_gfx` should be `DEBUG_W` x `DEBUG_H`, `gfx` can be any size.
+    pub fn new(core: Emulator, rom_path: Option<String>, gfx: Box<dyn Gfx>, debug_gfx: Option<Box<dyn Gfx>>) -> EMU {
+        EMU {
             paused: false,
             running: false,
             die: false,
-            tm: ctx.tick_manager.clone().unwrap(),
-            bus: ctx.bus.unwrap(),
-            cpu,
-            ppu: ctx.ppu.unwrap(),
+            core,
             gfx,
             debug_gfx,
-        };
-
-
-        emu
+            audio: None,
+            gbs: None,
+            gbs_track: 0,
+            rom_path,
+            state_slot: 0,
+            rewind: RewindBuffer::new(REWIND_CAPACITY, REWIND_KEYFRAME_INTERVAL),
+            rewinding: false,
+            rewind_speed: 1,
+            last_frame: 0,
+            input: 0,
+            screenshot_scale: 1,
+            movie: None,
+            recording: None,
+            gif: None,
+        }
     }
 
+    #[allow(dead_code)]
     pub fn delay(&self, duration_ms: u64) -> () {
         ::std::thread::sleep(Duration::from_millis(duration_ms as u64));
     }
 
-    pub fn load_game(&mut self, filename: String) {
-        let content = std::fs::read(&filename).unwrap();
-        self.bus.load_game(content).unwrap();
-    }
-
     pub fn stop(&mut self) {
         self.die = true;
         self.running = false;
-        self.cpu.lock().unwrap().halted = true;
-    }
-
-    pub fn cpu_run(cpu: Arc<Mutex<CPU>>) {
-        loop {
-            let mut cpu = cpu.lock().unwrap();
-            cpu.step_cpu().unwrap();
-        }
+        self.core.cpu.halted = true;
     }
 
     fn init_window(&mut self) {
@@ -144,13 +140,20 @@ impl EMU {
     }
 
     fn init_debug_window(&mut self) {
-        self.debug_gfx.init();
-        self.debug_gfx.clear(Color::new(0, 0, 0));
-        self.debug_gfx.present();
+        let Some(debug_gfx) = self.debug_gfx.as_mut() else {
+            return;
+        };
+        debug_gfx.init();
+        debug_gfx.clear(Color::new(0, 0, 0));
+        debug_gfx.present();
     }
 
+    /// Draws the game screen, with the SGB border when there is one.
     fn update_window(&mut self) {
-        self.gfx.present();
+        match self.core.sgb_framebuffer() {
+            Some(frame) => self.gfx.draw_frame(frame, SGB_WIDTH, SGB_HEIGHT).unwrap(),
+            None => self.gfx.draw_frame(self.core.framebuffer(), SCREEN_WIDTH, SCREEN_HEIGHT).unwrap(),
+        }
     }
 
     fn draw_chunk(gfx: &mut Box<dyn Gfx>, x: u32, y: u32, color: Color) {
@@ -161,12 +164,13 @@ impl EMU {
         }
     }
 
-    fn display_tile(ppu: Arc<Mutex<PPU>>, gfx: &mut Box<dyn Gfx>, addr: u16, tile_num: u16, x: u32, y: u32) {
+    /// Writes tile `tile_num` from `addr` into `frame` at tile column `x`, row `y`.
+    fn display_tile(ppu: &PPU, frame: &mut [u32], addr: u16, tile_num: u16, x: u32, y: u32) {
         let mut tile_addr = addr + (tile_num * 16);
         for i in 0..8 {
-            let byte1 = ppu.lock().unwrap().vram_read(tile_addr);
+            let byte1 = ppu.vram_read(tile_addr);
             tile_addr += 1;
-            let byte2 = ppu.lock().unwrap().vram_read(tile_addr);
+            let byte2 = ppu.vram_read(tile_addr);
             tile_addr += 1;
             for j in 0..8 {
                 let mut color = (byte1 >> (7 - j)) & 1;
@@ -178,23 +182,37 @@ impl EMU {
                     3 => Color::new(0, 0, 0),
                     _ => Color::new(0, 0, 0),
                 };
-                Self::draw_chunk(gfx, x + j, y + i, color)
+                frame[((y * 8 + i) * TILES_W + x * 8 + j) as usize] = color.to_argb();
             }
         }
     }
 
-    fn update_debug_window(&mut self) {
-        self.debug_gfx.present();
-        self.debug_gfx.clear(Color::from_hex(0x111111));
+    /// The 384 tiles in VRAM, 16 per row, `TILES_W` x `TILES_H` pixels as 0xAARRGGBB.
+    pub fn tile_viewer_frame(&self) -> Vec<u32> {
+        let mut frame = vec![0; (TILES_W * TILES_H) as usize];
         let addr = 0x0;
-        let mut x_draw = 0;
         let mut tile_num = 0;
-        for i in 0..24 {
-            for x in 0..16 {
-                EMU::display_tile(self.ppu.clone(),&mut self.debug_gfx, addr, tile_num, x_draw + (x * 8), i * 8);
+        for i in 0..TILES_H / 8 {
+            for x in 0..TILES_W / 8 {
+                EMU::display_tile(&self.core.cpu.bus.ppu, &mut frame, addr, tile_num, x, i);
                 tile_num += 1;
             }
         }
+        frame
+    }
+
+    fn update_debug_window(&mut self) {
+        if self.debug_gfx.is_none() {
+            return;
+        }
+        let frame = self.tile_viewer_frame();
+        let debug_gfx = self.debug_gfx.as_mut().unwrap();
+        debug_gfx.present();
+        debug_gfx.clear(Color::from_hex(0x111111));
+        for (i, &pixel) in frame.iter().enumerate() {
+            let (x, y) = (i as u32 % TILES_W, i as u32 / TILES_W);
+            Self::draw_chunk(debug_gfx, x, y, Color::from_hex(pixel));
+        }
     }
 
     pub fn run(&mut self) -> () {
@@ -203,26 +221,68 @@ impl EMU {
 
         self.init_window();
         self.init_debug_window();
-        self.tm.set_ticks(0).unwrap();
 
-        let cpu_ref = self.cpu.clone();
-        thread::spawn(move || {
-            EMU::cpu_run(cpu_ref);
-        });
+        let mut pacer = FramePacer::new();
+        while !self.die {
+            self.ui_step();
+            if !self.paused {
+                let result = match self.movie.is_some() {
+                    true => self.movie_step(),
+                    false => self.run_frame(),
+                };
+                if let Err(e) = result {
+                    println!("Emulation failed: {:?}", e);
+                    break;
+                }
+                self.record_frame();
+                self.frame_hook();
+                self.play_audio();
+            }
+            pacer.wait();
+        }
 
+        if let Err(e) = self.finish_movie() {
+            println!("Failed to save movie: {:?}", e);
+        }
+        if let Err(e) = self.finish_recording() {
+            println!("Failed to save recording: {}", e);
+        }
+        if let Err(e) = self.finish_gif() {
+            println!("Failed to save GIF: {}", e);
+        }
+    }
 
-        loop {
-            if self.die {
-                break;
-            }
-            self.ui_step();
-            self.delay(1);
+    pub fn run_frame(&mut self) -> Result<(), EmulatorError> {
+        self.core.run_frame()
+    }
+
+    fn play_audio(&mut self) {
+        if let Some(audio) = self.audio.as_mut() {
+            audio.queue(&self.core.audio_samples());
         }
     }
 
+    /// Movies pick the input up at the next frame boundary instead.
+    fn set_input(&mut self, buttons: u8) {
+        self.input = buttons;
+        if self.movie.is_none() {
+            self.core.set_buttons(buttons);
+        }
+    }
+
+    /// Runs once for every frame the PPU finished since the last call.
+    fn frame_hook(&mut self) {
+        let frame = self.core.frame_count();
+        if frame == self.last_frame {
+            return;
+        }
+
+        self.last_frame = frame;
+        self.rewind_step(frame);
+    }
+
     fn ui_step(&mut self) {
 
-        //canvas.clear();
         self.update_window();
         self.update_debug_window();
         let event_pump = self.gfx.get_user_events();
@@ -232,14 +292,36 @@ impl EMU {
                     println!("Quitting the emulator");
                     self.stop();
                 }
+                crate::gfx::UserEvents::KeyPressed(key) if key == "P" => {
+                    self.paused = !self.paused;
+                    println!("{}", if self.paused { "Paused" } else { "Resumed" });
+                }
+                crate::gfx::UserEvents::KeyPressed(key) if key == "F11" || key == "F12" => {
+                    self.handle_screenshot_hotkey(key);
+                }
+                crate::gfx::UserEvents::KeyPressed(key) if key == "F10" => self.handle_recording_hotkey(),
+                crate::gfx::UserEvents::KeyPressed(key) if key == "F7" || key == "F8" => self.handle_gif_hotkey(key),
                 crate::gfx::UserEvents::KeyPressed(key) => {
                     println!("Key pressed: {}", key);
+                    match Joypad::button_for_key(key) {
+                        Some(button) => self.set_input(self.input | button),
+                        // States and rewind would break a movie.
+                        None if self.movie.is_none() => self.handle_state_hotkey(key),
+                        None => {}
+                    }
+                }
+                crate::gfx::UserEvents::KeyReleased(key) => {
+                    match Joypad::button_for_key(key) {
+                        Some(button) => self.set_input(self.input & !button),
+                        None => self.handle_state_hotkey_release(key),
+                    }
                 }
                 _ => {}
             }
         }
 
         // The rest of the game loop goes here...
+        self.draw_movie_overlay();
 
         self.gfx.present();
     }
@@ -0,0 +1,138 @@
+use crate::emu::EMU;
+use crate::gfx::color::Color;
+use crate::gfx::font;
+use gbc_rs::movie::{Movie, MovieError, MovieStart, CHECKPOINT_INTERVAL};
+use gbc_rs::EmulatorError;
+use gbc_rs::util::crc32;
+
+const TEXT_SCALE: u32 = 3;
+
+pub struct MovieSession {
+    pub movie: Movie,
+    /// Where a recording is written when the emulator stops, `None` while playing back.
+    pub record_path: Option<String>,
+    pub frame: u32,
+    pub desynced_at: Option<u32>,
+}
+
+impl EMU {
+    fn rom_crc(&self) -> Result<u32, MovieError> {
+        let rom_path = self.rom_path.as_ref().ok_or(MovieError::NoRomLoaded)?;
+        Ok(crc32(&std::fs::read(rom_path)?))
+    }
+
+    /// Link cables and the camera sensor feed in data a movie cannot replay.
+    fn check_movie_inputs(&self) -> Result<(), MovieError> {
+        match self.core.link_connected() || self.core.has_camera_source() {
+            true => Err(MovieError::ExternalInput),
+            false => Ok(()),
+        }
+    }
+
+    /// Records from the current state, or from power-on when `from_power_on` is set.
+    pub fn start_movie_recording(&mut self, path: &str, from_power_on: bool) -> Result<(), MovieError> {
+        self.check_movie_inputs()?;
+        let start = match from_power_on {
+            true if self.core.ticks() > 0 => return Err(MovieError::NotAtPowerOn),
+            true => MovieStart::PowerOn,
+            false => MovieStart::State(self.core.save_state()?),
+        };
+        let movie = Movie::new(self.rom_crc()?, self.core.model, start);
+        self.movie = Some(MovieSession { movie, record_path: Some(path.to_string()), frame: 0, desynced_at: None });
+        Ok(())
+    }
+
+    pub fn start_movie_playback(&mut self, path: &str) -> Result<(), MovieError> {
+        let movie = Movie::load(path)?;
+        if movie.rom_crc != self.rom_crc()? {
+            return Err(MovieError::RomMismatch);
+        }
+        if movie.model != self.core.model {
+            return Err(MovieError::ModelMismatch);
+        }
+        self.check_movie_inputs()?;
+        match &movie.start {
+            MovieStart::PowerOn if self.core.ticks() > 0 => return Err(MovieError::NotAtPowerOn),
+            MovieStart::PowerOn => {}
+            MovieStart::State(state) => self.core.load_state(state)?,
+        }
+        self.movie = Some(MovieSession { movie, record_path: None, frame: 0, desynced_at: None });
+        Ok(())
+    }
+
+    /// Writes a recording out. Playback sessions are just dropped.
+    pub fn finish_movie(&mut self) -> Result<(), MovieError> {
+        let session = match self.movie.take() {
+            Some(session) => session,
+            None => return Ok(()),
+        };
+        if let Some(path) = session.record_path.as_ref() {
+            session.movie.save(path)?;
+            println!("Saved movie of {} frames to {}", session.movie.inputs.len(), path);
+        }
+        Ok(())
+    }
+
+    /// Runs one movie frame: the input is applied at the frame boundary, so the run only
+    /// depends on the start state and the inputs.
+    pub(crate) fn movie_step(&mut self) -> Result<(), EmulatorError> {
+        let Some(session) = self.movie.as_mut() else {
+            return Ok(());
+        };
+
+        let frame = session.frame as usize;
+        let buttons = match session.record_path {
+            Some(_) => {
+                session.movie.inputs.push(self.input);
+                self.input
+            }
+            None => session.movie.inputs.get(frame).copied().unwrap_or(0),
+        };
+        self.core.set_buttons(buttons);
+        self.run_frame()?;
+
+        let frame = frame as u32 + 1;
+        let checksum = match frame % CHECKPOINT_INTERVAL {
+            0 => self.core.save_state().ok().map(|state| crc32(&state)),
+            _ => None,
+        };
+        let session = self.movie.as_mut().unwrap();
+        session.frame = frame;
+        let Some(checksum) = checksum else {
+            return Ok(());
+        };
+
+        match session.record_path {
+            Some(_) => session.movie.checkpoints.push((frame, checksum)),
+            None => {
+                let expected = session.movie.checkpoint(frame);
+                if session.desynced_at.is_none() && expected.is_some_and(|expected| expected != checksum) {
+                    println!("Movie desynced at frame {}", frame);
+                    session.desynced_at = Some(frame);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Frame counter, and where playback desynced, over the game screen.
+    pub(crate) fn draw_movie_overlay(&mut self) {
+        let Some(session) = self.movie.as_ref() else {
+            return;
+        };
+
+        let status = match (&session.record_path, session.desynced_at) {
+            (Some(_), _) => format!("REC {}", session.frame),
+            (None, Some(frame)) => format!("PLAY {}/{} DESYNC AT {}", session.frame, session.movie.inputs.len(), frame),
+            (None, None) if session.frame as usize >= session.movie.inputs.len() => {
+                format!("PLAY {} END", session.frame)
+            }
+            (None, None) => format!("PLAY {}/{}", session.frame, session.movie.inputs.len()),
+        };
+        let color = match session.desynced_at {
+            Some(_) => Color::new(255, 64, 64),
+            None => Color::new(255, 255, 255),
+        };
+        font::draw_text(&mut self.gfx, &status, 8, 8, TEXT_SCALE, color);
+    }
+}
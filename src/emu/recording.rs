@@ -0,0 +1,182 @@
+use std::path::Path;
+use crate::emu::EMU;
+use gbc_rs::apu::CPU_FREQUENCY;
+use gbc_rs::emulator::{SCREEN_HEIGHT, SCREEN_WIDTH, SGB_HEIGHT, SGB_WIDTH};
+use gbc_rs::ppu::TICKS_PER_FRAME;
+use gbc_rs::util::gif::GifWriter;
+use gbc_rs::util::y4m::Y4mWriter;
+use gbc_rs::EmulatorError;
+
+#[derive(Debug)]
+pub enum RecordingError {
+    AlreadyRecording,
+    IoError(std::io::Error),
+    EmulatorError(EmulatorError),
+}
+
+impl std::fmt::Display for RecordingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RecordingError::AlreadyRecording => write!(f, "Already recording"),
+            RecordingError::IoError(e) => write!(f, "{}", e),
+            RecordingError::EmulatorError(e) => write!(f, "{:?}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for RecordingError {
+    fn from(e: std::io::Error) -> RecordingError {
+        RecordingError::IoError(e)
+    }
+}
+
+impl From<EmulatorError> for RecordingError {
+    fn from(e: EmulatorError) -> RecordingError {
+        RecordingError::EmulatorError(e)
+    }
+}
+
+pub struct Recording {
+    video: Y4mWriter,
+    path: String,
+}
+
+pub struct GifCapture {
+    gif: Option<GifWriter>,
+    path: String,
+    /// Frames seen since the capture was set up.
+    frame: u32,
+    /// Frames `start..end` go into the GIF, `None` records until it is stopped.
+    start: u32,
+    end: Option<u32>,
+}
+
+impl EMU {
+    /// Records every emulated frame to `path` as Y4M and the sound to a WAV file next to
+    /// it. Both follow emulated time, one video frame per 70224 T-cycles, so they stay in
+    /// sync however fast the host runs.
+    pub fn start_recording(&mut self, path: &str) -> Result<(), RecordingError> {
+        if self.recording.is_some() {
+            return Err(RecordingError::AlreadyRecording);
+        }
+
+        let (width, height) = match self.core.sgb_framebuffer() {
+            Some(_) => (SGB_WIDTH, SGB_HEIGHT),
+            None => (SCREEN_WIDTH, SCREEN_HEIGHT),
+        };
+        let video = Y4mWriter::create(path, width, height, CPU_FREQUENCY, TICKS_PER_FRAME as u32)?;
+        let audio_path = Path::new(path).with_extension("wav").to_string_lossy().to_string();
+        self.core.start_audio_capture(&audio_path, false)?;
+        self.recording = Some(Recording { video, path: path.to_string() });
+        Ok(())
+    }
+
+    pub fn finish_recording(&mut self) -> Result<(), RecordingError> {
+        let Some(recording) = self.recording.take() else {
+            return Ok(());
+        };
+        let frames = recording.video.frames();
+        recording.video.finish()?;
+        self.core.stop_audio_capture()?;
+        println!("Saved recording of {} frames to {}", frames, recording.path);
+        Ok(())
+    }
+
+    /// Records frames `start..end` counted from now into a GIF at `path`, or every frame
+    /// from `start` on until `finish_gif` without an `end`.
+    pub fn start_gif(&mut self, path: &str, start: u32, end: Option<u32>) -> Result<(), RecordingError> {
+        if self.gif.is_some() {
+            return Err(RecordingError::AlreadyRecording);
+        }
+        self.gif = Some(GifCapture { gif: None, path: path.to_string(), frame: 0, start, end });
+        Ok(())
+    }
+
+    pub fn finish_gif(&mut self) -> Result<(), RecordingError> {
+        let Some(capture) = self.gif.take() else {
+            return Ok(());
+        };
+        let Some(gif) = capture.gif else {
+            println!("No frames captured for {}", capture.path);
+            return Ok(());
+        };
+        let frames = gif.frames();
+        gif.finish()?;
+        println!("Saved GIF of {} frames to {}", frames, capture.path);
+        Ok(())
+    }
+
+    /// Adds the frame that just finished, called once per emulated frame.
+    pub(crate) fn record_frame(&mut self) {
+        if let Some(recording) = self.recording.as_mut() {
+            let frame = self.core.sgb_framebuffer().unwrap_or(self.core.framebuffer());
+            if let Err(e) = recording.video.write_frame(frame) {
+                println!("Failed to record frame: {}", e);
+                if let Err(e) = self.finish_recording() {
+                    println!("Failed to save recording: {}", e);
+                }
+            }
+        }
+        if let Err(e) = self.capture_gif_frame() {
+            println!("Failed to capture GIF frame: {}", e);
+            self.gif = None;
+        }
+    }
+
+    fn capture_gif_frame(&mut self) -> Result<(), RecordingError> {
+        let Some(capture) = self.gif.as_mut() else {
+            return Ok(());
+        };
+        let frame = capture.frame;
+        capture.frame += 1;
+        if frame < capture.start {
+            return Ok(());
+        }
+        if capture.end.is_some_and(|end| frame >= end) {
+            return self.finish_gif();
+        }
+
+        let (pixels, width, height) = match self.core.sgb_framebuffer() {
+            Some(pixels) => (pixels, SGB_WIDTH, SGB_HEIGHT),
+            None => (self.core.framebuffer(), SCREEN_WIDTH, SCREEN_HEIGHT),
+        };
+        if capture.gif.is_none() {
+            // The four shades of each layer, SGB colours go into per-frame tables.
+            let palette = self.core.palette();
+            let colors: Vec<u32> = [palette.bg, palette.obj0, palette.obj1].concat();
+            let rate_den = TICKS_PER_FRAME as u32;
+            capture.gif = Some(GifWriter::create(&capture.path, width, height, &colors, CPU_FREQUENCY, rate_den)?);
+        }
+        capture.gif.as_mut().unwrap().write_frame(pixels)?;
+        Ok(())
+    }
+
+    /// F7 starts a GIF next to the ROM, F8 saves it.
+    pub(crate) fn handle_gif_hotkey(&mut self, key: &str) {
+        let result = match key {
+            "F7" => {
+                let path = self.output_path("clip", "gif");
+                self.start_gif(&path, 0, None).inspect(|_| println!("Capturing GIF to {}", path))
+            }
+            "F8" => self.finish_gif(),
+            _ => return,
+        };
+        if let Err(e) = result {
+            println!("GIF capture failed: {}", e);
+        }
+    }
+
+    /// F10 starts a recording next to the ROM, or stops the running one.
+    pub(crate) fn handle_recording_hotkey(&mut self) {
+        let result = match self.recording.is_some() {
+            true => self.finish_recording(),
+            false => {
+                let path = self.output_path("video", "y4m");
+                self.start_recording(&path).inspect(|_| println!("Recording to {}", path))
+            }
+        };
+        if let Err(e) = result {
+            println!("Recording failed: {}", e);
+        }
+    }
+}
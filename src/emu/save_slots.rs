@@ -0,0 +1,87 @@
+use std::path::Path;
+use crate::emu::{EMU, REWIND_INTERVAL};
+use gbc_rs::savestate::SaveStateError;
+
+impl EMU {
+    /// `<rom name>.ss<slot>` next to the ROM.
+    fn state_slot_path(&self, slot: u8) -> Result<String, SaveStateError> {
+        let rom_path = self.rom_path.as_ref().ok_or(SaveStateError::NoRomLoaded)?;
+        Ok(Path::new(rom_path).with_extension(format!("ss{}", slot)).to_string_lossy().to_string())
+    }
+
+    pub fn save_slot(&mut self, slot: u8) -> Result<String, SaveStateError> {
+        let path = self.state_slot_path(slot)?;
+        std::fs::write(&path, self.core.export_bess()?)?;
+        Ok(path)
+    }
+
+    pub fn load_slot(&mut self, slot: u8) -> Result<String, SaveStateError> {
+        let path = self.state_slot_path(slot)?;
+        self.core.load_state(&std::fs::read(&path)?)?;
+        Ok(path)
+    }
+
+    /// Records a snapshot every `REWIND_INTERVAL` frames, or steps back through them while
+    /// rewinding.
+    pub(crate) fn rewind_step(&mut self, frame: u32) {
+        if self.rom_path.is_none() || self.movie.is_some() {
+            return;
+        }
+
+        if self.rewinding {
+            let mut state = None;
+            for _ in 0..self.rewind_speed {
+                match self.rewind.pop() {
+                    Some(snapshot) => state = Some(snapshot),
+                    None => break,
+                }
+            }
+            // Keep the oldest snapshot, so holding the key stays on it.
+            if let Some(state) = state {
+                if self.rewind.is_empty() {
+                    self.rewind.push(&state);
+                }
+                if let Err(e) = self.core.load_state(&state) {
+                    println!("Failed to rewind: {:?}", e);
+                }
+            }
+            return;
+        }
+
+        if frame.is_multiple_of(REWIND_INTERVAL) {
+            match self.core.save_state() {
+                Ok(state) => self.rewind.push(&state),
+                Err(e) => println!("Failed to take rewind snapshot: {:?}", e),
+            }
+        }
+    }
+
+    /// 0-9 select the slot, F5 saves to it and F9 loads it. Backspace rewinds while held.
+    pub(crate) fn handle_state_hotkey(&mut self, key: &str) {
+        match key {
+            "Backspace" => self.rewinding = true,
+            "F5" => match self.save_slot(self.state_slot) {
+                Ok(path) => println!("Saved state to {}", path),
+                Err(e) => println!("Failed to save state: {:?}", e),
+            },
+            "F9" => match self.load_slot(self.state_slot) {
+                Ok(path) => println!("Loaded state from {}", path),
+                Err(e) => println!("Failed to load state: {:?}", e),
+            },
+            _ => {
+                if let Ok(slot) = key.parse::<u8>() {
+                    if slot <= 9 {
+                        self.state_slot = slot;
+                        println!("State slot {}", slot);
+                    }
+                }
+            }
+        }
+    }
+
+    pub(crate) fn handle_state_hotkey_release(&mut self, key: &str) {
+        if key == "Backspace" {
+            self.rewinding = false;
+        }
+    }
+}
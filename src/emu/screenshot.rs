@@ -0,0 +1,46 @@
+use std::path::Path;
+use crate::emu::{EMU, TILES_H, TILES_W};
+use gbc_rs::util::png;
+
+impl EMU {
+    /// Saves the last frame as a PNG, with the SGB border on SGB models.
+    pub fn save_screenshot(&self, path: &str) -> std::io::Result<()> {
+        std::fs::write(path, self.core.screenshot(self.screenshot_scale))
+    }
+
+    /// Saves the tiles the debugger shows as a PNG.
+    pub fn save_tile_screenshot(&self, path: &str) -> std::io::Result<()> {
+        std::fs::write(path, png::encode_argb(TILES_W, TILES_H, &self.tile_viewer_frame(), self.screenshot_scale))
+    }
+
+    /// `<rom name>-<name><n>.<extension>` next to the ROM, the first one not taken yet.
+    pub(crate) fn output_path(&self, name: &str, extension: &str) -> String {
+        let base = self.rom_path.as_deref().unwrap_or("gbc-rs");
+        let stem = Path::new(base).with_extension("").to_string_lossy().to_string();
+        (0..)
+            .map(|n| format!("{}-{}{}.{}", stem, name, n, extension))
+            .find(|path| !Path::new(path).exists())
+            .unwrap()
+    }
+
+    /// F12 saves the screen, F11 the tile viewer.
+    pub(crate) fn handle_screenshot_hotkey(&mut self, key: &str) {
+        let (path, result) = match key {
+            "F12" => {
+                let path = self.output_path("screenshot", "png");
+                let result = self.save_screenshot(&path);
+                (path, result)
+            }
+            "F11" => {
+                let path = self.output_path("tiles", "png");
+                let result = self.save_tile_screenshot(&path);
+                (path, result)
+            }
+            _ => return,
+        };
+        match result {
+            Ok(()) => println!("Saved screenshot to {}", path),
+            Err(e) => println!("Failed to save screenshot: {}", e),
+        }
+    }
+}
@@ -0,0 +1,333 @@
+mod model;
+mod save_state;
+
+use crate::apu::ApuError;
+use crate::boot::{self, BootRom, BootRomError, CompatPalette};
+use crate::bus::{BusError, BUS};
+use crate::cpu::CPU;
+use crate::cpu::error::CpuError;
+use crate::lcd::palette::Palette;
+use crate::ppu::TICKS_PER_FRAME;
+use crate::savestate::SaveStateError;
+use crate::serial::link::LinkCable;
+use crate::util::png;
+
+pub use crate::apu::SAMPLE_RATE;
+pub use crate::emulator::model::Model;
+pub use crate::ppu::{XRES as SCREEN_WIDTH, YRES as SCREEN_HEIGHT};
+pub use crate::sgb::{SGB_HEIGHT, SGB_WIDTH};
+
+#[derive(Debug, Clone, Default)]
+pub struct EmulatorOptions {
+    /// Keeps the APU output for `audio_samples`.
+    pub audio: bool,
+    /// Boot ROM to start from. Without one the machine starts in the state the boot ROM
+    /// of the model leaves it in.
+    pub boot_rom: Option<Vec<u8>>,
+    /// Colours for the DMG shades on DMG models. CGB models running a DMG cartridge use the
+    /// set their boot ROM picks instead.
+    pub palette: Option<Palette>,
+}
+
+#[derive(Debug)]
+pub enum EmulatorError {
+    BusError(BusError),
+    CpuError(CpuError),
+    StateError(SaveStateError),
+    ApuError(ApuError),
+    BootRomError(BootRomError),
+}
+
+impl From<BusError> for EmulatorError {
+    fn from(e: BusError) -> EmulatorError {
+        EmulatorError::BusError(e)
+    }
+}
+
+impl From<CpuError> for EmulatorError {
+    fn from(e: CpuError) -> EmulatorError {
+        EmulatorError::CpuError(e)
+    }
+}
+
+impl From<SaveStateError> for EmulatorError {
+    fn from(e: SaveStateError) -> EmulatorError {
+        EmulatorError::StateError(e)
+    }
+}
+
+impl From<ApuError> for EmulatorError {
+    fn from(e: ApuError) -> EmulatorError {
+        EmulatorError::ApuError(e)
+    }
+}
+
+impl From<BootRomError> for EmulatorError {
+    fn from(e: BootRomError) -> EmulatorError {
+        EmulatorError::BootRomError(e)
+    }
+}
+
+/// A Game Boy with a cartridge inserted, driven one frame or one instruction at a time.
+/// Nothing here touches a window or the audio device, frontends present the output.
+pub struct Emulator {
+    pub model: Model,
+    /// Owns the bus and through it every other component, for frontends that need more
+    /// than the methods below.
+    pub cpu: CPU,
+    /// Palette the CGB boot ROM picked for a DMG cartridge, `None` for the default one.
+    pub compat_palette: Option<CompatPalette>,
+    /// Buttons currently held, one `joypad::BUTTON_*` bit each.
+    buttons: u8,
+    frame_overshoot: u64,
+}
+
+impl Emulator {
+    pub fn new(model: Model, rom: Vec<u8>, options: EmulatorOptions) -> Result<Emulator, EmulatorError> {
+        let mut bus = BUS::new();
+        bus.set_model(model);
+        bus.load_game(rom)?;
+        bus.io.apu.set_sample_buffer(options.audio);
+        let header = bus.rom_header().ok_or(BusError::NoCartridgeLoaded)?;
+        let compat_mode = model.is_cgb() && !boot::cgb_cartridge(header);
+        let compat_palette = match compat_mode {
+            true => CompatPalette::from_header(header),
+            false => None,
+        };
+        let registers = boot::post_boot_registers(model, header);
+
+        let mut cpu = CPU::new(bus);
+        let palette = match model.is_cgb() {
+            true if compat_mode => Some(Palette::cgb_compat(compat_palette)),
+            true => None,
+            false => options.palette,
+        };
+        if let Some(palette) = palette {
+            cpu.bus.ppu.lcd.set_palette(palette);
+        }
+        match options.boot_rom {
+            Some(data) => {
+                let boot_rom = BootRom::new(data)?;
+                boot_rom.check_model(model)?;
+                cpu.registers = boot::power_on_registers();
+                cpu.previous_pc = 0;
+                for (address, data) in boot::POWER_ON_IO {
+                    cpu.bus.write(address, data)?;
+                }
+                cpu.bus.io.timer.load_registers(0, 0, 0, 0);
+                cpu.bus.boot_rom = Some(boot_rom);
+            }
+            None => {
+                cpu.registers = registers;
+                for (address, data) in boot::post_boot_io(model) {
+                    cpu.bus.write(address, data)?;
+                }
+            }
+        }
+
+        Ok(Emulator {
+            model,
+            cpu,
+            compat_palette,
+            buttons: 0,
+            frame_overshoot: 0,
+        })
+    }
+
+    pub fn palette(&self) -> &Palette {
+        self.cpu.bus.ppu.lcd.palette()
+    }
+
+    /// Colours the DMG shades are drawn with from now on.
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.cpu.bus.ppu.lcd.set_palette(palette);
+    }
+
+    /// Swaps the cartridge without resetting the rest of the machine.
+    pub fn load_rom(&mut self, rom: Vec<u8>) -> Result<(), EmulatorError> {
+        Ok(self.cpu.bus.load_game(rom)?)
+    }
+
+    /// Runs the machine for one frame, 70224 T-cycles. An instruction that crosses the end
+    /// of the frame is paid back from the next one, so frame boundaries stay at fixed points
+    /// of emulated time.
+    pub fn run_frame(&mut self) -> Result<(), EmulatorError> {
+        let cpu = &mut self.cpu;
+        let start = cpu.bus.tm.get_ticks();
+        let end = start + TICKS_PER_FRAME - self.frame_overshoot.min(TICKS_PER_FRAME);
+        while cpu.bus.tm.get_ticks() < end {
+            cpu.step_cpu()?;
+        }
+        // Audio and everything else between events is caught up once per frame.
+        cpu.bus.sync();
+        self.frame_overshoot = cpu.bus.tm.get_ticks() - end;
+        Ok(())
+    }
+
+    /// Runs one instruction, or one halted M-cycle. Returns the T-cycles it took.
+    pub fn step_instruction(&mut self) -> Result<u32, EmulatorError> {
+        let start = self.cpu.bus.tm.get_ticks();
+        self.cpu.step_cpu()?;
+        self.cpu.bus.sync();
+        Ok((self.cpu.bus.tm.get_ticks() - start) as u32)
+    }
+
+    /// Last finished frame, `SCREEN_WIDTH` x `SCREEN_HEIGHT` pixels as 0xAARRGGBB.
+    pub fn framebuffer(&self) -> &[u32] {
+        self.cpu.bus.ppu.video_buffer()
+    }
+
+    /// On SGB models, the last frame with its border and colours, `SGB_WIDTH` x
+    /// `SGB_HEIGHT` pixels as 0xAARRGGBB.
+    pub fn sgb_framebuffer(&self) -> Option<&[u32]> {
+        self.cpu.bus.sgb.as_ref().map(|sgb| sgb.frame())
+    }
+
+    /// The last frame as a PNG, scaled up `scale` times. SGB models include the border.
+    pub fn screenshot(&self, scale: u32) -> Vec<u8> {
+        match self.sgb_framebuffer() {
+            Some(frame) => png::encode_argb(SGB_WIDTH, SGB_HEIGHT, frame, scale),
+            None => png::encode_argb(SCREEN_WIDTH, SCREEN_HEIGHT, self.framebuffer(), scale),
+        }
+    }
+
+    /// Frames the PPU finished since power-on.
+    pub fn frame_count(&self) -> u32 {
+        self.cpu.bus.ppu.current_frame()
+    }
+
+    /// T-cycles since power-on.
+    pub fn ticks(&self) -> u64 {
+        self.cpu.bus.tm.get_ticks()
+    }
+
+    /// Stereo samples at `SAMPLE_RATE` produced since the last call, left first. Empty
+    /// unless `EmulatorOptions::audio` was set.
+    pub fn audio_samples(&mut self) -> Vec<i16> {
+        self.cpu.bus.io.apu.take_samples()
+    }
+
+    /// Text most recently sent over the serial port, the last 4 KiB at most.
+    pub fn serial_output(&self) -> &str {
+        &self.cpu.bus.io.serial.message
+    }
+
+    pub fn set_button(&mut self, button: u8, pressed: bool) {
+        let buttons = match pressed {
+            true => self.buttons | button,
+            false => self.buttons & !button,
+        };
+        self.set_buttons(buttons);
+    }
+
+    /// Replaces every held button at once.
+    pub fn set_buttons(&mut self, buttons: u8) {
+        self.buttons = buttons;
+        self.cpu.bus.io.set_buttons(buttons);
+    }
+
+    pub fn buttons(&self) -> u8 {
+        self.buttons
+    }
+
+    /// Reads memory as the CPU would see it.
+    pub fn peek(&mut self, address: u16) -> Result<u8, EmulatorError> {
+        Ok(self.cpu.bus.read(address)?)
+    }
+
+    /// Writes memory as the CPU would, including the side effects of register writes.
+    pub fn poke(&mut self, address: u16, data: u8) -> Result<(), EmulatorError> {
+        Ok(self.cpu.bus.write(address, data)?)
+    }
+
+    /// Starts recording the mixed stereo output to `path`. With `per_channel` set, each of
+    /// the four channels is also written to its own mono file next to it.
+    pub fn start_audio_capture(&mut self, path: &str, per_channel: bool) -> Result<(), EmulatorError> {
+        Ok(self.cpu.bus.io.apu.start_capture(path, per_channel)?)
+    }
+
+    pub fn stop_audio_capture(&mut self) -> Result<(), EmulatorError> {
+        Ok(self.cpu.bus.io.apu.stop_capture()?)
+    }
+
+    /// Points the Pocket Camera sensor at a PNG file or a directory of PNG frames.
+    pub fn set_camera_source(&mut self, path: &str) -> Result<(), EmulatorError> {
+        Ok(self.cpu.bus.set_camera_source(path)?)
+    }
+
+    pub fn has_camera_source(&self) -> bool {
+        self.cpu.bus.has_camera_source()
+    }
+
+    pub fn link_connected(&self) -> bool {
+        self.cpu.bus.io.serial.has_cable()
+    }
+
+    /// Plugs a link cable into the serial port. Both instances should be connected before
+    /// they start running so their sync points line up.
+    pub fn connect_link(&mut self, cable: Box<dyn LinkCable>) {
+        self.cpu.bus.io.serial.attach_cable(cable);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 32 KiB ROM that turns the LCD on with a solid black background and loops.
+    fn test_rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        let program = [
+            0x3E, 0xFF, // LD A, 0xFF
+            0xE0, 0x47, // LDH (BGP), A
+            0x18, 0xFE, // JR -2
+        ];
+        rom[0x100..0x100 + program.len()].copy_from_slice(&program);
+        rom
+    }
+
+    #[test]
+    fn test_run_frame_and_memory_access() {
+        let options = EmulatorOptions { audio: true, ..Default::default() };
+        let mut emulator = Emulator::new(Model::Dmg, test_rom(), options).unwrap();
+        emulator.run_frame().unwrap();
+        emulator.run_frame().unwrap();
+
+        assert_eq!(emulator.ticks(), 2 * TICKS_PER_FRAME + emulator.frame_overshoot);
+        assert!(emulator.frame_count() >= 1);
+        assert_eq!(emulator.framebuffer().len(), (SCREEN_WIDTH * SCREEN_HEIGHT) as usize);
+        assert!(emulator.framebuffer().iter().all(|&pixel| pixel == 0xFF_00_00_00));
+        assert!(!emulator.audio_samples().is_empty());
+        assert!(emulator.audio_samples().is_empty());
+
+        emulator.poke(0xC000, 0x42).unwrap();
+        assert_eq!(emulator.peek(0xC000).unwrap(), 0x42);
+        assert!(emulator.step_instruction().unwrap() > 0);
+    }
+
+    #[test]
+    fn test_save_state_round_trip() {
+        let mut emulator = Emulator::new(Model::Dmg, test_rom(), EmulatorOptions::default()).unwrap();
+        emulator.poke(0xFF24, 0x35).unwrap();
+        emulator.poke(0xFF12, 0xF3).unwrap();
+        emulator.poke(0xFF14, 0x87).unwrap();
+        emulator.poke(0xFF01, 0x42).unwrap();
+        emulator.poke(0xFF02, 0x81).unwrap();
+        emulator.run_frame().unwrap();
+        let state = emulator.save_state().unwrap();
+
+        let mut other = Emulator::new(Model::Dmg, test_rom(), EmulatorOptions::default()).unwrap();
+        other.load_state(&state).unwrap();
+        assert_eq!(other.save_state().unwrap(), state);
+        assert_eq!(other.peek(0xFF26).unwrap() & 0x01, 0x01);
+        assert_eq!(other.frame_overshoot, emulator.frame_overshoot);
+
+        // Both carry on the same way, sound and serial included.
+        emulator.run_frame().unwrap();
+        other.run_frame().unwrap();
+        assert_eq!(other.save_state().unwrap(), emulator.save_state().unwrap());
+
+        let mut pocket = Emulator::new(Model::Mgb, test_rom(), EmulatorOptions::default()).unwrap();
+        assert!(matches!(pocket.load_state(&state), Err(SaveStateError::ModelMismatch)));
+    }
+}
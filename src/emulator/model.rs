@@ -0,0 +1,114 @@
+/// Hardware the core emulates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    /// Original Game Boy with the early boot ROM.
+    Dmg0,
+    Dmg,
+    /// Game Boy Pocket.
+    Mgb,
+    Sgb,
+    Sgb2,
+    /// Early Game Boy Color revision.
+    Cgb0,
+    Cgb,
+    /// Game Boy Advance, running Game Boy cartridges.
+    Agb,
+}
+
+impl Model {
+    /// The model a cartridge asks for: CGB for colour cartridges, SGB for cartridges with
+    /// SGB support, DMG otherwise.
+    pub fn from_rom(rom: &[u8]) -> Model {
+        let byte = |address: usize| rom.get(address).copied().unwrap_or(0);
+        if byte(0x143) & 0x80 != 0 {
+            return Model::Cgb;
+        }
+        // The SGB flag only counts with the new licensee code.
+        if byte(0x146) == 0x03 && byte(0x14B) == 0x33 {
+            return Model::Sgb;
+        }
+        Model::Dmg
+    }
+
+    pub fn from_name(name: &str) -> Option<Model> {
+        match name {
+            "dmg0" => Some(Model::Dmg0),
+            "dmg" => Some(Model::Dmg),
+            "mgb" => Some(Model::Mgb),
+            "sgb" => Some(Model::Sgb),
+            "sgb2" => Some(Model::Sgb2),
+            "cgb0" => Some(Model::Cgb0),
+            "cgb" => Some(Model::Cgb),
+            "agb" => Some(Model::Agb),
+            _ => None,
+        }
+    }
+
+    /// Models with the colour hardware, which run DMG cartridges in compatibility mode.
+    pub fn is_cgb(self) -> bool {
+        matches!(self, Model::Cgb0 | Model::Cgb | Model::Agb)
+    }
+
+    pub fn is_sgb(self) -> bool {
+        matches!(self, Model::Sgb | Model::Sgb2)
+    }
+
+    /// Model identifier used in BESS states.
+    pub fn bess_id(self) -> [u8; 4] {
+        match self {
+            Model::Dmg0 => *b"GD0 ",
+            Model::Dmg => *b"GDB ",
+            Model::Mgb => *b"GM  ",
+            Model::Sgb => *b"SN  ",
+            Model::Sgb2 => *b"S2  ",
+            Model::Cgb0 => *b"CC0 ",
+            Model::Cgb => *b"CCE ",
+            Model::Agb => *b"CA  ",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{Emulator, EmulatorOptions};
+
+    fn rom(cgb_flag: u8, sgb_flag: u8, licensee: u8) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x143] = cgb_flag;
+        rom[0x146] = sgb_flag;
+        rom[0x14B] = licensee;
+        rom
+    }
+
+    #[test]
+    fn test_model_detection_and_registers() {
+        assert_eq!(Model::from_rom(&rom(0x80, 0x00, 0x33)), Model::Cgb);
+        assert_eq!(Model::from_rom(&rom(0xC0, 0x03, 0x33)), Model::Cgb);
+        assert_eq!(Model::from_rom(&rom(0x00, 0x03, 0x33)), Model::Sgb);
+        assert_eq!(Model::from_rom(&rom(0x00, 0x03, 0x01)), Model::Dmg);
+        assert_eq!(Model::from_rom(&[]), Model::Dmg);
+
+        let registers = |model: Model, rom: Vec<u8>| {
+            let registers = Emulator::new(model, rom, EmulatorOptions::default()).unwrap().cpu.registers;
+            (registers.a, registers.b, registers.c, registers.e)
+        };
+        assert_eq!(registers(Model::Dmg0, rom(0, 0, 0)), (0x01, 0xFF, 0x13, 0xC1));
+        assert_eq!(registers(Model::Mgb, rom(0, 0, 0)), (0xFF, 0x00, 0x13, 0xD8));
+        assert_eq!(registers(Model::Sgb2, rom(0, 0x03, 0x33)), (0xFF, 0x00, 0x14, 0x00));
+        assert_eq!(registers(Model::Cgb, rom(0x80, 0, 0)), (0x11, 0x00, 0x00, 0x56));
+        assert_eq!(registers(Model::Agb, rom(0x80, 0, 0)), (0x11, 0x01, 0x00, 0x56));
+
+        // The infrared port only exists on CGB models.
+        let rp = |model: Model| {
+            let mut emulator = Emulator::new(model, rom(0, 0, 0), EmulatorOptions::default()).unwrap();
+            emulator.poke(0xFF56, 0x01).unwrap();
+            emulator.peek(0xFF56).unwrap()
+        };
+        assert_eq!((rp(Model::Dmg), rp(Model::Sgb), rp(Model::Cgb)), (0xFF, 0xFF, 0x3F));
+
+        // The SGB boot ROM does not play the chime.
+        let nr12 = |model: Model| Emulator::new(model, rom(0, 0, 0), EmulatorOptions::default()).unwrap().peek(0xFF12).unwrap();
+        assert_eq!((nr12(Model::Dmg), nr12(Model::Sgb2), nr12(Model::Cgb)), (0xF3, 0x00, 0xF3));
+    }
+}
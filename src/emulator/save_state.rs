@@ -0,0 +1,226 @@
+use crate::emulator::Emulator;
+use crate::savestate::{self, SaveStateError, StateFile};
+use crate::savestate::bess::{self, BessCore, BessState, IO_SIZE};
+
+const INTERRUPTS_TAG: [u8; 4] = *b"INTR";
+const TICKS_TAG: [u8; 4] = *b"TICK";
+const BOOT_ROM_TAG: [u8; 4] = *b"BOOT";
+const MODEL_TAG: [u8; 4] = *b"MODL";
+
+impl Emulator {
+    /// Serialises the whole machine, between two instructions.
+    pub fn save_state(&mut self) -> Result<Vec<u8>, SaveStateError> {
+        Ok(self.capture_state()?.to_bytes())
+    }
+
+    /// Restores a state made by `save_state`, or a BESS state from another emulator.
+    /// On error the machine is left as it was.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        if !savestate::is_native(data) && bess::has_footer(data) {
+            return self.import_bess(data);
+        }
+
+        let file = StateFile::from_bytes(data)?;
+        let backup = self.capture_state()?;
+
+        if let Err(e) = self.apply_state(&file) {
+            self.apply_state(&backup)?;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn capture_state(&mut self) -> Result<StateFile, SaveStateError> {
+        self.cpu.bus.sync();
+        let cpu = &self.cpu;
+        let bus = &cpu.bus;
+        let mut file = StateFile::new();
+        let model = self.model.bess_id();
+        file.add_raw(MODEL_TAG, 1, |writer| writer.bytes(&model));
+        file.add(cpu);
+        let int_flags = bus.io.int_flags.int_flags;
+        let ie_register = bus.ie_register.int_flags;
+        file.add_raw(INTERRUPTS_TAG, 1, |writer| {
+            writer.u8(int_flags);
+            writer.u8(ie_register);
+        });
+        file.add(&bus.ppu);
+        file.add(&bus.ppu.lcd);
+        file.add(&bus.io.timer);
+        file.add(&bus.dma);
+        file.add(&bus.io.joypad);
+        file.add(&bus.io.apu);
+        file.add(&bus.io.serial);
+        if let Some(sgb) = &bus.sgb {
+            file.add(sgb);
+        }
+        let boot_rom_mapped = bus.boot_rom.is_some();
+        file.add_raw(BOOT_ROM_TAG, 1, |writer| writer.bool(boot_rom_mapped));
+        bus.save_state(&mut file)?;
+        let ticks = bus.tm.get_ticks();
+        let frame_overshoot = self.frame_overshoot;
+        file.add_raw(TICKS_TAG, 1, |writer| {
+            writer.u64(ticks);
+            writer.u64(frame_overshoot);
+        });
+        Ok(file)
+    }
+
+    fn apply_state(&mut self, file: &StateFile) -> Result<(), SaveStateError> {
+        let mut model = [0; 4];
+        file.reader(MODEL_TAG, 1)?.bytes(&mut model)?;
+        if model != self.model.bess_id() {
+            return Err(SaveStateError::ModelMismatch);
+        }
+
+        let cpu = &mut self.cpu;
+        // The cartridge goes first: it rejects states made with another ROM.
+        cpu.bus.load_state(file)?;
+        file.load(cpu)?;
+
+        let bus = &mut cpu.bus;
+        let mut interrupts = file.reader(INTERRUPTS_TAG, 1)?;
+        bus.io.int_flags.int_flags = interrupts.u8()?;
+        bus.ie_register.int_flags = interrupts.u8()?;
+
+        file.load(&mut bus.ppu)?;
+        file.load(&mut bus.ppu.lcd)?;
+        file.load(&mut bus.io.timer)?;
+        file.load(&mut bus.dma)?;
+        file.load(&mut bus.io.joypad)?;
+        file.load(&mut bus.io.apu)?;
+        file.load(&mut bus.io.serial)?;
+        if let Some(sgb) = bus.sgb.as_mut() {
+            file.load(sgb)?;
+        }
+        let boot_rom_mapped = file.reader(BOOT_ROM_TAG, 1)?.bool()?;
+        if boot_rom_mapped && bus.boot_rom.is_none() {
+            return Err(SaveStateError::InvalidData("the state was saved while the boot ROM was running".to_string()));
+        }
+        if !boot_rom_mapped {
+            bus.boot_rom = None;
+        }
+
+        let mut ticks = file.reader(TICKS_TAG, 1)?;
+        bus.tm.set_ticks(ticks.u64()?);
+        self.frame_overshoot = ticks.u64()?;
+        Ok(())
+    }
+
+    /// Our own state followed by a BESS trailer, so other emulators can load it too.
+    pub fn export_bess(&mut self) -> Result<Vec<u8>, SaveStateError> {
+        let mut out = self.capture_state()?.to_bytes();
+        let state = self.capture_bess()?;
+        bess::append(&mut out, &state);
+        Ok(out)
+    }
+
+    /// Loads the BESS blocks of a state, ignoring whatever comes before them.
+    pub fn import_bess(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        let state = bess::parse(data)?;
+        if state.core.model[0] == b'C' {
+            return Err(SaveStateError::InvalidData("Game Boy Color states are not supported".to_string()));
+        }
+
+        let backup = self.capture_state()?;
+        if let Err(e) = self.apply_bess(&state) {
+            self.apply_state(&backup)?;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn capture_bess(&mut self) -> Result<BessState, SaveStateError> {
+        let mut io = [0; IO_SIZE];
+        for (offset, value) in io.iter_mut().enumerate() {
+            *value = self.cpu.bus.read(0xFF00 + offset as u16)?;
+        }
+
+        let cpu = &self.cpu;
+        let registers = &cpu.registers;
+        let execution_state = match (cpu.halted, cpu.stopped) {
+            (_, true) => bess::EXECUTION_STOPPED,
+            (true, _) => bess::EXECUTION_HALTED,
+            _ => bess::EXECUTION_RUNNING,
+        };
+        let ppu = &cpu.bus.ppu;
+        let mut state = BessState {
+            name: Some(format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))),
+            title: None,
+            global_checksum: None,
+            core: BessCore {
+                model: self.model.bess_id(),
+                pc: registers.pc,
+                af: u16::from_be_bytes([registers.a, registers.f]),
+                bc: u16::from_be_bytes([registers.b, registers.c]),
+                de: u16::from_be_bytes([registers.d, registers.e]),
+                hl: u16::from_be_bytes([registers.h, registers.l]),
+                sp: registers.sp,
+                ime: cpu.interrupt_master_enable,
+                ie: cpu.bus.ie_register.int_flags,
+                execution_state,
+                io,
+            },
+            wram: vec![],
+            vram: (0..0x2000).map(|address| ppu.vram_read(address)).collect(),
+            mbc_ram: vec![],
+            oam: (0..0xA0).map(|address| ppu.oam_read(address)).collect(),
+            hram: vec![],
+            mbc_writes: vec![],
+        };
+        cpu.bus.save_bess(&mut state)?;
+        Ok(state)
+    }
+
+    fn apply_bess(&mut self, state: &BessState) -> Result<(), SaveStateError> {
+        self.cpu.bus.load_bess(state)?;
+        let ppu = &mut self.cpu.bus.ppu;
+        for (address, &data) in state.vram.iter().take(0x2000).enumerate() {
+            ppu.vram_write(address as u16, data);
+        }
+        for (address, &data) in state.oam.iter().take(0xA0).enumerate() {
+            ppu.oam_write(address as u16, data);
+        }
+        self.apply_bess_io(&state.core.io)?;
+
+        let core = &state.core;
+        let cpu = &mut self.cpu;
+        let registers = &mut cpu.registers;
+        [registers.a, registers.f] = core.af.to_be_bytes();
+        registers.f &= 0xF0;
+        [registers.b, registers.c] = core.bc.to_be_bytes();
+        [registers.d, registers.e] = core.de.to_be_bytes();
+        [registers.h, registers.l] = core.hl.to_be_bytes();
+        registers.pc = core.pc;
+        registers.sp = core.sp;
+        cpu.interrupt_master_enable = core.ime;
+        cpu.enable_ime = false;
+        cpu.halted = core.execution_state == bess::EXECUTION_HALTED;
+        cpu.stopped = core.execution_state == bess::EXECUTION_STOPPED;
+        cpu.bus.ie_register.int_flags = core.ie;
+        Ok(())
+    }
+
+    /// Writes the IO registers through the bus, except where a write has side effects
+    /// that the saved value does not ask for.
+    fn apply_bess_io(&mut self, io: &[u8; IO_SIZE]) -> Result<(), SaveStateError> {
+        let bus = &mut self.cpu.bus;
+        // The writes below sync, which must not run the loaded timer.
+        bus.sync();
+        bus.io.timer.load_registers((io[0x04] as u16) << 8, io[0x05], io[0x06], io[0x07]);
+        // Sound registers ignore writes while the APU is off.
+        bus.write(0xFF26, io[0x26])?;
+
+        for (offset, &value) in io.iter().enumerate() {
+            let value = match offset {
+                // Timer, sound power and OAM DMA.
+                0x04..=0x07 | 0x26 | 0x46 => continue,
+                // Don't start a serial transfer or retrigger the sound channels.
+                0x02 | 0x14 | 0x19 | 0x1E | 0x23 => value & 0x7F,
+                _ => value,
+            };
+            bus.write(0xFF00 + offset as u16, value)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,285 @@
+/*
+    GBS file layout:
+    0x00 - 0x02: Identifier "GBS"
+    0x03 - 0x03: Version (1)
+    0x04 - 0x04: Number of songs
+    0x05 - 0x05: First song (1 based)
+    0x06 - 0x07: Load address
+    0x08 - 0x09: Init address
+    0x0A - 0x0B: Play address
+    0x0C - 0x0D: Stack pointer
+    0x0E - 0x0E: Timer modulo
+    0x0F - 0x0F: Timer control
+    0x10 - 0x2F: Title
+    0x30 - 0x4F: Author
+    0x50 - 0x6F: Copyright
+    0x70 - ....: Code and data, loaded at the load address
+*/
+
+use crate::apu::ApuError;
+use crate::bus::BusError;
+use crate::cpu::error::CpuError;
+
+const GBS_HEADER_SIZE: usize = 0x70;
+const BANK_SIZE: usize = 0x4000;
+
+const DRIVER_ADDRESS: u16 = 0x0150;
+const CART_TYPE_MBC5_RAM: u8 = 0x1A;
+const RAM_SIZE_8KB: u8 = 0x02;
+
+const TAC_ENABLE: u8 = 0x04;
+const IE_VBLANK: u8 = 0x01;
+const IE_TIMER: u8 = 0x04;
+
+#[derive(Debug)]
+pub enum GbsError {
+    InvalidHeader,
+    InvalidMagic,
+    UnsupportedVersion(u8),
+    InvalidLoadAddress(u16),
+    NoSongs,
+    InvalidFirstSong(u8),
+    NoGbsLoaded,
+    InvalidTrack(u8),
+    BusError(BusError),
+    CpuError(CpuError),
+    ApuError(ApuError),
+    IoError(std::io::Error),
+}
+
+impl From<BusError> for GbsError {
+    fn from(e: BusError) -> GbsError {
+        GbsError::BusError(e)
+    }
+}
+
+impl From<CpuError> for GbsError {
+    fn from(e: CpuError) -> GbsError {
+        GbsError::CpuError(e)
+    }
+}
+
+impl From<ApuError> for GbsError {
+    fn from(e: ApuError) -> GbsError {
+        GbsError::ApuError(e)
+    }
+}
+
+impl From<std::io::Error> for GbsError {
+    fn from(e: std::io::Error) -> GbsError {
+        GbsError::IoError(e)
+    }
+}
+
+pub struct GbsHeader {
+    pub version: u8,
+    pub song_count: u8,
+    pub first_song: u8,
+    pub load_address: u16,
+    pub init_address: u16,
+    pub play_address: u16,
+    pub stack_pointer: u16,
+    pub timer_modulo: u8,
+    pub timer_control: u8,
+    pub title: String,
+    pub author: String,
+    pub copyright: String,
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_string(data: &[u8]) -> String {
+    let end = data.iter().position(|&c| c == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).trim().to_string()
+}
+
+impl GbsHeader {
+    pub fn from_bytes(data: &[u8]) -> Result<GbsHeader, GbsError> {
+        if data.len() < GBS_HEADER_SIZE {
+            return Err(GbsError::InvalidHeader);
+        }
+
+        if &data[0..3] != b"GBS" {
+            return Err(GbsError::InvalidMagic);
+        }
+
+        let header = GbsHeader {
+            version: data[0x03],
+            song_count: data[0x04],
+            first_song: data[0x05],
+            load_address: read_u16(data, 0x06),
+            init_address: read_u16(data, 0x08),
+            play_address: read_u16(data, 0x0A),
+            stack_pointer: read_u16(data, 0x0C),
+            timer_modulo: data[0x0E],
+            timer_control: data[0x0F],
+            title: read_string(&data[0x10..0x30]),
+            author: read_string(&data[0x30..0x50]),
+            copyright: read_string(&data[0x50..0x70]),
+        };
+
+        if header.version != 1 {
+            return Err(GbsError::UnsupportedVersion(header.version));
+        }
+
+        if header.load_address < 0x0400 || header.load_address >= 0x8000 {
+            return Err(GbsError::InvalidLoadAddress(header.load_address));
+        }
+
+        if header.song_count == 0 {
+            return Err(GbsError::NoSongs);
+        }
+
+        if header.first_song == 0 || header.first_song > header.song_count {
+            return Err(GbsError::InvalidFirstSong(header.first_song));
+        }
+
+        Ok(header)
+    }
+
+    /// Songs are driven by the timer interrupt when TAC enables the timer, by VBlank otherwise.
+    pub fn uses_timer(&self) -> bool {
+        (self.timer_control & TAC_ENABLE) != 0
+    }
+}
+
+pub struct Gbs {
+    pub header: GbsHeader,
+    pub data: Vec<u8>,
+}
+
+impl Gbs {
+    pub fn new(content: Vec<u8>) -> Result<Gbs, GbsError> {
+        let header = GbsHeader::from_bytes(&content)?;
+        let data = content[GBS_HEADER_SIZE..].to_vec();
+        Ok(Gbs { header, data })
+    }
+
+    /// Builds a synthetic MBC5 cartridge with the GBS data at its load address and
+    /// a small driver that calls `init` for `song` (0 based) and then waits in HALT,
+    /// calling `play` from the VBlank or timer interrupt vector.
+    pub fn build_rom(&self, song: u8) -> Vec<u8> {
+        let load = self.header.load_address as usize;
+        let size = (load + self.data.len()).div_ceil(BANK_SIZE).max(2) * BANK_SIZE;
+        let mut rom = vec![0xFF; size];
+        rom[load..load + self.data.len()].copy_from_slice(&self.data);
+
+        // RST vectors are relocated to the load address.
+        for rst in (0x00..=0x38).step_by(8) {
+            Self::emit_jp(&mut rom, rst, (load + rst) as u16);
+        }
+
+        let play = self.header.play_address.to_le_bytes();
+        let vector = match self.header.uses_timer() {
+            true => 0x50,
+            false => 0x40,
+        };
+        rom[vector..vector + 4].copy_from_slice(&[0xCD, play[0], play[1], 0xD9]);
+
+        // Entry point and cartridge header.
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, DRIVER_ADDRESS as u8, (DRIVER_ADDRESS >> 8) as u8]);
+        rom[0x104..0x150].fill(0);
+        let title = self.header.title.as_bytes();
+        let title_len = title.len().min(16);
+        rom[0x134..0x134 + title_len].copy_from_slice(&title[..title_len]);
+        rom[0x147] = CART_TYPE_MBC5_RAM;
+        rom[0x149] = RAM_SIZE_8KB;
+
+        let sp = self.header.stack_pointer.to_le_bytes();
+        let init = self.header.init_address.to_le_bytes();
+        let ie = match self.header.uses_timer() {
+            true => IE_TIMER,
+            false => IE_VBLANK,
+        };
+
+        let driver: [u8; 28] = [
+            0xF3,                               // DI
+            0x31, sp[0], sp[1],                 // LD SP, sp
+            0x3E, self.header.timer_modulo,     // LD A, tma
+            0xE0, 0x06,                         // LDH (TMA), A
+            0x3E, self.header.timer_control,    // LD A, tac
+            0xE0, 0x07,                         // LDH (TAC), A
+            0x3E, ie,                           // LD A, ie
+            0xE0, 0xFF,                         // LDH (IE), A
+            0x3E, song,                         // LD A, song
+            0xCD, init[0], init[1],             // CALL init
+            0xAF,                               // XOR A
+            0xE0, 0x0F,                         // LDH (IF), A
+            0xFB,                               // EI
+            0x76,                               // HALT
+            0x18, 0xFD,                         // JR -3
+        ];
+        let start = DRIVER_ADDRESS as usize;
+        rom[start..start + driver.len()].copy_from_slice(&driver);
+
+        rom
+    }
+
+    fn emit_jp(rom: &mut [u8], at: usize, target: u16) {
+        rom[at] = 0xC3;
+        rom[at + 1] = target as u8;
+        rom[at + 2] = (target >> 8) as u8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gbs_file(load: u16, timer_control: u8) -> Vec<u8> {
+        let mut file = vec![0; GBS_HEADER_SIZE + 4];
+        file[0..3].copy_from_slice(b"GBS");
+        file[0x03] = 1;
+        file[0x04] = 12;
+        file[0x05] = 1;
+        file[0x06..0x08].copy_from_slice(&load.to_le_bytes());
+        file[0x08..0x0A].copy_from_slice(&0x0500u16.to_le_bytes());
+        file[0x0A..0x0C].copy_from_slice(&0x0600u16.to_le_bytes());
+        file[0x0C..0x0E].copy_from_slice(&0xDFFFu16.to_le_bytes());
+        file[0x0F] = timer_control;
+        file[0x10..0x15].copy_from_slice(b"SONGS");
+        file[GBS_HEADER_SIZE..].copy_from_slice(&[0xDE, 0xAD, 0xBE, 0xEF]);
+        file
+    }
+
+    #[test]
+    fn test_parse_header() {
+        let gbs = Gbs::new(gbs_file(0x0400, 0)).unwrap();
+        assert_eq!(gbs.header.song_count, 12);
+        assert_eq!(gbs.header.init_address, 0x0500);
+        assert_eq!(gbs.header.play_address, 0x0600);
+        assert_eq!(gbs.header.stack_pointer, 0xDFFF);
+        assert_eq!(gbs.header.title, "SONGS".to_string());
+        assert!(!gbs.header.uses_timer());
+    }
+
+    #[test]
+    fn test_invalid_magic() {
+        let mut file = gbs_file(0x0400, 0);
+        file[0] = b'X';
+        assert!(matches!(Gbs::new(file), Err(GbsError::InvalidMagic)));
+    }
+
+    #[test]
+    fn test_invalid_song_numbers() {
+        let mut file = gbs_file(0x0400, 0);
+        file[0x05] = 13;
+        assert!(matches!(Gbs::new(file.clone()), Err(GbsError::InvalidFirstSong(13))));
+        file[0x05] = 0;
+        assert!(matches!(Gbs::new(file.clone()), Err(GbsError::InvalidFirstSong(0))));
+        file[0x04] = 0;
+        assert!(matches!(Gbs::new(file), Err(GbsError::NoSongs)));
+    }
+
+    #[test]
+    fn test_build_rom_maps_data_and_vectors() {
+        let gbs = Gbs::new(gbs_file(0x0400, 0x04)).unwrap();
+        let rom = gbs.build_rom(3);
+        assert_eq!(&rom[0x400..0x404], &[0xDE, 0xAD, 0xBE, 0xEF]);
+        assert_eq!(&rom[0x50..0x54], &[0xCD, 0x00, 0x06, 0xD9]);
+        assert_eq!(&rom[0x08..0x0B], &[0xC3, 0x08, 0x04]);
+        assert_eq!(rom[0x147], CART_TYPE_MBC5_RAM);
+    }
+}
@@ -1,5 +1,3 @@
-use sdl2::pixels;
-
 #[derive(Debug, Clone, Copy)]
 pub struct Color {
     pub r: u8,
@@ -31,13 +29,19 @@ impl Color {
         }
     }
 
+    pub fn to_argb(self) -> u32 {
+        0xFF_00_00_00 | (self.r as u32) << 16 | (self.g as u32) << 8 | self.b as u32
+    }
+
+    #[cfg(feature = "sdl")]
     pub fn to_sdl_color(&self) -> sdl2::pixels::Color {
         sdl2::pixels::Color::RGB(self.r, self.g, self.b)
     }
 }
 
-impl Into<pixels::Color> for Color {
-    fn into(self) -> pixels::Color {
+#[cfg(feature = "sdl")]
+impl Into<sdl2::pixels::Color> for Color {
+    fn into(self) -> sdl2::pixels::Color {
         self.to_sdl_color()
     }
 }
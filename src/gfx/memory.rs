@@ -0,0 +1,77 @@
+use crate::gfx::color::Color;
+use crate::gfx::{Gfx, GfxError, UserEvents};
+
+/// Draws into a pixel buffer instead of a window, for runs without a display.
+pub struct MemoryGfx {
+    pub width: u32,
+    pub height: u32,
+    /// 0xAARRGGBB, row by row.
+    pub pixels: Vec<u32>,
+}
+
+impl MemoryGfx {
+    pub fn new(width: u32, height: u32) -> MemoryGfx {
+        MemoryGfx {
+            width,
+            height,
+            pixels: vec![0xFF_00_00_00; (width * height) as usize],
+        }
+    }
+}
+
+impl Gfx for MemoryGfx {
+    fn init(&self) {}
+
+    fn present(&mut self) {}
+
+    fn clear(&mut self, color: Color) {
+        self.pixels.fill(color.to_argb());
+    }
+
+    fn draw_pixel(&mut self, x: i32, y: i32, color: Color) -> Result<(), GfxError> {
+        if x < 0 || y < 0 || x as u32 >= self.width || y as u32 >= self.height {
+            return Err(GfxError::DrawError(format!("Pixel {},{} is off screen", x, y)));
+        }
+
+        self.pixels[(y as u32 * self.width + x as u32) as usize] = color.to_argb();
+        Ok(())
+    }
+
+    fn draw_frame(&mut self, pixels: &[u32], width: u32, height: u32) -> Result<(), GfxError> {
+        if pixels.len() < (width * height) as usize {
+            return Err(GfxError::DrawError("Frame is smaller than its size".to_string()));
+        }
+
+        for y in 0..self.height {
+            let row = (y * height / self.height * width) as usize;
+            for x in 0..self.width {
+                self.pixels[(y * self.width + x) as usize] = pixels[row + (x * width / self.width) as usize];
+            }
+        }
+        Ok(())
+    }
+
+    fn get_user_events(&mut self) -> Vec<UserEvents> {
+        Vec::new()
+    }
+
+    fn get_ticks(&self) -> Result<u32, String> {
+        Err("No clock without a display".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_draw_frame_scales_nearest() {
+        let mut gfx = MemoryGfx::new(4, 2);
+        gfx.draw_frame(&[1, 2], 2, 1).unwrap();
+        assert_eq!(gfx.pixels, vec![1, 1, 2, 2, 1, 1, 2, 2]);
+
+        gfx.draw_pixel(3, 1, Color::new(0x12, 0x34, 0x56)).unwrap();
+        assert_eq!(gfx.pixels[7], 0xFF_12_34_56);
+        assert!(gfx.draw_pixel(4, 0, Color::new(0, 0, 0)).is_err());
+    }
+}
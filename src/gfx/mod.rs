@@ -1,16 +1,22 @@
 use crate::gfx::color::Color;
 
 pub(crate) mod color;
+pub(crate) mod font;
+pub(crate) mod memory;
+#[cfg(feature = "sdl")]
 pub(crate) mod sdl;
 
 #[derive(Debug)]
+#[cfg_attr(not(feature = "sdl"), allow(dead_code))]
 pub enum UserEvents {
     Unknown,
     Quit,
     KeyPressed(String),
+    KeyReleased(String),
 }
 
 #[derive(Debug)]
+#[cfg_attr(not(feature = "sdl"), allow(dead_code))]
 pub enum GfxError {
     InitError(String),
     DrawError(String),
@@ -21,6 +27,8 @@ pub trait Gfx {
     fn present(&mut self) -> ();
     fn clear(&mut self, color: Color) -> ();
     fn draw_pixel(&mut self, x: i32, y: i32, color: Color) -> Result<(), GfxError>;
+    /// Stretches a `width` x `height` buffer of 0xAARRGGBB pixels over the whole output.
+    fn draw_frame(&mut self, pixels: &[u32], width: u32, height: u32) -> Result<(), GfxError>;
     fn get_user_events(&mut self) -> Vec<UserEvents>;
     fn get_ticks(&self) -> Result<u32, String>;
 }
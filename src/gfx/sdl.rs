@@ -8,7 +8,7 @@ pub struct SDL {
 }
 
 impl SDL {
-    pub fn new(w: u32, h: u32, isDebug: bool) -> Result<SDL, GfxError> {
+    pub fn new(w: u32, h: u32, isDebug: bool, fullscreen: bool) -> Result<SDL, GfxError> {
         let sdl_context = match sdl2::init() {
             Ok(sdl_context) => sdl_context,
             Err(e) => return Err(GfxError::InitError(e.to_string())),
@@ -24,10 +24,12 @@ impl SDL {
             false => "gba-rs",
         };
 
-        let window_result = video_subsystem
-            .window(window_name, w, h)
-            .position_centered()
-            .build();
+        let mut window_builder = video_subsystem.window(window_name, w, h);
+        window_builder.position_centered();
+        if fullscreen {
+            window_builder.fullscreen_desktop();
+        }
+        let window_result = window_builder.build();
 
         let window = match window_result {
             Ok(window) => window,
@@ -77,6 +79,18 @@ impl Gfx for SDL {
         };
     }
 
+    fn draw_frame(&mut self, pixels: &[u32], width: u32, height: u32) -> Result<(), GfxError> {
+        let texture_creator = self.canvas.texture_creator();
+        let mut texture = texture_creator
+            .create_texture_streaming(sdl2::pixels::PixelFormatEnum::ARGB8888, width, height)
+            .map_err(|e| GfxError::DrawError(e.to_string()))?;
+        let bytes: Vec<u8> = pixels.iter().flat_map(|pixel| pixel.to_ne_bytes()).collect();
+        texture
+            .update(None, &bytes, width as usize * 4)
+            .map_err(|e| GfxError::DrawError(e.to_string()))?;
+        self.canvas.copy(&texture, None, None).map_err(GfxError::DrawError)
+    }
+
     fn get_user_events(&mut self) -> Vec<UserEvents> {
         self.event_pump.as_mut().unwrap()
             .poll_iter()
@@ -86,6 +100,10 @@ impl Gfx for SDL {
                     keycode: Some(keycode),
                     ..
                 } => UserEvents::KeyPressed(keycode.name().to_string()),
+                sdl2::event::Event::KeyUp {
+                    keycode: Some(keycode),
+                    ..
+                } => UserEvents::KeyReleased(keycode.name().to_string()),
                 _ => UserEvents::Unknown,
             })
             .collect()
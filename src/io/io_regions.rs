@@ -30,6 +30,7 @@
     0x24 - 0x24: Master Volume Control
     0x25 - 0x25: Sound Panning
     0x26 - 0x26: Sound On/Off
+    0x30 - 0x3F: Wave Pattern RAM
     0x40 - 0x40: LCD Control
     0x41 - 0x41: LCD Status
     0x42 - 0x42: Viewport Y
@@ -93,6 +94,7 @@ pub enum IoRegions {
     MasterVolumeControl,
     SoundPanning,
     SoundOnOff,
+    WavePattern,
     Lcd,
     SpeedSwitch,
     VRAMBank,
@@ -145,6 +147,7 @@ impl IoRegions {
             0x24 => Ok(IoRegions::MasterVolumeControl),
             0x25 => Ok(IoRegions::SoundPanning),
             0x26 => Ok(IoRegions::SoundOnOff),
+            0x30..=0x3F => Ok(IoRegions::WavePattern),
             0x40..=0x4B => Ok(IoRegions::Lcd),
             0x4D => Ok(IoRegions::SpeedSwitch),
             0x4F => Ok(IoRegions::VRAMBank),
@@ -166,4 +169,32 @@ impl IoRegions {
             _ => Ok(IoRegions::PCM34),
         }
     }
+
+    pub fn is_sound(&self) -> bool {
+        matches!(
+            self,
+            IoRegions::SoundMode1Sweep
+                | IoRegions::SoundMode1LengthWave
+                | IoRegions::SoundMode1Volume
+                | IoRegions::SoundMode1PeriodLow
+                | IoRegions::SoundMode1PeriodHigh
+                | IoRegions::SoundMode2LengthWave
+                | IoRegions::SoundMode2Volume
+                | IoRegions::SoundMode2PeriodLow
+                | IoRegions::SoundMode2PeriodHigh
+                | IoRegions::SoundMode3DACEnable
+                | IoRegions::SoundMode3Length
+                | IoRegions::SoundMode3OutputLevel
+                | IoRegions::SoundMode3PeriodLow
+                | IoRegions::SoundMode3PeriodHigh
+                | IoRegions::SoundMode4LengthTimer
+                | IoRegions::SoundMode4Volume
+                | IoRegions::SoundMode4FrequencyRandomness
+                | IoRegions::SoundMode4Control
+                | IoRegions::MasterVolumeControl
+                | IoRegions::SoundPanning
+                | IoRegions::SoundOnOff
+                | IoRegions::WavePattern
+        )
+    }
 }
\ No newline at end of file
@@ -1,13 +1,14 @@
-use std::sync::{Arc, Mutex};
+use crate::apu::APU;
 use crate::cpu::interrupts::IFlagsRegister;
 use crate::dma::DMA;
-use crate::emu::GlobalContext;
 use crate::io::io_regions::IoRegions;
-use crate::lcd::LCD;
+use crate::joypad::Joypad;
+use crate::serial::Serial;
 use crate::timer::Timer;
 
 mod io_regions;
-mod serial;
+
+const RP_ADDRESS: u8 = 0x56;
 
 #[derive(Debug)]
 #[allow(dead_code)]
@@ -15,43 +16,50 @@ pub enum IoError {
     InvalidAddress,
 }
 
+/// The devices behind the IO registers, except the LCD registers which the PPU owns.
 pub struct IO {
-    pub serial_data: u8,
-    pub serial_control: u8,
-    pub serial_message: String,
-    pub timer: Arc<Mutex<Timer>>,
-    pub int_flags: Arc<Mutex<IFlagsRegister>>,
-    pub lcd: Arc<Mutex<LCD>>,
+    pub serial: Serial,
+    pub joypad: Joypad,
+    pub timer: Timer,
+    pub int_flags: IFlagsRegister,
+    pub apu: APU,
 }
 
 impl IO {
-    pub fn new(global: GlobalContext) -> IO {
+    pub fn new() -> IO {
         IO {
-            int_flags: global.int_flags.clone(),
-            serial_data: 0,
-            serial_control: 0,
-            serial_message: String::new(),
-            timer: global.timer.clone(),
-            lcd: global.lcd.unwrap(),
+            int_flags: IFlagsRegister::new(),
+            serial: Serial::new(),
+            joypad: Joypad::new(),
+            timer: Timer::new(),
+            apu: APU::new(),
         }
     }
 
+    pub fn set_buttons(&mut self, buttons: u8) {
+        self.joypad.set_buttons(buttons, &mut self.int_flags);
+    }
+
     pub fn read(&mut self, address: u8) -> Result<u8, IoError> {
         let io_region = IoRegions::from_u8_address(address)?;
         match io_region {
-            IoRegions::SerialTransferData => Ok(self.serial_data),
-            IoRegions::SerialTransferControl => Ok(self.serial_control),
+            IoRegions::JoyPad => Ok(self.joypad.read()),
+            IoRegions::SerialTransferData => Ok(self.serial.get_sb()),
+            IoRegions::SerialTransferControl => Ok(self.serial.get_sc()),
             IoRegions::DividerRegister => {
-                let ticks = self.timer.lock().unwrap().get_divider();
+                let ticks = self.timer.get_divider();
                 Ok((ticks >> 8) as u8)
             },
-            IoRegions::TimerCounter => Ok(self.timer.lock().unwrap().get_tima()),
-            IoRegions::TimerModulo => Ok(self.timer.lock().unwrap().get_tma()),
-            IoRegions::TimerControl => Ok(self.timer.lock().unwrap().get_tac()),
-            IoRegions::InterruptFlags => Ok(self.int_flags.lock().unwrap().int_flags),
-            IoRegions::Lcd => {
-                Ok(self.lcd.lock().unwrap().lcd_read(address as u16))
+            IoRegions::TimerCounter => Ok(self.timer.get_tima()),
+            IoRegions::TimerModulo => Ok(self.timer.get_tma()),
+            IoRegions::TimerControl => Ok(self.timer.get_tac()),
+            IoRegions::InterruptFlags => Ok(self.int_flags.int_flags),
+            // Only CGB models have the infrared port.
+            IoRegions::RP if address == RP_ADDRESS => match self.serial.model.is_cgb() {
+                true => Ok(self.serial.infrared.get_rp()),
+                false => Ok(0xFF),
             },
+            region if region.is_sound() => Ok(self.apu.read(address)),
             _ => Ok(0),
         }
 
@@ -60,37 +68,44 @@ impl IO {
     pub fn write(&mut self, address: u8, data: u8) -> Result<(), IoError>{
         let io_region = IoRegions::from_u8_address(address)?;
         match io_region {
+            IoRegions::JoyPad => {
+                self.joypad.write(data, &mut self.int_flags);
+                Ok(())
+            },
             IoRegions::SerialTransferData => {
-                self.serial_data = data;
+                self.serial.set_sb(data);
                 Ok(())
             },
             IoRegions::SerialTransferControl => {
-                self.serial_control = data;
-                self.on_serial_update();
+                self.serial.set_sc(data);
                 Ok(())
             },
             IoRegions::DividerRegister => {
-                self.timer.lock().unwrap().clear_divider();
+                self.timer.clear_divider();
                 Ok(())
             },
             IoRegions::TimerCounter => {
-                self.timer.lock().unwrap().set_tima(data);
+                self.timer.set_tima(data);
                 Ok(())
             },
             IoRegions::TimerModulo => {
-                self.timer.lock().unwrap().set_tma(data);
+                self.timer.set_tma(data);
                 Ok(())
             },
             IoRegions::TimerControl => {
-                self.timer.lock().unwrap().set_tac(data);
+                self.timer.set_tac(data);
                 Ok(())
             },
             IoRegions::InterruptFlags => {
-                self.int_flags.lock().unwrap().int_flags = data;
+                self.int_flags.int_flags = data;
+                Ok(())
+            },
+            IoRegions::RP if address == RP_ADDRESS && self.serial.model.is_cgb() => {
+                self.serial.infrared.set_rp(data);
                 Ok(())
             },
-            IoRegions::Lcd => {
-                self.lcd.lock().unwrap().lcd_write(address as u16, data);
+            region if region.is_sound() => {
+                self.apu.write(address, data);
                 Ok(())
             },
 
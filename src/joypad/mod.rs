@@ -0,0 +1,133 @@
+/*
+    P1/JOYP (0xFF00):
+    Bit 5: 0 selects the action buttons
+    Bit 4: 0 selects the direction buttons
+    Bit 3: Start / Down
+    Bit 2: Select / Up
+    Bit 1: B / Left
+    Bit 0: A / Right
+    Bits 3-0 read 0 while a button of a selected group is pressed.
+*/
+
+use crate::cpu::interrupts::{IFlagsRegister, InterruptType};
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+
+pub const BUTTON_A: u8 = 1 << 0;
+pub const BUTTON_B: u8 = 1 << 1;
+pub const BUTTON_SELECT: u8 = 1 << 2;
+pub const BUTTON_START: u8 = 1 << 3;
+pub const BUTTON_RIGHT: u8 = 1 << 4;
+pub const BUTTON_LEFT: u8 = 1 << 5;
+pub const BUTTON_UP: u8 = 1 << 6;
+pub const BUTTON_DOWN: u8 = 1 << 7;
+
+const SELECT_ACTION: u8 = 1 << 5;
+const SELECT_DIRECTION: u8 = 1 << 4;
+
+pub struct Joypad {
+    select: u8,
+    /// Pressed buttons, one `BUTTON_*` bit each.
+    buttons: u8,
+}
+
+impl Joypad {
+    pub fn new() -> Joypad {
+        Joypad {
+            select: SELECT_ACTION | SELECT_DIRECTION,
+            buttons: 0,
+        }
+    }
+
+    /// Keyboard key bound to each button.
+    pub fn button_for_key(key: &str) -> Option<u8> {
+        match key {
+            "X" => Some(BUTTON_A),
+            "Z" => Some(BUTTON_B),
+            "Right Shift" => Some(BUTTON_SELECT),
+            "Return" => Some(BUTTON_START),
+            "Right" => Some(BUTTON_RIGHT),
+            "Left" => Some(BUTTON_LEFT),
+            "Up" => Some(BUTTON_UP),
+            "Down" => Some(BUTTON_DOWN),
+            _ => None,
+        }
+    }
+
+    /// Low nibble of P1, active low.
+    fn lines(&self) -> u8 {
+        let mut pressed = 0;
+        if (self.select & SELECT_ACTION) == 0 {
+            pressed |= self.buttons & 0x0F;
+        }
+        if (self.select & SELECT_DIRECTION) == 0 {
+            pressed |= self.buttons >> 4;
+        }
+        !pressed & 0x0F
+    }
+
+    pub fn read(&self) -> u8 {
+        0xC0 | self.select | self.lines()
+    }
+
+    pub fn write(&mut self, data: u8, int_flags: &mut IFlagsRegister) {
+        self.update(int_flags, |joypad| joypad.select = data & (SELECT_ACTION | SELECT_DIRECTION));
+    }
+
+    pub fn set_buttons(&mut self, buttons: u8, int_flags: &mut IFlagsRegister) {
+        self.update(int_flags, |joypad| joypad.buttons = buttons);
+    }
+
+    /// Applies `change` and requests the interrupt when a line goes from high to low.
+    fn update(&mut self, int_flags: &mut IFlagsRegister, change: impl FnOnce(&mut Joypad)) {
+        let before = self.lines();
+        change(self);
+        if (before & !self.lines()) != 0 {
+            int_flags.add_interrupt(InterruptType::JoyPad);
+        }
+    }
+}
+
+impl SaveState for Joypad {
+    const TAG: [u8; 4] = *b"JOYP";
+    const VERSION: u16 = 1;
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.u8(self.select);
+        writer.u8(self.buttons);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.select = reader.u8()?;
+        self.buttons = reader.u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selected_group_and_interrupt() {
+        let mut int_flags = IFlagsRegister::new();
+        let mut joypad = Joypad::new();
+        assert_eq!(joypad.read(), 0xFF);
+
+        // Nothing selected: pressing doesn't show up or interrupt.
+        joypad.set_buttons(BUTTON_START, &mut int_flags);
+        assert_eq!(joypad.read(), 0xFF);
+        assert_eq!(int_flags.int_flags, 0);
+
+        joypad.write(SELECT_DIRECTION, &mut int_flags);
+        assert_eq!(joypad.read(), 0xD7);
+        assert_eq!(int_flags.int_flags, 0x10);
+
+        int_flags.int_flags = 0;
+        joypad.set_buttons(BUTTON_START | BUTTON_DOWN, &mut int_flags);
+        assert_eq!(joypad.read(), 0xD7);
+        assert_eq!(int_flags.int_flags, 0);
+
+        joypad.write(SELECT_ACTION, &mut int_flags);
+        assert_eq!(joypad.read(), 0xE7);
+    }
+}
@@ -1,5 +1,7 @@
-use std::sync::{Arc, Mutex};
-use crate::dma::DMA;
+pub mod palette;
+
+use crate::lcd::palette::Palette;
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
 use crate::util;
 
 pub enum LCDMode {
@@ -70,18 +72,12 @@ pub struct LcdRegisters {
 
 pub struct LCD {
     pub register: LcdRegisters,
-    pub dma: Arc<Mutex<DMA>>,
+    /// Colours of the shades the palette registers select.
+    palette: Palette,
 }
 
-const colors_default: [u32; 4] = [
-    0xFF_FF_FF_FF,
-    0xFF_AA_AA_AA,
-    0xFF_55_55_55,
-    0xFF_00_00_00,
-];
-
 impl LCD {
-    pub fn new(dma: Arc<Mutex<DMA>>) -> LCD {
+    pub fn new() -> LCD {
         let reg = LcdRegisters {
             lcdc: 0x91,
             lcds: 0,
@@ -94,14 +90,14 @@ impl LCD {
             obj_palette: [0xFF, 0xFF],
             wy: 0,
             wx: 0,
-            bg_colors: colors_default.clone(),
-            sp1_colors: colors_default.clone(),
-            sp2_colors: colors_default.clone(),
+            bg_colors: Palette::DEFAULT.bg,
+            sp1_colors: Palette::DEFAULT.obj0,
+            sp2_colors: Palette::DEFAULT.obj1,
         };
 
         LCD {
             register: reg,
-            dma,
+            palette: Palette::DEFAULT,
         }
     }
 
@@ -169,63 +165,52 @@ impl LCD {
         self.register.lcds & interrupt.to_u8()
     }
 
-    pub fn lcd_read(&self, mut address: u16) -> u8 {
+    /// Offset of an LCD register from 0xFF40, `address` is either absolute or from 0xFF00.
+    fn register_offset(address: u16) -> u16 {
+        (address & 0xFF).wrapping_sub(0x40) & 0xFF
+    }
+
+    pub fn lcd_read(&self, address: u16) -> u8 {
+        let address = Self::register_offset(address);
         unsafe {
             let lcd_buff = std::slice::from_raw_parts(&self.register as *const LcdRegisters as *const u8, std::mem::size_of::<LcdRegisters>());
-            if address >= lcd_buff.len() as u16 {
-                address -= 0xFF40;
-            }
-
-            if address >= 0x40 {
-                address -= 0x40;
-            }
-
             lcd_buff[address as usize]
         }
     }
 
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    /// Swaps the colours and recomputes the colour tables from the palette registers.
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+        self.update_palette(self.register.bg_palette, 0);
+        self.update_palette(self.register.obj_palette[0] & !0b11, 1);
+        self.update_palette(self.register.obj_palette[1] & !0b11, 2);
+    }
+
     pub fn update_palette(&mut self, data: u8, pal: u8) {
-        match pal {
-            0 => {
-                self.register.bg_colors[0] = colors_default[(data & 0b11) as usize];
-                self.register.bg_colors[1] = colors_default[((data >> 2) & 0b11) as usize];
-                self.register.bg_colors[2] = colors_default[((data >> 4) & 0b11) as usize];
-                self.register.bg_colors[3] = colors_default[((data >> 6) & 0b11) as usize];
-            }
-            1 => {
-                self.register.sp1_colors[0] = colors_default[(data & 0b11) as usize];
-                self.register.sp1_colors[1] = colors_default[((data >> 2) & 0b11) as usize];
-                self.register.sp1_colors[2] = colors_default[((data >> 4) & 0b11) as usize];
-                self.register.sp1_colors[3] = colors_default[((data >> 6) & 0b11) as usize];
-            }
-            2 => {
-                self.register.sp2_colors[0] = colors_default[(data & 0b11) as usize];
-                self.register.sp2_colors[1] = colors_default[((data >> 2) & 0b11) as usize];
-                self.register.sp2_colors[2] = colors_default[((data >> 4) & 0b11) as usize];
-                self.register.sp2_colors[3] = colors_default[((data >> 6) & 0b11) as usize];
-            }
-            _ => {}
+        let (colors, table) = match pal {
+            0 => (&self.palette.bg, &mut self.register.bg_colors),
+            1 => (&self.palette.obj0, &mut self.register.sp1_colors),
+            2 => (&self.palette.obj1, &mut self.register.sp2_colors),
+            _ => return,
+        };
+        for (shade, color) in table.iter_mut().enumerate() {
+            *color = colors[((data >> (shade * 2)) & 0b11) as usize];
         }
     }
 
-    pub fn lcd_write(&mut self, mut address: u16, data: u8) {
+    pub fn lcd_write(&mut self, address: u16, data: u8) {
+        let address = Self::register_offset(address);
         unsafe {
             let mut lcd_buff = std::slice::from_raw_parts_mut(&self.register as *const LcdRegisters as *mut u8, std::mem::size_of::<LcdRegisters>());
-            if address >= lcd_buff.len() as u16 {
-                address -= 0xFF40;
-            }
-
-            if address >= 0x40 {
-                address -= 0x40;
-            }
-
             lcd_buff[address as usize] = data;
         }
 
+        // 0x06 starts the OAM DMA, the bus takes care of it.
         match address {
-            0x06 => {
-                self.dma.lock().unwrap().dma_start(data);
-            }
             0x07 => {
                 self.update_palette(data, 0);
             }
@@ -240,5 +225,27 @@ impl LCD {
     }
 }
 
+impl SaveState for LCD {
+    const TAG: [u8; 4] = *b"LCD ";
+    const VERSION: u16 = 1;
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        let r = &self.register;
+        writer.bytes(&[
+            r.lcdc, r.lcds, r.scroll_y, r.scroll_x, r.ly, r.ly_compare,
+            r.dma, r.bg_palette, r.obj_palette[0], r.obj_palette[1], r.wy, r.wx,
+        ]);
+    }
 
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        let mut regs = [0; 12];
+        reader.bytes(&mut regs)?;
+        let r = &mut self.register;
+        [r.lcdc, r.lcds, r.scroll_y, r.scroll_x, r.ly, r.ly_compare] = regs[0..6].try_into().unwrap();
+        [r.dma, r.bg_palette, r.obj_palette[0], r.obj_palette[1], r.wy, r.wx] = regs[6..12].try_into().unwrap();
 
+        // The colour tables are derived from the palette registers.
+        self.set_palette(self.palette);
+        Ok(())
+    }
+}
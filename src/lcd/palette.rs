@@ -0,0 +1,234 @@
+/*
+    Colours the four DMG shades are shown with, one set each for the background and the
+    two object palettes. Shades go from lightest (0) to darkest (3), colours are
+    0xAARRGGBB.
+
+    On a CGB the boot ROM picks the set for DMG cartridges: Nintendo titles are looked
+    up by title checksum, titles sharing a checksum are told apart by the fourth letter,
+    and everything else gets the default set. The boot ROM also offers the sets below
+    through button combinations held during the logo.
+*/
+
+use crate::boot::CompatPalette;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub bg: [u32; 4],
+    pub obj0: [u32; 4],
+    pub obj1: [u32; 4],
+}
+
+const GREY: [u32; 4] = [0xFF_FF_FF_FF, 0xFF_AA_AA_AA, 0xFF_55_55_55, 0xFF_00_00_00];
+const CLASSIC_GREEN: [u32; 4] = [0xFF_9B_BC_0F, 0xFF_8B_AC_0F, 0xFF_30_62_30, 0xFF_0F_38_0F];
+const POCKET_GREY: [u32; 4] = [0xFF_C4_CF_A1, 0xFF_8B_95_6D, 0xFF_4D_53_3C, 0xFF_1F_1F_1F];
+
+/// The CGB boot ROM colours as RGB555, four per palette.
+const COMPAT_COLORS: [u16; 120] = [
+    0x7FFF, 0x32BF, 0x00D0, 0x0000, 0x639F, 0x4279, 0x15B0, 0x04CB, 0x7FFF, 0x6E31, 0x454A, 0x0000,
+    0x7FFF, 0x1BEF, 0x0200, 0x0000, 0x7FFF, 0x421F, 0x1CF2, 0x0000, 0x7FFF, 0x5294, 0x294A, 0x0000,
+    0x7FFF, 0x03FF, 0x012F, 0x0000, 0x7FFF, 0x03EF, 0x01D6, 0x0000, 0x7FFF, 0x42B5, 0x3DC8, 0x0000,
+    0x7E74, 0x03FF, 0x0180, 0x0000, 0x67FF, 0x77AC, 0x1A13, 0x2D6B, 0x7ED6, 0x4BFF, 0x2175, 0x0000,
+    0x53FF, 0x4A5F, 0x7E52, 0x0000, 0x4FFF, 0x7ED2, 0x3A4C, 0x1CE0, 0x03ED, 0x7FFF, 0x255F, 0x0000,
+    0x036A, 0x021F, 0x03FF, 0x7FFF, 0x7FFF, 0x01DF, 0x0112, 0x0000, 0x231F, 0x035F, 0x00F2, 0x0009,
+    0x7FFF, 0x03EA, 0x011F, 0x0000, 0x299F, 0x001A, 0x000C, 0x0000, 0x7FFF, 0x027F, 0x001F, 0x0000,
+    0x7FFF, 0x03E0, 0x0206, 0x0120, 0x7FFF, 0x7EEB, 0x001F, 0x7C00, 0x7FFF, 0x3FFF, 0x7E00, 0x001F,
+    0x7FFF, 0x03FF, 0x001F, 0x0000, 0x03FF, 0x001F, 0x000C, 0x0000, 0x7FFF, 0x033F, 0x0193, 0x0000,
+    0x0000, 0x4200, 0x037F, 0x7FFF, 0x7FFF, 0x7E8C, 0x7C00, 0x0000, 0x7FFF, 0x1BEF, 0x6180, 0x0000,
+];
+
+/// A set made of whole palettes, as (obj0, obj1, bg) offsets into COMPAT_COLORS.
+const fn set(obj0: u8, obj1: u8, bg: u8) -> (u8, u8, u8) {
+    (obj0 * 4, obj1 * 4, bg * 4)
+}
+
+/// The boot ROM sets. A few start in the middle of a palette, those are given as offsets.
+const COMPAT_SETS: [(u8, u8, u8); 51] = [
+    set(4, 4, 29),
+    set(18, 18, 18),
+    set(20, 20, 20),
+    set(24, 24, 24),
+    set(9, 9, 9),
+    set(0, 0, 0),
+    set(27, 27, 27),
+    set(5, 5, 5),
+    set(12, 12, 12),
+    set(26, 26, 26),
+    set(16, 8, 8),
+    set(4, 28, 28),
+    set(4, 2, 2),
+    set(3, 4, 4),
+    set(4, 29, 29),
+    set(28, 4, 28),
+    set(2, 17, 2),
+    set(16, 16, 8),
+    set(4, 4, 7),
+    set(4, 4, 18),
+    set(4, 4, 20),
+    set(19, 19, 9),
+    (15, 15, 44),
+    set(17, 17, 2),
+    set(4, 4, 2),
+    set(4, 4, 3),
+    set(28, 28, 0),
+    set(3, 3, 0),
+    set(0, 0, 1),
+    set(18, 22, 18),
+    set(20, 22, 20),
+    set(24, 22, 24),
+    set(16, 22, 8),
+    set(17, 4, 13),
+    (111, 0, 56),
+    (111, 16, 60),
+    set(19, 22, 9),
+    set(16, 28, 10),
+    set(4, 23, 28),
+    set(17, 22, 2),
+    set(4, 0, 2),
+    set(4, 28, 3),
+    set(28, 3, 0),
+    set(3, 28, 4),
+    set(21, 28, 4),
+    set(3, 28, 0),
+    set(25, 3, 28),
+    set(0, 28, 8),
+    set(4, 3, 28),
+    set(28, 3, 6),
+    set(4, 28, 29),
+];
+
+/// Set for titles the boot ROM does not know, also picked with Right + A.
+const DEFAULT_COMPAT: u8 = 0;
+
+/// Title checksums the boot ROM knows. From FIRST_SHARED on the checksums repeat and
+/// the fourth letter of the title has to match as well.
+const COMPAT_CHECKSUMS: [u8; 94] = [
+    0x00, 0x88, 0x16, 0x36, 0xD1, 0xDB, 0xF2, 0x3C, 0x8C, 0x92, 0x3D, 0x5C, 0x58, 0xC9, 0x3E, 0x70,
+    0x1D, 0x59, 0x69, 0x19, 0x35, 0xA8, 0x14, 0xAA, 0x75, 0x95, 0x99, 0x34, 0x6F, 0x15, 0xFF, 0x97,
+    0x4B, 0x90, 0x17, 0x10, 0x39, 0xF7, 0xF6, 0xA2, 0x49, 0x4E, 0x43, 0x68, 0xE0, 0x8B, 0xF0, 0xCE,
+    0x0C, 0x29, 0xE8, 0xB7, 0x86, 0x9A, 0x52, 0x01, 0x9D, 0x71, 0x9C, 0xBD, 0x5D, 0x6D, 0x67, 0x3F,
+    0x6B, 0xB3, 0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4, 0xB3,
+    0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4, 0xB3,
+];
+
+const FIRST_SHARED: usize = 65;
+
+/// Fourth title letters for the checksums from FIRST_SHARED on.
+const COMPAT_LETTERS: &[u8; 29] = b"BEFAARBEKEK R-URAR INAILICE R";
+
+/// Set for every checksum in COMPAT_CHECKSUMS.
+const COMPAT_SET_INDICES: [u8; 94] = [
+    0, 4, 5, 35, 34, 3, 31, 15, 10, 5, 19, 36, 7, 37, 30, 44, 21, 32, 31, 20, 5, 33, 13, 14, 5, 29, 5, 18, 9, 3, 2, 26,
+    25, 25, 41, 42, 26, 45, 42, 45, 36, 38, 26, 42, 30, 41, 34, 34, 5, 42, 6, 5, 33, 25, 42, 42, 40, 2, 16, 25, 42, 42,
+    5, 0, 39, 36, 22, 25, 6, 32, 12, 36, 11, 39, 18, 39, 24, 31, 50, 17, 46, 6, 27, 0, 47, 41, 41, 0, 0, 19, 34, 23,
+    18, 29,
+];
+
+/// The sets the boot ROM offers through buttons held during the logo.
+const CGB_PRESETS: [(&str, u8); 12] = [
+    ("cgb-up", 5),
+    ("cgb-up-a", 43),
+    ("cgb-up-b", 28),
+    ("cgb-left", 48),
+    ("cgb-left-a", 40),
+    ("cgb-left-b", 7),
+    ("cgb-down", 8),
+    ("cgb-down-a", 3),
+    ("cgb-down-b", 49),
+    ("cgb-right", 1),
+    ("cgb-right-a", DEFAULT_COMPAT),
+    ("cgb-right-b", 6),
+];
+
+impl Palette {
+    /// Plain greys, what the DMG screen has always been drawn with here.
+    pub const DEFAULT: Palette = Palette::uniform(GREY);
+
+    /// The same colours for every layer.
+    pub const fn uniform(colors: [u32; 4]) -> Palette {
+        Palette { bg: colors, obj0: colors, obj1: colors }
+    }
+
+    /// A preset by name, or hex colours: 4 `RRGGBB` values for every layer, or 12 for the
+    /// background and both object palettes, separated by commas.
+    pub fn from_name(name: &str) -> Option<Palette> {
+        match name {
+            "grey" => Some(Palette::uniform(GREY)),
+            "green" => Some(Palette::uniform(CLASSIC_GREEN)),
+            "pocket" => Some(Palette::uniform(POCKET_GREY)),
+            _ => CGB_PRESETS
+                .iter()
+                .find(|(preset, _)| *preset == name)
+                .map(|&(_, set)| Palette::compat_set(set))
+                .or_else(|| Palette::from_hex(name)),
+        }
+    }
+
+    fn from_hex(value: &str) -> Option<Palette> {
+        let colors = value
+            .split(',')
+            .map(|color| u32::from_str_radix(color.trim().trim_start_matches('#'), 16).ok().filter(|&c| c <= 0xFF_FF_FF))
+            .collect::<Option<Vec<u32>>>()?;
+        let set = |i: usize| -> [u32; 4] { std::array::from_fn(|c| 0xFF_00_00_00 | colors[i * 4 + c]) };
+        match colors.len() {
+            4 => Some(Palette::uniform(set(0))),
+            12 => Some(Palette { bg: set(0), obj0: set(1), obj1: set(2) }),
+            _ => None,
+        }
+    }
+
+    /// The set the CGB boot ROM gives a DMG cartridge, `None` being a non-Nintendo title.
+    pub fn cgb_compat(title: Option<CompatPalette>) -> Palette {
+        let set = title
+            .and_then(|title| {
+                (0..COMPAT_CHECKSUMS.len()).find(|&i| {
+                    COMPAT_CHECKSUMS[i] == title.checksum && (i < FIRST_SHARED || COMPAT_LETTERS[i - FIRST_SHARED] == title.fourth_letter)
+                })
+            })
+            .map_or(DEFAULT_COMPAT, |i| COMPAT_SET_INDICES[i]);
+        Palette::compat_set(set)
+    }
+
+    fn compat_set(set: u8) -> Palette {
+        let (obj0, obj1, bg) = COMPAT_SETS[set as usize];
+        let colors = |offset: u8| -> [u32; 4] { std::array::from_fn(|i| rgb555(COMPAT_COLORS[offset as usize + i])) };
+        Palette { bg: colors(bg), obj0: colors(obj0), obj1: colors(obj1) }
+    }
+}
+
+/// Widens the 5 bit channels to 8 bits.
+fn rgb555(color: u16) -> u32 {
+    let channel = |shift: u16| -> u32 {
+        let value = ((color >> shift) & 0x1F) as u32;
+        (value << 3) | (value >> 2)
+    };
+    0xFF_00_00_00 | (channel(0) << 16) | (channel(5) << 8) | channel(10)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presets_and_compat_lookup() {
+        assert_eq!(Palette::from_name("green").unwrap().bg[0], 0xFF_9B_BC_0F);
+        let green = [0xFF_FF_FF_FF, 0xFF_7B_FF_31, 0xFF_00_84_00, 0xFF_00_00_00];
+        assert_eq!(Palette::from_name("cgb-left").unwrap().obj1, green);
+        assert_eq!(Palette::from_name("FFFFFF,AAAAAA,555555,000000"), Some(Palette::DEFAULT));
+        assert!(Palette::from_name("FFFFFF,AAAAAA").is_none());
+        assert!(Palette::from_name("sepia").is_none());
+
+        // POKEMON RED, TETRIS, then POKEMON BLUE and VEGAS STAKES sharing a checksum.
+        let red = CompatPalette { checksum: 0x14, fourth_letter: b'K' };
+        assert_eq!(Palette::cgb_compat(Some(red)).bg, [0xFF_FF_FF_FF, 0xFF_FF_84_84, 0xFF_94_39_39, 0xFF_00_00_00]);
+        let tetris = CompatPalette { checksum: 0xDB, fourth_letter: b'R' };
+        assert_eq!(Palette::cgb_compat(Some(tetris)), Palette::from_name("cgb-down-a").unwrap());
+        let blue = CompatPalette { checksum: 0x61, fourth_letter: b'E' };
+        assert_eq!(Palette::cgb_compat(Some(blue)).bg[2], 0xFF_00_00_FF);
+        let vegas = CompatPalette { checksum: 0x61, fourth_letter: b'A' };
+        assert_eq!(Palette::cgb_compat(Some(vegas)), Palette::compat_set(41));
+
+        let unknown = CompatPalette { checksum: 0x61, fourth_letter: b'Z' };
+        assert_eq!(Palette::cgb_compat(Some(unknown)), Palette::compat_set(DEFAULT_COMPAT));
+        assert_eq!(Palette::cgb_compat(None), Palette::from_name("cgb-right-a").unwrap());
+    }
+}
@@ -0,0 +1,30 @@
+//! Game Boy emulator core. `Emulator` is the entry point, the modules underneath are the
+//! hardware components it is built from.
+
+// The components predate the library and are built with `new()`/`default()` constructors.
+#![allow(clippy::new_without_default, clippy::should_implement_trait)]
+
+pub mod apu;
+pub mod boot;
+pub mod bus;
+pub mod cartridge;
+pub mod cpu;
+pub mod emulator;
+pub mod gbs;
+pub mod instructions;
+pub mod ram;
+pub mod util;
+pub mod tick;
+pub mod io;
+pub mod joypad;
+pub mod debug;
+pub mod timer;
+pub mod ppu;
+pub mod savestate;
+pub mod serial;
+pub mod sgb;
+pub mod dma;
+pub mod lcd;
+pub mod movie;
+
+pub use emulator::{Emulator, EmulatorError, EmulatorOptions, Model};
@@ -1,28 +1,205 @@
-mod bus;
-mod cartridge;
-mod cpu;
+mod audio;
+mod cli;
 mod emu;
 mod gfx;
-mod instructions;
-mod ram;
-mod util;
-mod tick;
-mod io;
-mod debug;
-mod timer;
-mod ppu;
-mod dma;
-mod lcd;
+
+use gbc_rs::debug::log::LogLevel;
+use gbc_rs::debug::trace::Trace;
+use gbc_rs::emulator::{SCREEN_HEIGHT, SCREEN_WIDTH, SGB_HEIGHT, SGB_WIDTH};
+use gbc_rs::{serial, Emulator, EmulatorOptions, Model};
+use gbc_rs::gbs::Gbs;
+use crate::audio::AudioSink;
+use crate::cli::{CliError, Options};
+use crate::emu::headless::HeadlessExit;
+use crate::gfx::Gfx;
+use crate::gfx::memory::MemoryGfx;
+
+/// The value, or the error printed after `context` and the same exit code as a failed
+/// headless run.
+fn or_exit<T, E: std::fmt::Debug>(result: Result<T, E>, context: &str) -> T {
+    match result {
+        Ok(value) => value,
+        Err(e) => {
+            eprintln!("{}: {:?}", context, e);
+            std::process::exit(HeadlessExit::Error.code());
+        }
+    }
+}
+
+/// The SDL window and the tile viewer, or in-memory buffers for headless runs and GBS
+/// exports. The tile viewer is only opened with the debugger.
+fn create_gfx(options: &Options, model: Model) -> (Box<dyn Gfx>, Option<Box<dyn Gfx>>) {
+    let (width, height) = match model.is_sgb() {
+        true => (SGB_WIDTH * options.scale, SGB_HEIGHT * options.scale),
+        false => (SCREEN_WIDTH * options.scale, SCREEN_HEIGHT * options.scale),
+    };
+
+    let windowed = options.headless.is_none() && options.gbs_export.is_none();
+    #[cfg(feature = "sdl")]
+    if windowed {
+        let gfx = Box::new(or_exit(gfx::sdl::SDL::new(width, height, false, options.fullscreen), "Failed to open the window"));
+        let debug_gfx = match options.debug {
+            true => {
+                let debug_gfx = or_exit(gfx::sdl::SDL::new(emu::DEBUG_W, emu::DEBUG_H, true, false), "Failed to open the tile viewer");
+                Some(Box::new(debug_gfx) as Box<dyn Gfx>)
+            }
+            false => None,
+        };
+        return (gfx, debug_gfx);
+    }
+
+    #[cfg(not(feature = "sdl"))]
+    if windowed {
+        eprintln!("Built without the sdl feature, only --headless runs are available");
+        std::process::exit(HeadlessExit::Error.code());
+    }
+
+    (Box::new(MemoryGfx::new(width, height)), None)
+}
+
+/// Sound output for windowed runs.
+fn create_audio(options: &Options) -> Option<Box<dyn AudioSink>> {
+    if !options.audio || options.headless.is_some() || options.gbs_export.is_some() {
+        return None;
+    }
+
+    #[cfg(feature = "sdl")]
+    match audio::sdl::SdlAudio::new() {
+        Ok(audio) => return Some(Box::new(audio)),
+        Err(e) => eprintln!("Audio disabled: {}", e),
+    }
+    None
+}
+
+/// The cable plugged into the serial port, the options only allow one.
+fn create_cable(options: &Options) -> Option<Box<dyn serial::link::LinkCable>> {
+    if let Some(address) = &options.link_listen {
+        let context = format!("Failed to listen on {}", address);
+        return Some(or_exit(serial::link::LinkAddress::parse(address).and_then(|address| address.listen()), &context));
+    }
+    if let Some(address) = &options.link_connect {
+        let context = format!("Failed to connect to {}", address);
+        return Some(or_exit(serial::link::LinkAddress::parse(address).and_then(|address| address.connect()), &context));
+    }
+    options.printer.as_ref().map(|directory| Box::new(serial::printer::Printer::new(directory)) as Box<dyn serial::link::LinkCable>)
+}
 
 fn main() {
-    let mut emu = emu::EMU::default();
-    let filename = "./games/tetris.gb".to_string();
-    emu.load_game(filename);
-    emu.run();
-    println!("EMU is paused: {}", emu.paused);
-    println!("EMU is running: {}", emu.running);
-    println!("EMU ticks: {}", emu.tm.get_ticks().unwrap());
-
-    println!("Cpu Trace:");
-    debug::trace::Trace::print_last_static(20);
+    let args: Vec<String> = std::env::args().collect();
+    let options = match Options::parse(&args[1..]) {
+        Ok(options) => options,
+        Err(CliError::Help) => {
+            println!("{}", cli::HELP);
+            return;
+        }
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, cli::HELP);
+            std::process::exit(HeadlessExit::Error.code());
+        }
+    };
+    LogLevel::set(options.log_level);
+    Trace::set_recording(options.debug);
+
+    let content = or_exit(std::fs::read(&options.rom), &format!("Failed to read {}", options.rom));
+    let is_gbs = options.rom.ends_with(".gbs");
+    let model = match options.model {
+        Some(model) => model,
+        None if is_gbs => Model::Dmg,
+        None => {
+            let model = Model::from_rom(&content);
+            if model.is_cgb() {
+                eprintln!("Warning: the colour features of the Game Boy Color are not emulated yet");
+            }
+            model
+        }
+    };
+    let (gfx, debug_gfx) = create_gfx(&options, model);
+    let audio = create_audio(&options);
+    let boot_rom = options.boot_rom.as_ref().map(|path| or_exit(std::fs::read(path), &format!("Failed to read {}", path)));
+    let core_options = EmulatorOptions { audio: audio.is_some(), boot_rom, palette: options.palette };
+    let mut emu = match is_gbs {
+        true => {
+            let gbs = or_exit(Gbs::new(content), "Failed to load the GBS file");
+            let core = Emulator::new(model, gbs.build_rom(0), EmulatorOptions { boot_rom: None, ..core_options });
+            let mut emu = emu::EMU::new(or_exit(core, "Failed to start the emulator"), None, gfx, debug_gfx);
+            or_exit(emu.load_gbs(gbs), "Failed to load the GBS file");
+            emu
+        }
+        false => {
+            let core = or_exit(Emulator::new(model, content, core_options), "Failed to start the emulator");
+            emu::EMU::new(core, Some(options.rom.clone()), gfx, debug_gfx)
+        }
+    };
+    emu.audio = audio;
+    emu.paused = options.paused;
+    emu.screenshot_scale = options.screenshot_scale;
+    if let Some(cable) = create_cable(&options) {
+        emu.core.connect_link(cable);
+    }
+    if let Some(speed) = options.rewind_speed {
+        emu.rewind_speed = speed;
+    }
+
+    if let Some(track) = options.gbs_track {
+        or_exit(emu.gbs_play_track(track - 1), "Failed to start the track");
+    }
+    if let Some(path) = &options.gbs_export {
+        let track = emu.gbs_track;
+        or_exit(emu.export_gbs_track(track, options.gbs_seconds, path), &format!("Failed to export {}", path));
+        return;
+    }
+    if emu.gbs.is_some() && options.headless.is_none() {
+        emu.run_gbs();
+        return;
+    }
+
+    if let Some(path) = &options.camera {
+        or_exit(emu.core.set_camera_source(path), &format!("Failed to load camera image {}", path));
+    }
+    if let Some(path) = &options.state {
+        let state = or_exit(std::fs::read(path), &format!("Failed to read {}", path));
+        or_exit(emu.core.load_state(&state), &format!("Failed to load state {}", path));
+    }
+    if let Some(path) = &options.record_movie {
+        or_exit(emu.start_movie_recording(path, options.state.is_none()), "Failed to start the movie");
+    }
+    if let Some(path) = &options.play_movie {
+        or_exit(emu.start_movie_playback(path), &format!("Failed to play movie {}", path));
+    }
+    if let Some(path) = &options.record_video {
+        or_exit(emu.start_recording(path), "Failed to start recording");
+    }
+    if let Some(path) = &options.record_gif {
+        let (start, end) = options.gif_frames;
+        or_exit(emu.start_gif(path, start, end), "Failed to start the GIF");
+    }
+
+    let exit = match options.headless {
+        Some(frames) => {
+            let exit = emu.run_headless(frames, options.until, options.fail_if);
+            println!("Headless run ended after {} frames: {:?}", emu.core.frame_count(), exit);
+            exit
+        }
+        None => {
+            emu.run();
+            HeadlessExit::Passed
+        }
+    };
+
+    if let Some(path) = &options.screenshot {
+        if let Err(e) = emu.save_screenshot(path) {
+            eprintln!("Failed to save screenshot: {}", e);
+        }
+    }
+    if let Some(path) = &options.tile_screenshot {
+        if let Err(e) = emu.save_tile_screenshot(path) {
+            eprintln!("Failed to save screenshot: {}", e);
+        }
+    }
+    if options.debug {
+        println!("EMU ticks: {}", emu.core.ticks());
+        println!("Cpu Trace:");
+        Trace::print_last_static(20);
+    }
+    std::process::exit(exit.code());
 }
@@ -0,0 +1,180 @@
+/*
+    Movie file layout:
+    0x00 - 0x03: Identifier "GBMV"
+    0x04 - 0x05: Format version (LE)
+    0x06 - 0x09: CRC-32 of the ROM (LE)
+    0x0A:        Model (0 = DMG, 1 = DMG0, 2 = MGB, 3 = SGB, 4 = SGB2, 5 = CGB0, 6 = CGB,
+                 7 = AGB)
+    0x0B:        Start (0 = power-on, 1 = save state)
+    0x0C - 0x0F: Start state length (LE), followed by the state
+    Then:        Frame count (LE u32) and one joypad byte per frame (BUTTON_* bits)
+    Then:        Checkpoint count (LE u32) and (frame, CRC-32 of the state) pairs, both LE u32
+*/
+
+use crate::emulator::Model;
+use crate::savestate::{StateReader, SaveStateError};
+
+const MAGIC: &[u8; 4] = b"GBMV";
+const FORMAT_VERSION: u16 = 1;
+/// Models by their number in the header.
+const MODELS: [Model; 8] = [
+    Model::Dmg,
+    Model::Dmg0,
+    Model::Mgb,
+    Model::Sgb,
+    Model::Sgb2,
+    Model::Cgb0,
+    Model::Cgb,
+    Model::Agb,
+];
+/// Frames between two state checksums.
+pub const CHECKPOINT_INTERVAL: u32 = 60;
+
+#[derive(Debug)]
+pub enum MovieError {
+    InvalidMagic,
+    UnsupportedVersion(u16),
+    InvalidData(String),
+    RomMismatch,
+    /// The movie was recorded on another model.
+    ModelMismatch,
+    /// A power-on movie started on a machine that already ran or loaded a state.
+    NotAtPowerOn,
+    /// A link cable or camera source feeds in data the movie does not record.
+    ExternalInput,
+    NoRomLoaded,
+    StateError(SaveStateError),
+    IoError(std::io::Error),
+}
+
+impl From<std::io::Error> for MovieError {
+    fn from(e: std::io::Error) -> MovieError {
+        MovieError::IoError(e)
+    }
+}
+
+impl From<SaveStateError> for MovieError {
+    fn from(e: SaveStateError) -> MovieError {
+        match e {
+            SaveStateError::Truncated => MovieError::InvalidData("truncated".to_string()),
+            e => MovieError::StateError(e),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MovieStart {
+    PowerOn,
+    State(Vec<u8>),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Movie {
+    pub rom_crc: u32,
+    pub model: Model,
+    pub start: MovieStart,
+    /// Joypad buttons held during each frame.
+    pub inputs: Vec<u8>,
+    /// State checksums taken after `frame` frames.
+    pub checkpoints: Vec<(u32, u32)>,
+}
+
+impl Movie {
+    pub fn new(rom_crc: u32, model: Model, start: MovieStart) -> Movie {
+        Movie {
+            rom_crc,
+            model,
+            start,
+            inputs: Vec::new(),
+            checkpoints: Vec::new(),
+        }
+    }
+
+    pub fn checkpoint(&self, frame: u32) -> Option<u32> {
+        self.checkpoints.iter().find(|(at, _)| *at == frame).map(|(_, crc)| *crc)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        out.extend_from_slice(&self.rom_crc.to_le_bytes());
+        out.push(MODELS.iter().position(|&model| model == self.model).unwrap() as u8);
+        let state: &[u8] = match &self.start {
+            MovieStart::PowerOn => {
+                out.push(0);
+                &[]
+            }
+            MovieStart::State(state) => {
+                out.push(1);
+                state
+            }
+        };
+        out.extend_from_slice(&(state.len() as u32).to_le_bytes());
+        out.extend_from_slice(state);
+        out.extend_from_slice(&(self.inputs.len() as u32).to_le_bytes());
+        out.extend_from_slice(&self.inputs);
+        out.extend_from_slice(&(self.checkpoints.len() as u32).to_le_bytes());
+        for (frame, crc) in &self.checkpoints {
+            out.extend_from_slice(&frame.to_le_bytes());
+            out.extend_from_slice(&crc.to_le_bytes());
+        }
+        out
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Movie, MovieError> {
+        if data.len() < 6 || &data[0..4] != MAGIC {
+            return Err(MovieError::InvalidMagic);
+        }
+
+        let mut reader = StateReader::new(&data[4..], FORMAT_VERSION);
+        let version = reader.u16()?;
+        if version != FORMAT_VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+
+        let rom_crc = reader.u32()?;
+        let model = *MODELS.get(reader.u8()? as usize).ok_or(MovieError::InvalidData("model".to_string()))?;
+        let has_state = reader.bool()?;
+        let mut state = vec![0; reader.u32()? as usize];
+        reader.bytes(&mut state)?;
+        let start = match has_state {
+            true => MovieStart::State(state),
+            false => MovieStart::PowerOn,
+        };
+
+        let mut inputs = vec![0; reader.u32()? as usize];
+        reader.bytes(&mut inputs)?;
+        let mut checkpoints = Vec::new();
+        for _ in 0..reader.u32()? {
+            checkpoints.push((reader.u32()?, reader.u32()?));
+        }
+
+        Ok(Movie { rom_crc, model, start, inputs, checkpoints })
+    }
+
+    pub fn load(path: &str) -> Result<Movie, MovieError> {
+        Movie::from_bytes(&std::fs::read(path)?)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), MovieError> {
+        Ok(std::fs::write(path, self.to_bytes())?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let mut movie = Movie::new(0xDEADBEEF, Model::Sgb, MovieStart::State(vec![1, 2, 3]));
+        movie.inputs = vec![0x00, 0x08, 0x09, 0x00];
+        movie.checkpoints = vec![(0, 0x1234), (60, 0x5678)];
+
+        let bytes = movie.to_bytes();
+        assert_eq!(Movie::from_bytes(&bytes).unwrap(), movie);
+        assert_eq!(movie.checkpoint(60), Some(0x5678));
+        assert!(matches!(Movie::from_bytes(&bytes[..bytes.len() - 1]), Err(MovieError::InvalidData(_))));
+        assert!(matches!(Movie::from_bytes(b"GBST\x01\x00"), Err(MovieError::InvalidMagic)));
+    }
+}
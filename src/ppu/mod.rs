@@ -1,8 +1,7 @@
-use std::sync::{Arc, Mutex};
 use crate::cpu::CPU;
 use crate::cpu::interrupts::{IFlagsRegister, InterruptType};
-use crate::emu::GlobalContext;
 use crate::lcd::{LCD, LCDMode, StatSrc};
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
 use crate::tick::TickManager;
 
 const BG_WINDOW_MASK: u8 = 1 << 7;
@@ -14,8 +13,15 @@ const CGB_PALLETE_NUMBER_MASK: u8 = 0x03;
 
 const LINES_PER_FRAME: u8 = 154;
 const TICKS_PER_LINE: u32 = 456;
-const YRES: u32 = 144;
-const XRES: u32 = 160;
+/// Size of the LCD in pixels.
+pub const YRES: u32 = 144;
+pub const XRES: u32 = 160;
+const SPRITES_PER_LINE: usize = 10;
+
+const OAM_SIZE: u16 = 0xA0;
+
+/// T-cycles in one frame, 70224.
+pub const TICKS_PER_FRAME: u64 = LINES_PER_FRAME as u64 * TICKS_PER_LINE as u64;
 
 const TARGET_FRAME_TIME: u32 = 1000/60;
 
@@ -64,41 +70,71 @@ impl OAM {
     }
 }
 
-#[derive(Clone)]
 pub struct PPU {
     oam_ram: [OAM; 40],
     vram: [u8; 0x2000],
 
     current_frame: u32,
     line_ticks: u32,
+    /// Window line drawn next, it only moves on lines where the window is visible.
+    window_line: u32,
     video_buffer: [u32; (XRES * YRES) as usize],
-    lcd: Arc<Mutex<LCD>>,
-    int_flags: Arc<Mutex<IFlagsRegister>>,
+    /// The same frame as DMG shades 0-3 after the palette registers, what the SGB colours.
+    shade_buffer: [u8; (XRES * YRES) as usize],
+    pub lcd: LCD,
 }
 
 impl PPU {
 
-    pub fn new(global_context: GlobalContext) -> PPU {
-        let lcd = global_context.lcd.unwrap();
-        lcd.lock().unwrap().lcds_mode_set(LCDMode::OAM);
+    pub fn new() -> PPU {
+        let mut lcd = LCD::new();
+        lcd.lcds_mode_set(LCDMode::OAM);
         PPU {
             oam_ram: [OAM::default(); 40],
             vram: [0; 0x2000],
             current_frame: 0,
             line_ticks: 0,
+            window_line: 0,
             video_buffer: [0; (XRES * YRES) as usize],
+            shade_buffer: [0; (XRES * YRES) as usize],
             lcd,
-            int_flags: global_context.int_flags.clone(),
         }
     }
 
-    pub fn increment_ly(&mut self) {
-        let mut lcd = self.lcd.lock().unwrap();
+    pub fn current_frame(&self) -> u32 {
+        self.current_frame
+    }
+
+    /// Last finished frame, `XRES` x `YRES` pixels as 0xAARRGGBB.
+    pub fn video_buffer(&self) -> &[u32] {
+        &self.video_buffer
+    }
+
+    pub fn shade_buffer(&self) -> &[u8] {
+        &self.shade_buffer
+    }
+
+    /// DMG-family quirk: a STAT write briefly enables every STAT source, so it raises the
+    /// interrupt during HBlank, VBlank or on an LY=LYC line.
+    pub fn stat_write_quirk(&mut self, int_flags: &mut IFlagsRegister) {
+        let lcd = &self.lcd;
+        if !lcd.lcdc_display_enabled() {
+            return;
+        }
+        let blanking = matches!(lcd.lcds_mode_flag(), LCDMode::HBlank | LCDMode::VBlank);
+        if blanking || lcd.lcds_lyc_flag() {
+            int_flags.add_interrupt(InterruptType::LcdStat);
+        }
+    }
+
+    pub fn increment_ly(&mut self, int_flags: &mut IFlagsRegister) {
+        let lcd = &mut self.lcd;
+        lcd.register.ly = lcd.register.ly.wrapping_add(1);
 
         if lcd.register.ly == lcd.register.ly_compare {
             lcd.lcds_lyc_set(true);
             if (lcd.lcds_stat_int(StatSrc::LYC) != 0) {
-                self.int_flags.lock().unwrap().add_interrupt(InterruptType::LcdStat)
+                int_flags.add_interrupt(InterruptType::LcdStat)
             }
         }else {
             lcd.lcds_lyc_set(false);
@@ -107,18 +143,19 @@ impl PPU {
 
     pub fn ppu_mode_oam(&mut self) {
         if self.line_ticks >= 80 {
-            self.lcd.lock().unwrap().lcds_mode_set(LCDMode::PixelTransfer);
+            self.lcd.lcds_mode_set(LCDMode::PixelTransfer);
         }
     }
 
-    pub fn ppu_mode_vblank(&mut self) {
+    pub fn ppu_mode_vblank(&mut self, int_flags: &mut IFlagsRegister) {
         if self.line_ticks >= (TICKS_PER_LINE) as u32 {
-            self.increment_ly();
+            self.increment_ly(int_flags);
             {
-                let mut lcd = self.lcd.lock().unwrap();
+                let lcd = &mut self.lcd;
                 if lcd.register.ly >= LINES_PER_FRAME {
                     lcd.lcds_mode_set(LCDMode::OAM);
                     lcd.register.ly = 0;
+                    self.window_line = 0;
                 }
             }
 
@@ -126,17 +163,17 @@ impl PPU {
         }
     }
 
-    pub fn ppu_mode_hblank(&mut self) {
+    pub fn ppu_mode_hblank(&mut self, int_flags: &mut IFlagsRegister) {
         if self.line_ticks >= (TICKS_PER_LINE) as u32 {
-            self.increment_ly();
+            self.increment_ly(int_flags);
             {
-                let mut lcd = self.lcd.lock().unwrap();
+                let lcd = &mut self.lcd;
                 if lcd.register.ly >= YRES as u8 {
                     lcd.lcds_mode_set(LCDMode::VBlank);
-                    self.int_flags.lock().unwrap().add_interrupt(InterruptType::VBlank);
+                    int_flags.add_interrupt(InterruptType::VBlank);
 
                     if (lcd.lcds_stat_int(StatSrc::VBlank) != 0) {
-                        self.int_flags.lock().unwrap().add_interrupt(InterruptType::LcdStat);
+                        int_flags.add_interrupt(InterruptType::LcdStat);
                     }
 
                     self.current_frame += 1;
@@ -155,19 +192,128 @@ impl PPU {
 
     pub fn ppu_mode_pixel_transfer(&mut self) {
         if self.line_ticks >= (0x80 + 172) {
-            self.lcd.lock().unwrap().lcds_mode_set(LCDMode::HBlank);
+            self.render_line();
+            self.lcd.lcds_mode_set(LCDMode::HBlank);
         }
     }
 
-    pub fn ppu_tick(&mut self) {
+    /// Colour index of pixel (`x`, `y`) of the tile at `tile_addr`, rows run on into the
+    /// next tile for 8x16 sprites.
+    fn tile_pixel(&self, tile_addr: u16, x: u32, y: u32) -> u8 {
+        let low = self.vram_read(tile_addr + y as u16 * 2);
+        let high = self.vram_read(tile_addr + y as u16 * 2 + 1);
+        let bit = 7 - x;
+        (((high >> bit) & 1) << 1) | ((low >> bit) & 1)
+    }
+
+    /// VRAM offset of a background or window tile, 0x8800 addressing uses signed indices.
+    pub fn bgw_tile_addr(&self, tile: u8) -> u16 {
+        match self.lcd.lcdc_bgw_data_area() {
+            0x8000 => tile as u16 * 16,
+            _ => (0x1000 + (tile as i8 as i16) * 16) as u16,
+        }
+    }
+
+    /// Draws line LY into the video buffer in one go: background, window, then sprites.
+    fn render_line(&mut self) {
+        let ly = self.lcd.register.ly as u32;
+        if ly >= YRES {
+            return;
+        }
+
+        let mut line = [self.lcd.register.bg_colors[0]; XRES as usize];
+        let mut shades = [self.lcd.register.bg_palette & 0b11; XRES as usize];
+        let mut bg_index = [0u8; XRES as usize];
+        if !self.lcd.lcdc_display_enabled() {
+            line = [self.lcd.palette().bg[0]; XRES as usize];
+            shades = [0; XRES as usize];
+        } else if self.lcd.lcdc_bgw_enabled() {
+            self.render_bg_window(ly, &mut line, &mut shades, &mut bg_index);
+        }
+        if self.lcd.lcdc_display_enabled() && self.lcd.lcdc_obj_enabled() {
+            self.render_sprites(ly, &mut line, &mut shades, &bg_index);
+        }
+
+        let start = (ly * XRES) as usize;
+        self.video_buffer[start..start + XRES as usize].copy_from_slice(&line);
+        self.shade_buffer[start..start + XRES as usize].copy_from_slice(&shades);
+    }
+
+    fn render_bg_window(&mut self, ly: u32, line: &mut [u32; XRES as usize], shades: &mut [u8; XRES as usize], bg_index: &mut [u8; XRES as usize]) {
+        let r = &self.lcd.register;
+        let (scroll_x, scroll_y, wx) = (r.scroll_x as u32, r.scroll_y as u32, r.wx as u32);
+        let window = self.lcd.lcdc_win_enabled() && r.wy as u32 <= ly && wx < XRES + 7;
+
+        for x in 0..XRES {
+            let (map, px, py) = match window && x + 7 >= wx {
+                true => (self.lcd.lcdc_win_map_area(), x + 7 - wx, self.window_line),
+                false => (self.lcd.lcdc_bg_map_area(), (x + scroll_x) & 0xFF, (ly + scroll_y) & 0xFF),
+            };
+            let tile = self.vram_read(map - 0x8000 + (py / 8 * 32 + px / 8) as u16);
+            let index = self.tile_pixel(self.bgw_tile_addr(tile), px % 8, py % 8);
+            bg_index[x as usize] = index;
+            line[x as usize] = self.lcd.register.bg_colors[index as usize];
+            shades[x as usize] = (self.lcd.register.bg_palette >> (index * 2)) & 0b11;
+        }
+
+        if window {
+            self.window_line += 1;
+        }
+    }
+
+    fn render_sprites(&self, ly: u32, line: &mut [u32; XRES as usize], shades: &mut [u8; XRES as usize], bg_index: &[u8; XRES as usize]) {
+        let height = self.lcd.lcdc_obj_height() as u32;
+        let mut sprites: Vec<&OAM> = self
+            .oam_ram
+            .iter()
+            .filter(|oam| ly + 16 >= oam.y as u32 && ly + 16 < oam.y as u32 + height)
+            .take(SPRITES_PER_LINE)
+            .collect();
+        // The leftmost sprite wins, then the first in OAM: draw those last.
+        sprites.sort_by_key(|oam| oam.x);
+
+        for oam in sprites.iter().rev() {
+            let mut row = ly + 16 - oam.y as u32;
+            if oam.get_y_flip() {
+                row = height - 1 - row;
+            }
+            let tile = match height {
+                16 => oam.tile & 0xFE,
+                _ => oam.tile,
+            };
+            let (colors, palette) = match oam.get_palette_number() {
+                true => (&self.lcd.register.sp2_colors, self.lcd.register.obj_palette[1]),
+                false => (&self.lcd.register.sp1_colors, self.lcd.register.obj_palette[0]),
+            };
+
+            for i in 0..8 {
+                let x = oam.x as i32 - 8 + i as i32;
+                if !(0..XRES as i32).contains(&x) {
+                    continue;
+                }
+                let column = match oam.get_x_flip() {
+                    true => 7 - i,
+                    false => i,
+                };
+                let index = self.tile_pixel(tile as u16 * 16, column, row);
+                if index == 0 || (oam.get_bg_window_priority() && bg_index[x as usize] != 0) {
+                    continue;
+                }
+                line[x as usize] = colors[index as usize];
+                shades[x as usize] = (palette >> (index * 2)) & 0b11;
+            }
+        }
+    }
+
+    pub fn ppu_tick(&mut self, int_flags: &mut IFlagsRegister) {
         self.line_ticks += 1;
-        let lcd_mode = self.lcd.lock().unwrap().lcds_mode_flag();
+        let lcd_mode = self.lcd.lcds_mode_flag();
         match lcd_mode {
             LCDMode::HBlank => {
-                self.ppu_mode_hblank();
+                self.ppu_mode_hblank(int_flags);
             }
             LCDMode::VBlank => {
-                self.ppu_mode_vblank();
+                self.ppu_mode_vblank(int_flags);
             }
             LCDMode::OAM => {
                 self.ppu_mode_oam();
@@ -178,6 +324,30 @@ impl PPU {
         }
     }
 
+    /// T-cycles until the tick that changes the LCD mode.
+    pub fn ticks_to_next_mode(&self) -> u32 {
+        let end = match self.lcd.lcds_mode_flag() {
+            LCDMode::OAM => 80,
+            LCDMode::PixelTransfer => 0x80 + 172,
+            LCDMode::HBlank | LCDMode::VBlank => TICKS_PER_LINE,
+        };
+        end.saturating_sub(self.line_ticks).max(1)
+    }
+
+    /// Runs `cycles` T-cycles, only stepping the mode logic at the transitions.
+    pub fn advance(&mut self, mut cycles: u32, int_flags: &mut IFlagsRegister) {
+        while cycles > 0 {
+            let skip = (self.ticks_to_next_mode() - 1).min(cycles);
+            self.line_ticks += skip;
+            cycles -= skip;
+
+            if cycles > 0 {
+                self.ppu_tick(int_flags);
+                cycles -= 1;
+            }
+        }
+    }
+
     pub fn read(&self, address: u16) -> u8 {
         match address {
             0x8000..=0x9FFF => self.vram[(address - 0x8000) as usize],
@@ -227,3 +397,30 @@ impl PPU {
 
 }
 
+impl SaveState for PPU {
+    const TAG: [u8; 4] = *b"PPU ";
+    const VERSION: u16 = 1;
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.bytes(&self.vram);
+        for index in 0..OAM_SIZE {
+            writer.u8(self.oam_read(index));
+        }
+        writer.u32(self.line_ticks);
+        writer.u32(self.current_frame);
+        writer.u32(self.window_line);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        reader.bytes(&mut self.vram)?;
+        for index in 0..OAM_SIZE {
+            let data = reader.u8()?;
+            self.oam_write(index, data);
+        }
+        self.line_ticks = reader.u32()?;
+        self.current_frame = reader.u32()?;
+        // Indexes the window's tile map, which only has rows for the visible lines.
+        self.window_line = reader.u32()?.min(YRES);
+        Ok(())
+    }
+}
@@ -1,3 +1,5 @@
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+
 #[derive(Debug)]
 pub enum RamError {
     InvalidAddress,
@@ -49,3 +51,18 @@ impl Ram {
         Ok(self.hram[address as usize])
     }
 }
+
+impl SaveState for Ram {
+    const TAG: [u8; 4] = *b"RAM ";
+    const VERSION: u16 = 1;
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.bytes(&self.wram);
+        writer.bytes(&self.hram);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        reader.bytes(&mut self.wram)?;
+        reader.bytes(&mut self.hram)
+    }
+}
@@ -0,0 +1,246 @@
+/*
+    BESS (Best Effort Save State) trailer, shared with SameBoy, Emulicious and others.
+    Memory buffers are stored before the blocks and referenced by file offset.
+    Each block: 4 bytes name, 4 bytes payload length (LE), payload.
+    NAME: Emulator name
+    INFO: ROM title (0x134 - 0x143) and global checksum (0x14E - 0x14F)
+    CORE: Version, model, CPU registers, IO registers and the memory buffer table
+    MBC:  (address LE, value) writes that restore the mapper registers
+    END:  Empty, last block
+    Footer: offset of the first block (LE), then "BESS".
+*/
+
+use crate::savestate::SaveStateError;
+
+const FOOTER_MAGIC: &[u8; 4] = b"BESS";
+const CORE_SIZE: usize = 0xD0;
+const MAJOR_VERSION: u16 = 1;
+const MINOR_VERSION: u16 = 1;
+pub const IO_SIZE: usize = 0x80;
+
+pub const EXECUTION_RUNNING: u8 = 0;
+pub const EXECUTION_HALTED: u8 = 1;
+pub const EXECUTION_STOPPED: u8 = 2;
+
+pub struct BessCore {
+    /// Model identifier, e.g. "GD  " for a DMG.
+    pub model: [u8; 4],
+    pub pc: u16,
+    pub af: u16,
+    pub bc: u16,
+    pub de: u16,
+    pub hl: u16,
+    pub sp: u16,
+    pub ime: bool,
+    pub ie: u8,
+    pub execution_state: u8,
+    /// 0xFF00 - 0xFF7F
+    pub io: [u8; IO_SIZE],
+}
+
+pub struct BessState {
+    pub name: Option<String>,
+    pub title: Option<[u8; 16]>,
+    pub global_checksum: Option<[u8; 2]>,
+    pub core: BessCore,
+    pub wram: Vec<u8>,
+    pub vram: Vec<u8>,
+    pub mbc_ram: Vec<u8>,
+    pub oam: Vec<u8>,
+    pub hram: Vec<u8>,
+    pub mbc_writes: Vec<(u16, u8)>,
+}
+
+fn push_block(out: &mut Vec<u8>, name: &[u8; 4], payload: &[u8]) {
+    out.extend_from_slice(name);
+    out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    out.extend_from_slice(payload);
+}
+
+/// Appends the buffers, blocks and footer to `out`, usually a native state.
+pub fn append(out: &mut Vec<u8>, state: &BessState) {
+    let mut buffer_table = Vec::new();
+    for buffer in [&state.wram, &state.vram, &state.mbc_ram, &state.oam, &state.hram] {
+        buffer_table.extend_from_slice(&(buffer.len() as u32).to_le_bytes());
+        buffer_table.extend_from_slice(&(out.len() as u32).to_le_bytes());
+        out.extend_from_slice(buffer);
+    }
+    // No CGB palettes.
+    buffer_table.extend_from_slice(&[0; 16]);
+
+    let first_block = out.len() as u32;
+    if let Some(name) = state.name.as_ref() {
+        push_block(out, b"NAME", name.as_bytes());
+    }
+    if let (Some(title), Some(checksum)) = (state.title, state.global_checksum) {
+        let mut info = title.to_vec();
+        info.extend_from_slice(&checksum);
+        push_block(out, b"INFO", &info);
+    }
+
+    let core = &state.core;
+    let mut payload = Vec::with_capacity(CORE_SIZE);
+    payload.extend_from_slice(&MAJOR_VERSION.to_le_bytes());
+    payload.extend_from_slice(&MINOR_VERSION.to_le_bytes());
+    payload.extend_from_slice(&core.model);
+    for register in [core.pc, core.af, core.bc, core.de, core.hl, core.sp] {
+        payload.extend_from_slice(&register.to_le_bytes());
+    }
+    payload.extend_from_slice(&[core.ime as u8, core.ie, core.execution_state, 0]);
+    payload.extend_from_slice(&core.io);
+    payload.extend_from_slice(&buffer_table);
+    push_block(out, b"CORE", &payload);
+
+    if !state.mbc_writes.is_empty() {
+        let mut mbc = Vec::with_capacity(state.mbc_writes.len() * 3);
+        for (address, value) in &state.mbc_writes {
+            mbc.extend_from_slice(&address.to_le_bytes());
+            mbc.push(*value);
+        }
+        push_block(out, b"MBC ", &mbc);
+    }
+
+    push_block(out, b"END ", &[]);
+    out.extend_from_slice(&first_block.to_le_bytes());
+    out.extend_from_slice(FOOTER_MAGIC);
+}
+
+pub fn has_footer(data: &[u8]) -> bool {
+    data.len() >= 8 && &data[data.len() - 4..] == FOOTER_MAGIC
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// Copies a buffer referenced by the (size, offset) pair at `entry` in the CORE payload.
+fn read_buffer(data: &[u8], core: &[u8], entry: usize) -> Result<Vec<u8>, SaveStateError> {
+    let size = read_u32(core, entry) as usize;
+    let offset = read_u32(core, entry + 4) as usize;
+    Ok(data.get(offset..offset + size).ok_or(SaveStateError::Truncated)?.to_vec())
+}
+
+pub fn parse(data: &[u8]) -> Result<BessState, SaveStateError> {
+    if !has_footer(data) {
+        return Err(SaveStateError::InvalidMagic);
+    }
+
+    let mut pos = read_u32(data, data.len() - 8) as usize;
+    let mut name = None;
+    let mut info = None;
+    let mut core = None;
+    let mut mbc_writes = Vec::new();
+
+    loop {
+        let header = data.get(pos..pos + 8).ok_or(SaveStateError::Truncated)?;
+        let block: [u8; 4] = header[0..4].try_into().unwrap();
+        let len = read_u32(header, 4) as usize;
+        let payload = data.get(pos + 8..pos + 8 + len).ok_or(SaveStateError::Truncated)?;
+        pos += 8 + len;
+
+        match &block {
+            b"NAME" => name = Some(String::from_utf8_lossy(payload).to_string()),
+            b"INFO" if len == 0x12 => info = Some(payload.to_vec()),
+            b"CORE" => {
+                if len < CORE_SIZE {
+                    return Err(SaveStateError::Truncated);
+                }
+                let major = read_u16(payload, 0);
+                if major != MAJOR_VERSION {
+                    return Err(SaveStateError::UnsupportedVersion(major));
+                }
+                core = Some(payload.to_vec());
+            }
+            b"MBC " => {
+                mbc_writes = payload
+                    .chunks_exact(3)
+                    .map(|write| (read_u16(write, 0), write[2]))
+                    .collect();
+            }
+            b"END " => break,
+            _ => {}
+        }
+    }
+
+    let payload = core.ok_or_else(|| SaveStateError::MissingSection("CORE".to_string()))?;
+    let mut io = [0; IO_SIZE];
+    io.copy_from_slice(&payload[0x18..0x18 + IO_SIZE]);
+    let core = BessCore {
+        model: payload[4..8].try_into().unwrap(),
+        pc: read_u16(&payload, 0x08),
+        af: read_u16(&payload, 0x0A),
+        bc: read_u16(&payload, 0x0C),
+        de: read_u16(&payload, 0x0E),
+        hl: read_u16(&payload, 0x10),
+        sp: read_u16(&payload, 0x12),
+        ime: payload[0x14] != 0,
+        ie: payload[0x15],
+        execution_state: payload[0x16],
+        io,
+    };
+
+    Ok(BessState {
+        name,
+        title: info.as_ref().map(|info| info[0..16].try_into().unwrap()),
+        global_checksum: info.as_ref().map(|info| [info[16], info[17]]),
+        wram: read_buffer(data, &payload, 0x98)?,
+        vram: read_buffer(data, &payload, 0xA0)?,
+        mbc_ram: read_buffer(data, &payload, 0xA8)?,
+        oam: read_buffer(data, &payload, 0xB0)?,
+        hram: read_buffer(data, &payload, 0xB8)?,
+        core,
+        mbc_writes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_and_parse() {
+        let mut io = [0; IO_SIZE];
+        io[0x40] = 0x91;
+        let state = BessState {
+            name: Some("gbc-rs".to_string()),
+            title: Some(*b"TETRIS\0\0\0\0\0\0\0\0\0\0"),
+            global_checksum: Some([0x16, 0xBF]),
+            core: BessCore {
+                model: *b"GD  ",
+                pc: 0x0150,
+                af: 0x01B0,
+                bc: 0x0013,
+                de: 0x00D8,
+                hl: 0x014D,
+                sp: 0xFFFE,
+                ime: true,
+                ie: 0x09,
+                execution_state: EXECUTION_HALTED,
+                io,
+            },
+            wram: vec![0x11; 0x2000],
+            vram: vec![0x22; 0x2000],
+            mbc_ram: vec![],
+            oam: vec![0x33; 0xA0],
+            hram: vec![0x44; 0x7F],
+            mbc_writes: vec![(0x2000, 0x01)],
+        };
+
+        let mut data = b"native state".to_vec();
+        append(&mut data, &state);
+        let parsed = parse(&data).unwrap();
+
+        assert_eq!(parsed.name.as_deref(), Some("gbc-rs"));
+        assert_eq!(parsed.global_checksum, Some([0x16, 0xBF]));
+        assert_eq!((parsed.core.pc, parsed.core.sp, parsed.core.ie), (0x0150, 0xFFFE, 0x09));
+        assert!(parsed.core.ime);
+        assert_eq!(parsed.core.io[0x40], 0x91);
+        assert_eq!(parsed.vram, state.vram);
+        assert_eq!(parsed.hram, state.hram);
+        assert_eq!(parsed.mbc_writes, vec![(0x2000, 0x01)]);
+    }
+}
@@ -0,0 +1,290 @@
+/*
+    Save state file layout:
+    0x00 - 0x03: Identifier "GBST"
+    0x04 - 0x05: Format version (LE)
+    0x06 - ....: Sections, each:
+                 4 bytes tag, 2 bytes section version (LE), 4 bytes payload length (LE), payload
+    The last section is "END " with an empty payload. Unknown sections are skipped.
+*/
+
+pub mod bess;
+pub mod rewind;
+
+use std::collections::HashMap;
+use crate::bus::BusError;
+
+const MAGIC: &[u8; 4] = b"GBST";
+pub const FORMAT_VERSION: u16 = 1;
+const END_TAG: [u8; 4] = *b"END ";
+
+#[derive(Debug)]
+pub enum SaveStateError {
+    InvalidMagic,
+    UnsupportedVersion(u16),
+    UnsupportedSectionVersion(String, u16),
+    MissingSection(String),
+    Truncated,
+    InvalidData(String),
+    RomMismatch,
+    /// The state was saved on another hardware model.
+    ModelMismatch,
+    NoRomLoaded,
+    IoError(std::io::Error),
+    BusError(BusError),
+}
+
+impl From<std::io::Error> for SaveStateError {
+    fn from(e: std::io::Error) -> SaveStateError {
+        SaveStateError::IoError(e)
+    }
+}
+
+impl From<BusError> for SaveStateError {
+    fn from(e: BusError) -> SaveStateError {
+        SaveStateError::BusError(e)
+    }
+}
+
+/// True for states written by `StateFile::to_bytes`.
+pub fn is_native(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+fn tag_name(tag: [u8; 4]) -> String {
+    String::from_utf8_lossy(&tag).trim().to_string()
+}
+
+/// A component that can be written to and restored from its own section.
+/// Fields added later go at the end of the payload behind a bump of `VERSION`,
+/// so `load_state` can check `reader.version` and keep defaults for older states.
+pub trait SaveState {
+    const TAG: [u8; 4];
+    const VERSION: u16;
+
+    fn save_state(&self, writer: &mut StateWriter);
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError>;
+}
+
+#[derive(Default)]
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bytes(&mut self, value: &[u8]) {
+        self.data.extend_from_slice(value);
+    }
+
+    /// Length-prefixed block, for buffers whose size depends on the cartridge.
+    pub fn block(&mut self, value: &[u8]) {
+        self.u32(value.len() as u32);
+        self.bytes(value);
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    pub version: u16,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8], version: u16) -> StateReader<'a> {
+        StateReader { data, pos: 0, version }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SaveStateError> {
+        let slice = self.data.get(self.pos..self.pos + len).ok_or(SaveStateError::Truncated)?;
+        self.pos += len;
+        Ok(slice)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, SaveStateError> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, SaveStateError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, SaveStateError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, SaveStateError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn bytes(&mut self, out: &mut [u8]) -> Result<(), SaveStateError> {
+        out.copy_from_slice(self.take(out.len())?);
+        Ok(())
+    }
+
+    /// Reads a length-prefixed block that must have the size of `out`.
+    pub fn block(&mut self, out: &mut [u8]) -> Result<(), SaveStateError> {
+        let len = self.u32()? as usize;
+        if len != out.len() {
+            return Err(SaveStateError::InvalidData(format!("block of {} bytes, expected {}", len, out.len())));
+        }
+        self.bytes(out)
+    }
+}
+
+/// Sections of a save state, keyed by tag.
+#[derive(Default)]
+pub struct StateFile {
+    order: Vec<[u8; 4]>,
+    sections: HashMap<[u8; 4], (u16, Vec<u8>)>,
+}
+
+impl StateFile {
+    pub fn new() -> StateFile {
+        StateFile::default()
+    }
+
+    pub fn add<T: SaveState>(&mut self, component: &T) {
+        self.add_raw(T::TAG, T::VERSION, |writer| component.save_state(writer));
+    }
+
+    pub fn add_raw(&mut self, tag: [u8; 4], version: u16, save: impl FnOnce(&mut StateWriter)) {
+        let mut writer = StateWriter::default();
+        save(&mut writer);
+        if !self.sections.contains_key(&tag) {
+            self.order.push(tag);
+        }
+        self.sections.insert(tag, (version, writer.data));
+    }
+
+    pub fn load<T: SaveState>(&self, component: &mut T) -> Result<(), SaveStateError> {
+        let mut reader = self.reader(T::TAG, T::VERSION)?;
+        component.load_state(&mut reader)
+    }
+
+    /// Reader over the section `tag`, which must not be newer than `max_version`.
+    pub fn reader(&self, tag: [u8; 4], max_version: u16) -> Result<StateReader<'_>, SaveStateError> {
+        let (version, data) = self
+            .sections
+            .get(&tag)
+            .ok_or_else(|| SaveStateError::MissingSection(tag_name(tag)))?;
+        if *version > max_version {
+            return Err(SaveStateError::UnsupportedSectionVersion(tag_name(tag), *version));
+        }
+        Ok(StateReader::new(data, *version))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        for tag in &self.order {
+            let (version, data) = &self.sections[tag];
+            out.extend_from_slice(tag);
+            out.extend_from_slice(&version.to_le_bytes());
+            out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            out.extend_from_slice(data);
+        }
+        out.extend_from_slice(&END_TAG);
+        out.extend_from_slice(&0u16.to_le_bytes());
+        out.extend_from_slice(&0u32.to_le_bytes());
+        out
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<StateFile, SaveStateError> {
+        if data.len() < 6 || &data[0..4] != MAGIC {
+            return Err(SaveStateError::InvalidMagic);
+        }
+
+        let mut reader = StateReader::new(&data[4..], 0);
+        let version = reader.u16()?;
+        if version > FORMAT_VERSION {
+            return Err(SaveStateError::UnsupportedVersion(version));
+        }
+
+        let mut file = StateFile::new();
+        loop {
+            let mut tag = [0; 4];
+            reader.bytes(&mut tag)?;
+            let section_version = reader.u16()?;
+            let len = reader.u32()? as usize;
+            if tag == END_TAG {
+                return Ok(file);
+            }
+            let payload = reader.take(len)?.to_vec();
+            file.order.push(tag);
+            file.sections.insert(tag, (section_version, payload));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Counter {
+        value: u16,
+        extra: u8,
+    }
+
+    impl SaveState for Counter {
+        const TAG: [u8; 4] = *b"TEST";
+        const VERSION: u16 = 2;
+
+        fn save_state(&self, writer: &mut StateWriter) {
+            writer.u16(self.value);
+            writer.u8(self.extra);
+        }
+
+        fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+            self.value = reader.u16()?;
+            if reader.version >= 2 {
+                self.extra = reader.u8()?;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_roundtrip_and_older_section() {
+        let mut file = StateFile::new();
+        file.add(&Counter { value: 0x1234, extra: 7 });
+        file.add_raw(*b"OLD ", 1, |writer| writer.u16(0x4321));
+        let bytes = file.to_bytes();
+
+        let file = StateFile::from_bytes(&bytes).unwrap();
+        let mut counter = Counter { value: 0, extra: 0 };
+        file.load(&mut counter).unwrap();
+        assert_eq!((counter.value, counter.extra), (0x1234, 7));
+
+        // A version 1 section predates `extra`, which keeps its default.
+        let mut counter = Counter { value: 0, extra: 9 };
+        let mut reader = file.reader(*b"OLD ", 1).unwrap();
+        counter.load_state(&mut reader).unwrap();
+        assert_eq!((counter.value, counter.extra), (0x4321, 9));
+
+        assert!(matches!(StateFile::from_bytes(b"NOPE\x01\x00"), Err(SaveStateError::InvalidMagic)));
+        assert!(matches!(file.reader(*b"MISS", 1), Err(SaveStateError::MissingSection(_))));
+    }
+}
@@ -0,0 +1,178 @@
+/*
+    Rewind snapshots are kept as groups: a keyframe followed by deltas, each delta being
+    the snapshot XORed with its keyframe. Both are compressed as a sequence of tokens:
+    2 bytes zero run (LE), 2 bytes literal count (LE), literal bytes.
+    A keyframe is stored as its delta against an empty state.
+*/
+
+use std::collections::VecDeque;
+
+const MAX_RUN: usize = 0xFFFF;
+/// Zero bytes that end a literal run, shorter runs cost less to copy than a new token.
+const MIN_ZERO_RUN: usize = 4;
+
+struct Snapshot {
+    keyframe: bool,
+    data: Vec<u8>,
+}
+
+/// Bounded ring of machine states, newest last.
+pub struct RewindBuffer {
+    snapshots: VecDeque<Snapshot>,
+    capacity: usize,
+    keyframe_interval: usize,
+    deltas_since_keyframe: usize,
+    keyframe: Option<Vec<u8>>,
+}
+
+impl RewindBuffer {
+    pub fn new(capacity: usize, keyframe_interval: usize) -> RewindBuffer {
+        RewindBuffer {
+            snapshots: VecDeque::new(),
+            capacity,
+            keyframe_interval: keyframe_interval.max(1),
+            deltas_since_keyframe: 0,
+            keyframe: None,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.deltas_since_keyframe = 0;
+        self.keyframe = None;
+    }
+
+    /// Compressed size of every snapshot in the buffer.
+    #[allow(dead_code)]
+    pub fn memory_usage(&self) -> usize {
+        self.snapshots.iter().map(|snapshot| snapshot.data.len()).sum()
+    }
+
+    pub fn push(&mut self, state: &[u8]) {
+        match self.keyframe.as_ref() {
+            Some(keyframe) if self.deltas_since_keyframe + 1 < self.keyframe_interval => {
+                let data = compress(&xor(state, keyframe));
+                self.snapshots.push_back(Snapshot { keyframe: false, data });
+                self.deltas_since_keyframe += 1;
+            }
+            _ => {
+                self.snapshots.push_back(Snapshot { keyframe: true, data: compress(state) });
+                self.keyframe = Some(state.to_vec());
+                self.deltas_since_keyframe = 0;
+            }
+        }
+
+        // Deltas can't outlive their keyframe, so the oldest group goes as a whole.
+        while self.snapshots.len() > self.capacity {
+            self.snapshots.pop_front();
+            while self.snapshots.front().is_some_and(|snapshot| !snapshot.keyframe) {
+                self.snapshots.pop_front();
+            }
+        }
+    }
+
+    /// Removes and returns the newest state.
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let snapshot = self.snapshots.pop_back()?;
+        if snapshot.keyframe {
+            // The next push starts a new group.
+            self.keyframe = None;
+            return Some(decompress(&snapshot.data));
+        }
+
+        self.deltas_since_keyframe = self.deltas_since_keyframe.saturating_sub(1);
+        let keyframe = match self.keyframe.as_ref() {
+            Some(keyframe) => keyframe.clone(),
+            None => {
+                let keyframe = self.snapshots.iter().rev().find(|snapshot| snapshot.keyframe)?;
+                let keyframe = decompress(&keyframe.data);
+                self.keyframe = Some(keyframe.clone());
+                keyframe
+            }
+        };
+        Some(xor(&decompress(&snapshot.data), &keyframe))
+    }
+}
+
+fn xor(data: &[u8], base: &[u8]) -> Vec<u8> {
+    data.iter().enumerate().map(|(i, &byte)| byte ^ base.get(i).copied().unwrap_or(0)).collect()
+}
+
+fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let zeros_start = i;
+        while i < data.len() && data[i] == 0 && i - zeros_start < MAX_RUN {
+            i += 1;
+        }
+        let literal_start = i;
+        while i < data.len() && i - literal_start < MAX_RUN && !data[i..].starts_with(&[0; MIN_ZERO_RUN]) {
+            i += 1;
+        }
+        out.extend_from_slice(&((literal_start - zeros_start) as u16).to_le_bytes());
+        out.extend_from_slice(&((i - literal_start) as u16).to_le_bytes());
+        out.extend_from_slice(&data[literal_start..i]);
+    }
+    out
+}
+
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i + 4 <= data.len() {
+        let zeros = u16::from_le_bytes([data[i], data[i + 1]]) as usize;
+        let literals = u16::from_le_bytes([data[i + 2], data[i + 3]]) as usize;
+        i += 4;
+        out.resize(out.len() + zeros, 0);
+        let end = (i + literals).min(data.len());
+        out.extend_from_slice(&data[i..end]);
+        i = end;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(seed: u8) -> Vec<u8> {
+        let mut state = vec![0; 0x3000];
+        state[0x10] = seed;
+        state[0x1234] = seed.wrapping_mul(3);
+        state[0x2FFF] = 0xAA;
+        state
+    }
+
+    #[test]
+    fn test_push_pop_and_capacity() {
+        let mut buffer = RewindBuffer::new(6, 3);
+        for seed in 0..8 {
+            buffer.push(&state(seed));
+        }
+
+        // 8 snapshots in groups of 3: the oldest group was dropped.
+        assert_eq!(buffer.len(), 5);
+        assert!(buffer.memory_usage() < 0x100);
+        for seed in (3..8).rev() {
+            assert_eq!(buffer.pop(), Some(state(seed)));
+        }
+        assert_eq!(buffer.pop(), None);
+
+        buffer.push(&state(1));
+        buffer.push(&state(2));
+        assert_eq!(buffer.pop(), Some(state(2)));
+        buffer.push(&state(9));
+        assert_eq!(buffer.pop(), Some(state(9)));
+        assert_eq!(buffer.pop(), Some(state(1)));
+    }
+}
@@ -0,0 +1,89 @@
+/*
+    RP (0xFF56), CGB infrared port:
+    Bit 0: LED on (write)
+    Bit 1: Received signal (read, 0 while light is received)
+    Bits 6-7: Both set to enable reading the signal
+    Bits 2-5 are unused and read 1.
+*/
+
+const RP_LED: u8 = 1 << 0;
+const RP_NO_SIGNAL: u8 = 1 << 1;
+const RP_READ_ENABLE: u8 = 0xC0;
+const RP_UNUSED_BITS: u8 = 0x3C;
+
+/// Number of LED samples exchanged per sync point.
+pub const IR_SLOTS: u32 = 64;
+
+/// The LED is sampled into `IR_SLOTS` slots per sync quantum. At each sync point the samples
+/// are exchanged over the link cable and the peer's are replayed during the next quantum, so
+/// light arrives one quantum late on both sides.
+pub struct Infrared {
+    rp: u8,
+    outgoing: u64,
+    incoming: u64,
+    slot: u32,
+}
+
+impl Infrared {
+    pub fn new() -> Infrared {
+        Infrared {
+            rp: 0,
+            outgoing: 0,
+            incoming: 0,
+            slot: 0,
+        }
+    }
+
+    pub fn get_rp(&self) -> u8 {
+        let receiving = (self.rp & RP_READ_ENABLE) == RP_READ_ENABLE && self.receiving();
+        let signal = match receiving {
+            true => 0,
+            false => RP_NO_SIGNAL,
+        };
+        RP_UNUSED_BITS | (self.rp & (RP_READ_ENABLE | RP_LED)) | signal
+    }
+
+    pub fn set_rp(&mut self, data: u8) {
+        self.rp = data & (RP_READ_ENABLE | RP_LED);
+        self.sample();
+    }
+
+    fn led_on(&self) -> bool {
+        (self.rp & RP_LED) != 0
+    }
+
+    fn receiving(&self) -> bool {
+        (self.incoming >> self.slot) & 1 != 0
+    }
+
+    fn sample(&mut self) {
+        if self.led_on() {
+            self.outgoing |= 1 << self.slot;
+        }
+    }
+
+    /// Moves to the slot for `cycles` T-cycles into a quantum of `quantum` T-cycles.
+    pub fn advance(&mut self, cycles: u32, quantum: u32) {
+        let slot = (cycles * IR_SLOTS / quantum).min(IR_SLOTS - 1);
+        // The LED only changes on RP writes, every slot since the last call saw the same level.
+        while self.slot < slot {
+            self.slot += 1;
+            self.sample();
+        }
+        self.slot = slot;
+        self.sample();
+    }
+
+    /// Samples sent to the peer for the quantum that just ended.
+    pub fn take_outgoing(&mut self) -> u64 {
+        let outgoing = self.outgoing;
+        self.slot = 0;
+        self.outgoing = 0;
+        self.sample();
+        outgoing
+    }
+
+    pub fn set_incoming(&mut self, samples: u64) {
+        self.incoming = samples;
+    }
+}
@@ -0,0 +1,174 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+/// T-cycles between two sync points. Both instances exchange a packet every
+/// `SYNC_QUANTUM` cycles of emulated time, whatever the host timing.
+pub const SYNC_QUANTUM: u32 = 2048;
+
+#[derive(Debug)]
+pub enum LinkError {
+    Disconnected,
+    InvalidAddress(String),
+    IoError(std::io::Error),
+}
+
+impl From<std::io::Error> for LinkError {
+    fn from(e: std::io::Error) -> LinkError {
+        LinkError::IoError(e)
+    }
+}
+
+/// Serial state published by one side of the cable at a sync point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkPacket {
+    /// Byte of an internally clocked transfer waiting for the peer's byte.
+    pub transfer: Option<u8>,
+    /// Current contents of SB.
+    pub sb: u8,
+    /// An externally clocked transfer is waiting for the peer's clock.
+    pub armed: bool,
+    /// Infrared LED samples over the quantum that just ended, see `Infrared`.
+    pub ir: u64,
+}
+
+impl LinkPacket {
+    const SIZE: usize = 11;
+
+    pub fn to_bytes(self) -> [u8; LinkPacket::SIZE] {
+        let flags = (self.transfer.is_some() as u8) | ((self.armed as u8) << 1);
+        let mut bytes = [0; LinkPacket::SIZE];
+        bytes[..3].copy_from_slice(&[flags, self.transfer.unwrap_or(0), self.sb]);
+        bytes[3..].copy_from_slice(&self.ir.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: [u8; LinkPacket::SIZE]) -> LinkPacket {
+        LinkPacket {
+            transfer: match (bytes[0] & 0x01) != 0 {
+                true => Some(bytes[1]),
+                false => None,
+            },
+            sb: bytes[2],
+            armed: (bytes[0] & 0x02) != 0,
+            ir: u64::from_le_bytes(bytes[3..].try_into().unwrap()),
+        }
+    }
+}
+
+pub trait LinkCable: Send {
+    /// Sends the local packet and blocks until the peer's packet for the same sync point arrives.
+    fn sync(&mut self, local: LinkPacket) -> Result<LinkPacket, LinkError>;
+
+    /// T-cycles between two calls to `sync`. Peripherals that answer immediately can sync more often.
+    fn quantum(&self) -> u32 {
+        SYNC_QUANTUM
+    }
+}
+
+/// Cable between two emulators in the same process. Each instance must run on its own thread,
+/// since `sync` blocks until the other side reaches the same point.
+pub struct LoopbackCable {
+    tx: Sender<LinkPacket>,
+    rx: Receiver<LinkPacket>,
+}
+
+impl LoopbackCable {
+    pub fn pair() -> (LoopbackCable, LoopbackCable) {
+        let (tx_a, rx_b) = channel();
+        let (tx_b, rx_a) = channel();
+        (
+            LoopbackCable { tx: tx_a, rx: rx_a },
+            LoopbackCable { tx: tx_b, rx: rx_b },
+        )
+    }
+}
+
+impl LinkCable for LoopbackCable {
+    fn sync(&mut self, local: LinkPacket) -> Result<LinkPacket, LinkError> {
+        self.tx.send(local).map_err(|_| LinkError::Disconnected)?;
+        self.rx.recv().map_err(|_| LinkError::Disconnected)
+    }
+}
+
+/// Cable to another process over a stream socket.
+pub struct SocketCable<S: Read + Write + Send> {
+    stream: S,
+}
+
+impl<S: Read + Write + Send> SocketCable<S> {
+    pub fn new(stream: S) -> SocketCable<S> {
+        SocketCable { stream }
+    }
+}
+
+impl<S: Read + Write + Send> LinkCable for SocketCable<S> {
+    fn sync(&mut self, local: LinkPacket) -> Result<LinkPacket, LinkError> {
+        self.stream.write_all(&local.to_bytes())?;
+        self.stream.flush()?;
+
+        let mut bytes = [0; LinkPacket::SIZE];
+        match self.stream.read_exact(&mut bytes) {
+            Ok(_) => Ok(LinkPacket::from_bytes(bytes)),
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Err(LinkError::Disconnected),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Where to find the other instance: `unix:<path>` for a Unix domain socket,
+/// anything else is taken as a TCP `host:port`.
+pub enum LinkAddress {
+    Tcp(String),
+    #[cfg(unix)]
+    Unix(String),
+}
+
+impl LinkAddress {
+    pub fn parse(address: &str) -> Result<LinkAddress, LinkError> {
+        if let Some(path) = address.strip_prefix("unix:") {
+            #[cfg(unix)]
+            return Ok(LinkAddress::Unix(path.to_string()));
+            #[cfg(not(unix))]
+            return Err(LinkError::InvalidAddress(path.to_string()));
+        }
+
+        let address = address.strip_prefix("tcp:").unwrap_or(address);
+        if !address.contains(':') {
+            return Err(LinkError::InvalidAddress(address.to_string()));
+        }
+
+        Ok(LinkAddress::Tcp(address.to_string()))
+    }
+
+    /// Waits for the other instance to connect.
+    pub fn listen(&self) -> Result<Box<dyn LinkCable>, LinkError> {
+        match self {
+            LinkAddress::Tcp(address) => {
+                let (stream, _) = TcpListener::bind(address)?.accept()?;
+                stream.set_nodelay(true)?;
+                Ok(Box::new(SocketCable::new(stream)))
+            }
+            #[cfg(unix)]
+            LinkAddress::Unix(path) => {
+                let _ = std::fs::remove_file(path);
+                let (stream, _) = UnixListener::bind(path)?.accept()?;
+                Ok(Box::new(SocketCable::new(stream)))
+            }
+        }
+    }
+
+    pub fn connect(&self) -> Result<Box<dyn LinkCable>, LinkError> {
+        match self {
+            LinkAddress::Tcp(address) => {
+                let stream = TcpStream::connect(address)?;
+                stream.set_nodelay(true)?;
+                Ok(Box::new(SocketCable::new(stream)))
+            }
+            #[cfg(unix)]
+            LinkAddress::Unix(path) => Ok(Box::new(SocketCable::new(UnixStream::connect(path)?))),
+        }
+    }
+}
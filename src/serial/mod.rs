@@ -0,0 +1,429 @@
+use crate::cpu::interrupts::{IFlagsRegister, InterruptType};
+use crate::debug::log::{Logger, LoggerTrait};
+use crate::emulator::Model;
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+use crate::serial::infrared::Infrared;
+use crate::serial::link::{LinkCable, LinkPacket};
+
+pub mod infrared;
+pub mod link;
+pub mod printer;
+
+const SC_TRANSFER_START: u8 = 1 << 7;
+const SC_FAST_CLOCK: u8 = 1 << 1;
+const SC_INTERNAL_CLOCK: u8 = 1 << 0;
+const SC_UNUSED_BITS: u8 = 0x7C;
+
+/// Internal clock: one bit every 512 T-cycles (8192 Hz), taken from the falling edge of DIV bit 8.
+const NORMAL_CLOCK_DIV_BIT: u16 = 1 << 8;
+/// CGB fast clock: one bit every 16 T-cycles (262144 Hz), DIV bit 3.
+const FAST_CLOCK_DIV_BIT: u16 = 1 << 3;
+
+/// Bytes of serial output kept for `message`, the oldest half is dropped when it fills up.
+const MESSAGE_LIMIT: usize = 4096;
+
+/// Byte shifted in when nothing drives the serial input line.
+pub const DISCONNECTED_BYTE: u8 = 0xFF;
+
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+    bits_remaining: u8,
+    incoming: u8,
+    outgoing: u8,
+    last_div: u16,
+    /// The fast clock bit only exists on CGB models.
+    pub model: Model,
+    /// The most recent output, up to `MESSAGE_LIMIT` bytes.
+    pub message: String,
+    cable: Option<Box<dyn LinkCable>>,
+    link_cycles: u32,
+    link_pending: bool,
+    /// The CGB infrared port shares the link cable's sync points.
+    pub infrared: Infrared,
+}
+
+impl Serial {
+    pub fn new() -> Serial {
+        Serial {
+            sb: 0,
+            sc: 0,
+            bits_remaining: 0,
+            incoming: DISCONNECTED_BYTE,
+            outgoing: 0,
+            last_div: 0,
+            model: Model::Dmg,
+            message: String::new(),
+            cable: None,
+            link_cycles: 0,
+            link_pending: false,
+            infrared: Infrared::new(),
+        }
+    }
+
+    pub fn attach_cable(&mut self, cable: Box<dyn LinkCable>) {
+        self.cable = Some(cable);
+        self.link_cycles = 0;
+        self.link_pending = false;
+    }
+
+    pub fn has_cable(&self) -> bool {
+        self.cable.is_some()
+    }
+
+    #[allow(dead_code)]
+    pub fn detach_cable(&mut self) {
+        self.cable = None;
+        self.link_pending = false;
+        self.infrared.set_incoming(0);
+    }
+
+    pub fn get_sb(&self) -> u8 {
+        self.sb
+    }
+
+    pub fn get_sc(&self) -> u8 {
+        self.sc | self.unused_bits()
+    }
+
+    fn unused_bits(&self) -> u8 {
+        match self.model.is_cgb() {
+            true => SC_UNUSED_BITS,
+            false => SC_UNUSED_BITS | SC_FAST_CLOCK,
+        }
+    }
+
+    pub fn set_sb(&mut self, data: u8) {
+        self.sb = data;
+    }
+
+    pub fn set_sc(&mut self, data: u8) {
+        self.sc = data & !self.unused_bits();
+        if !self.transferring() {
+            self.bits_remaining = 0;
+            return;
+        }
+
+        self.bits_remaining = 8;
+        self.outgoing = self.sb;
+        self.incoming = DISCONNECTED_BYTE;
+        // With a cable attached the peer's byte is only known at the next sync point,
+        // the internal clock holds off until then.
+        self.link_pending = self.internal_clock() && self.cable.is_some();
+    }
+
+    pub fn transferring(&self) -> bool {
+        (self.sc & SC_TRANSFER_START) != 0
+    }
+
+    pub fn internal_clock(&self) -> bool {
+        (self.sc & SC_INTERNAL_CLOCK) != 0
+    }
+
+    fn clock_div_bit(&self) -> u16 {
+        match (self.sc & SC_FAST_CLOCK) != 0 {
+            true => FAST_CLOCK_DIV_BIT,
+            false => NORMAL_CLOCK_DIV_BIT,
+        }
+    }
+
+    /// Called once per M-cycle with the current DIV counter. Shifts one bit on each
+    /// falling edge of the serial clock while an internally clocked transfer is running.
+    pub fn serial_tick(&mut self, div: u16, int_flags: &mut IFlagsRegister) {
+        if let Some(cable) = self.cable.as_ref() {
+            let quantum = cable.quantum();
+            self.link_cycles += 4;
+            if self.link_cycles >= quantum {
+                self.link_cycles -= quantum;
+                self.link_sync(int_flags);
+            } else {
+                self.infrared.advance(self.link_cycles, quantum);
+            }
+        }
+
+        let bit = self.clock_div_bit();
+        let falling_edge = (self.last_div & bit) != 0 && (div & bit) == 0;
+        self.last_div = div;
+
+        if !falling_edge || !self.transferring() || !self.internal_clock() || self.link_pending {
+            return;
+        }
+
+        self.shift_bit(int_flags);
+    }
+
+    /// Runs `cycles` T-cycles that ended with the DIV counter at `div`. Without a cable or
+    /// a running transfer nothing happens in between, only the last DIV value is kept.
+    pub fn advance(&mut self, cycles: u32, div: u16, int_flags: &mut IFlagsRegister) {
+        let clocking = self.transferring() && self.internal_clock();
+        if self.cable.is_none() && !clocking {
+            self.last_div = div;
+            return;
+        }
+
+        for step in (0..cycles / 4).rev() {
+            self.serial_tick(div.wrapping_sub((step * 4) as u16), int_flags);
+        }
+    }
+
+    /// T-cycles until the next link sync point or shifted bit.
+    pub fn ticks_to_event(&self) -> u32 {
+        let link = match self.cable.as_ref() {
+            Some(cable) => cable.quantum().saturating_sub(self.link_cycles).max(4),
+            None => u32::MAX,
+        };
+        if !self.transferring() || !self.internal_clock() || self.link_pending {
+            return link;
+        }
+
+        let period = (self.clock_div_bit() as u32) << 1;
+        link.min(period - (self.last_div as u32 % period))
+    }
+
+    /// Exchanges state with the peer. Both sides apply the same rules to the same pair of
+    /// packets, so the outcome only depends on emulated time.
+    fn link_sync(&mut self, int_flags: &mut IFlagsRegister) {
+        let armed = self.transferring() && !self.internal_clock();
+        let local = LinkPacket {
+            transfer: match self.link_pending {
+                true => Some(self.outgoing),
+                false => None,
+            },
+            sb: self.sb,
+            armed,
+            ir: self.infrared.take_outgoing(),
+        };
+
+        let peer = match self.cable.as_mut().unwrap().sync(local) {
+            Ok(peer) => peer,
+            Err(e) => {
+                Logger::log(format!("Link cable disconnected: {:?} \n", e));
+                self.cable = None;
+                self.link_pending = false;
+                self.infrared.set_incoming(0);
+                return;
+            }
+        };
+
+        self.infrared.set_incoming(peer.ir);
+
+        if let Some(byte) = peer.transfer {
+            if armed {
+                self.external_clock_transfer(byte, int_flags);
+            }
+        }
+
+        if self.link_pending {
+            self.incoming = match peer.armed && peer.transfer.is_none() {
+                true => peer.sb,
+                false => DISCONNECTED_BYTE,
+            };
+            self.link_pending = false;
+        }
+    }
+
+    fn shift_bit(&mut self, int_flags: &mut IFlagsRegister) {
+        if self.bits_remaining == 0 {
+            return;
+        }
+
+        self.bits_remaining -= 1;
+        let in_bit = (self.incoming >> self.bits_remaining) & 1;
+        self.sb = (self.sb << 1) | in_bit;
+
+        if self.bits_remaining == 0 {
+            self.complete_transfer(int_flags);
+        }
+    }
+
+    fn complete_transfer(&mut self, int_flags: &mut IFlagsRegister) {
+        self.sc &= !SC_TRANSFER_START;
+        int_flags.add_interrupt(InterruptType::Serial);
+
+        if self.message.len() >= MESSAGE_LIMIT {
+            let mut cut = self.message.len() - MESSAGE_LIMIT / 2;
+            while !self.message.is_char_boundary(cut) {
+                cut += 1;
+            }
+            self.message.drain(..cut);
+        }
+        self.message.push(self.outgoing as char);
+        Logger::log(format!("Serial byte: {:02X} \n", self.outgoing));
+    }
+
+    /// Completes a transfer clocked by the other side of the link: `incoming` is shifted
+    /// in and the byte that was in SB is returned. Returns `None` when no externally
+    /// clocked transfer is waiting.
+    pub fn external_clock_transfer(&mut self, incoming: u8, int_flags: &mut IFlagsRegister) -> Option<u8> {
+        if !self.transferring() || self.internal_clock() {
+            return None;
+        }
+
+        let outgoing = self.sb;
+        self.outgoing = outgoing;
+        self.sb = incoming;
+        self.bits_remaining = 0;
+        self.complete_transfer(int_flags);
+        Some(outgoing)
+    }
+}
+
+/// The cable itself is not part of the state, transfers waiting on a peer restart
+/// the handshake at the next sync point.
+impl SaveState for Serial {
+    const TAG: [u8; 4] = *b"SERL";
+    const VERSION: u16 = 1;
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.bytes(&[self.sb, self.sc, self.bits_remaining, self.incoming, self.outgoing]);
+        writer.u16(self.last_div);
+        writer.u8(self.infrared.get_rp());
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.sb = reader.u8()?;
+        self.sc = reader.u8()? & !self.unused_bits();
+        self.bits_remaining = reader.u8()?.min(8);
+        self.incoming = reader.u8()?;
+        self.outgoing = reader.u8()?;
+        self.last_div = reader.u16()?;
+        self.infrared.set_rp(reader.u8()?);
+        self.link_pending = self.transferring() && self.internal_clock() && self.cable.is_some();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serial::link::{LoopbackCable, SYNC_QUANTUM};
+    use std::thread;
+
+    fn run(serial: &mut Serial, int_flags: &mut IFlagsRegister, cycles: u32) {
+        let mut div: u16 = 0;
+        for _ in 0..cycles / 4 {
+            div = div.wrapping_add(4);
+            serial.serial_tick(div, int_flags);
+        }
+    }
+
+    #[test]
+    fn test_internal_clock_without_peer_shifts_in_ff() {
+        let mut int_flags = IFlagsRegister::new();
+        let mut serial = Serial::new();
+        serial.set_sb(0x42);
+        serial.set_sc(0x81);
+
+        run(&mut serial, &mut int_flags, 8 * 512 - 4);
+        assert!(serial.transferring());
+
+        run(&mut serial, &mut int_flags, 512);
+        assert!(!serial.transferring());
+        assert_eq!(serial.get_sb(), 0xFF);
+        assert!(int_flags.has_interrupt(InterruptType::Serial));
+    }
+
+    #[test]
+    fn test_fast_clock_shifts_every_16_cycles() {
+        let mut int_flags = IFlagsRegister::new();
+        let mut serial = Serial::new();
+        // DMG models ignore the bit.
+        serial.set_sc(0x02);
+        assert_eq!(serial.get_sc(), 0x7E);
+
+        serial.model = Model::Cgb;
+        serial.set_sb(0x42);
+        serial.set_sc(0x83);
+
+        run(&mut serial, &mut int_flags, 8 * 16 - 4);
+        assert!(serial.transferring());
+
+        run(&mut serial, &mut int_flags, 16);
+        assert!(!serial.transferring());
+        assert_eq!(serial.get_sb(), 0xFF);
+        assert_eq!(int_flags.int_flags & 0x08, 0x08);
+    }
+
+    #[test]
+    fn test_external_clock_waits_for_peer() {
+        let mut int_flags = IFlagsRegister::new();
+        let mut serial = Serial::new();
+        serial.set_sb(0x42);
+        serial.set_sc(0x80);
+
+        // Nothing clocks the transfer without a peer.
+        run(&mut serial, &mut int_flags, 4 * 8 * 512);
+        assert!(serial.transferring());
+        assert_eq!(serial.get_sb(), 0x42);
+        assert_eq!(int_flags.int_flags & 0x08, 0);
+
+        assert_eq!(serial.external_clock_transfer(0x5A, &mut int_flags), Some(0x42));
+        assert!(!serial.transferring());
+        assert_eq!(serial.get_sb(), 0x5A);
+        assert_eq!(int_flags.int_flags & 0x08, 0x08);
+
+        // Transfers clocked by this side ignore the peer's clock.
+        serial.set_sc(0x81);
+        assert_eq!(serial.external_clock_transfer(0x00, &mut int_flags), None);
+    }
+
+    #[test]
+    fn test_message_keeps_recent_output() {
+        let mut int_flags = IFlagsRegister::new();
+        let mut serial = Serial::new();
+        for i in 0..MESSAGE_LIMIT + 10 {
+            serial.set_sb(if i % 2 == 0 { b'a' } else { 0xE9 });
+            serial.set_sc(0x80);
+            serial.external_clock_transfer(0xFF, &mut int_flags);
+        }
+        assert!(serial.message.len() <= MESSAGE_LIMIT);
+        assert!(serial.message.ends_with("a\u{E9}"));
+    }
+
+    #[test]
+    fn test_loopback_exchanges_bytes() {
+        let (cable_a, cable_b) = LoopbackCable::pair();
+
+        let slave = thread::spawn(move || {
+            let mut serial = Serial::new();
+            serial.attach_cable(Box::new(cable_b));
+            serial.set_sb(0x99);
+            serial.set_sc(0x80);
+            run(&mut serial, &mut IFlagsRegister::new(), 8 * SYNC_QUANTUM);
+            serial.get_sb()
+        });
+
+        let mut master = Serial::new();
+        master.attach_cable(Box::new(cable_a));
+        master.set_sb(0x42);
+        master.set_sc(0x81);
+        run(&mut master, &mut IFlagsRegister::new(), 8 * SYNC_QUANTUM);
+
+        assert_eq!(master.get_sb(), 0x99);
+        assert_eq!(slave.join().unwrap(), 0x42);
+    }
+
+    #[test]
+    fn test_infrared_light_reaches_peer() {
+        let (cable_a, cable_b) = LoopbackCable::pair();
+
+        let receiver = thread::spawn(move || {
+            let mut serial = Serial::new();
+            serial.attach_cable(Box::new(cable_b));
+            serial.infrared.set_rp(0xC0);
+            run(&mut serial, &mut IFlagsRegister::new(), SYNC_QUANTUM - 4);
+            let before = serial.infrared.get_rp();
+            run(&mut serial, &mut IFlagsRegister::new(), SYNC_QUANTUM / 2 + 4);
+            (before, serial.infrared.get_rp())
+        });
+
+        let mut sender = Serial::new();
+        sender.attach_cable(Box::new(cable_a));
+        sender.infrared.set_rp(0x01);
+        run(&mut sender, &mut IFlagsRegister::new(), SYNC_QUANTUM * 3 / 2);
+
+        let (before, after) = receiver.join().unwrap();
+        assert_eq!(before & 0x02, 0x02);
+        assert_eq!(after & 0x02, 0x00);
+    }
+}
@@ -0,0 +1,346 @@
+/*
+    Game Boy Printer packet, sent by the Game Boy as the clock master:
+    0x88 0x33          Magic bytes
+    command            0x01 init, 0x02 print, 0x04 data, 0x0F status
+    compression        1 when the data is RLE compressed
+    length (LE)        Number of data bytes that follow
+    data               Up to 640 bytes (two rows of 20 tiles)
+    checksum (LE)      16 bit sum of every byte from the command to the end of the data
+    0x00 0x00          The printer answers 0x81 (alive) and its status byte
+*/
+
+use std::path::PathBuf;
+use crate::debug::log::{Logger, LoggerTrait};
+use crate::serial::link::{LinkCable, LinkError, LinkPacket};
+use crate::util::png;
+
+const MAGIC_1: u8 = 0x88;
+const MAGIC_2: u8 = 0x33;
+const ALIVE: u8 = 0x81;
+
+const CMD_INIT: u8 = 0x01;
+const CMD_PRINT: u8 = 0x02;
+const CMD_DATA: u8 = 0x04;
+const CMD_STATUS: u8 = 0x0F;
+
+const STATUS_CHECKSUM_ERROR: u8 = 1 << 0;
+const STATUS_BUSY: u8 = 1 << 1;
+const STATUS_FULL: u8 = 1 << 2;
+const STATUS_UNPROCESSED: u8 = 1 << 3;
+const STATUS_PACKET_ERROR: u8 = 1 << 4;
+
+pub const PRINT_WIDTH: usize = 160;
+const TILES_PER_ROW: usize = PRINT_WIDTH / 8;
+const TILE_BYTES: usize = 16;
+const BUFFER_SIZE: usize = 0x2000;
+/// Pixel rows fed per unit of the print command's margins.
+const MARGIN_UNIT_ROWS: usize = 16;
+/// Status polls answered as busy after a print command.
+const PRINT_BUSY_POLLS: u8 = 4;
+const DEFAULT_PALETTE: u8 = 0xE4;
+const SHADES: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PacketState {
+    Magic1,
+    Magic2,
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    Alive,
+    Status,
+}
+
+/// Game Boy Printer attached to the serial port. Every finished print, ending with a
+/// non-zero bottom margin, is written to `<output_dir>/print_NNNN.png`.
+pub struct Printer {
+    output_dir: PathBuf,
+    state: PacketState,
+    command: u8,
+    compressed: bool,
+    length: u16,
+    data: Vec<u8>,
+    checksum: u16,
+    received_checksum: u16,
+    status: u8,
+    busy_polls: u8,
+    buffer: Vec<u8>,
+    /// Grey levels of the print in progress, `PRINT_WIDTH` pixels per row.
+    sheet: Vec<u8>,
+    print_count: u32,
+}
+
+impl Printer {
+    pub fn new(output_dir: &str) -> Printer {
+        Printer {
+            output_dir: PathBuf::from(output_dir),
+            state: PacketState::Magic1,
+            command: 0,
+            compressed: false,
+            length: 0,
+            data: Vec::new(),
+            checksum: 0,
+            received_checksum: 0,
+            status: 0,
+            busy_polls: 0,
+            buffer: Vec::new(),
+            sheet: Vec::new(),
+            print_count: 0,
+        }
+    }
+
+    /// Handles one byte sent by the Game Boy and returns the byte shifted back.
+    pub fn transfer(&mut self, byte: u8) -> u8 {
+        let mut reply = 0x00;
+        self.state = match self.state {
+            PacketState::Magic1 if byte == MAGIC_1 => PacketState::Magic2,
+            PacketState::Magic1 => PacketState::Magic1,
+            PacketState::Magic2 if byte == MAGIC_2 => PacketState::Command,
+            PacketState::Magic2 => PacketState::Magic1,
+            PacketState::Command => {
+                self.command = byte;
+                self.checksum = byte as u16;
+                self.data.clear();
+                PacketState::Compression
+            }
+            PacketState::Compression => {
+                self.compressed = (byte & 0x01) != 0;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                PacketState::LengthLow
+            }
+            PacketState::LengthLow => {
+                self.length = byte as u16;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                PacketState::LengthHigh
+            }
+            PacketState::LengthHigh => {
+                self.length |= (byte as u16) << 8;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                match self.length {
+                    0 => PacketState::ChecksumLow,
+                    _ => PacketState::Data,
+                }
+            }
+            PacketState::Data => {
+                self.data.push(byte);
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                match self.data.len() == self.length as usize {
+                    true => PacketState::ChecksumLow,
+                    false => PacketState::Data,
+                }
+            }
+            PacketState::ChecksumLow => {
+                self.received_checksum = byte as u16;
+                PacketState::ChecksumHigh
+            }
+            PacketState::ChecksumHigh => {
+                self.received_checksum |= (byte as u16) << 8;
+                PacketState::Alive
+            }
+            PacketState::Alive => {
+                reply = ALIVE;
+                self.execute();
+                PacketState::Status
+            }
+            PacketState::Status => {
+                reply = self.status;
+                PacketState::Magic1
+            }
+        };
+        reply
+    }
+
+    fn execute(&mut self) {
+        if self.received_checksum != self.checksum {
+            self.status |= STATUS_CHECKSUM_ERROR;
+            return;
+        }
+        self.status &= !(STATUS_CHECKSUM_ERROR | STATUS_PACKET_ERROR);
+
+        match self.command {
+            CMD_INIT => {
+                self.buffer.clear();
+                self.busy_polls = 0;
+                self.status = 0;
+            }
+            CMD_DATA => self.receive_data(),
+            CMD_PRINT => self.print(),
+            CMD_STATUS => {
+                if self.busy_polls > 0 {
+                    self.busy_polls -= 1;
+                    if self.busy_polls == 0 {
+                        self.status &= !STATUS_BUSY;
+                    }
+                }
+            }
+            _ => self.status |= STATUS_PACKET_ERROR,
+        }
+    }
+
+    fn receive_data(&mut self) {
+        // An empty data packet marks the end of the image.
+        if self.data.is_empty() {
+            self.status |= STATUS_FULL;
+            return;
+        }
+
+        let data = match self.compressed {
+            true => Self::decompress(&self.data),
+            false => self.data.clone(),
+        };
+        let room = BUFFER_SIZE - self.buffer.len();
+        self.buffer.extend_from_slice(&data[..data.len().min(room)]);
+        self.status |= STATUS_UNPROCESSED;
+    }
+
+    /// Control bytes with bit 7 set repeat the next byte `(n & 0x7F) + 2` times,
+    /// otherwise the next `n + 1` bytes are copied as they are.
+    fn decompress(data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut i = 0;
+        while i < data.len() {
+            let control = data[i];
+            i += 1;
+            if (control & 0x80) != 0 {
+                let count = (control & 0x7F) as usize + 2;
+                if let Some(&byte) = data.get(i) {
+                    out.extend(std::iter::repeat_n(byte, count));
+                }
+                i += 1;
+            } else {
+                let count = control as usize + 1;
+                let end = (i + count).min(data.len());
+                out.extend_from_slice(&data[i..end]);
+                i = end;
+            }
+        }
+        out
+    }
+
+    fn print(&mut self) {
+        let (sheets, margins, palette) = match self.data.as_slice() {
+            [sheets, margins, palette, _exposure, ..] => (*sheets, *margins, *palette),
+            _ => {
+                self.status |= STATUS_PACKET_ERROR;
+                return;
+            }
+        };
+        let palette = match palette {
+            0 => DEFAULT_PALETTE,
+            palette => palette,
+        };
+
+        self.feed((margins >> 4) as usize);
+        // Zero sheets only feeds paper.
+        if sheets > 0 {
+            self.render_buffer(palette);
+        }
+        self.feed((margins & 0x0F) as usize);
+
+        self.buffer.clear();
+        self.status = STATUS_BUSY;
+        self.busy_polls = PRINT_BUSY_POLLS;
+
+        // Prints without a bottom margin are continued by the next print command.
+        if (margins & 0x0F) != 0 {
+            self.finish_sheet();
+        }
+    }
+
+    fn feed(&mut self, units: usize) {
+        let rows = units * MARGIN_UNIT_ROWS;
+        self.sheet.extend(std::iter::repeat_n(SHADES[0], rows * PRINT_WIDTH));
+    }
+
+    fn render_buffer(&mut self, palette: u8) {
+        let rows = self.buffer.len() / (TILES_PER_ROW * TILE_BYTES) * 8;
+        for y in 0..rows {
+            for x in 0..PRINT_WIDTH {
+                let tile = (y / 8) * TILES_PER_ROW + x / 8;
+                let offset = tile * TILE_BYTES + (y % 8) * 2;
+                let bit = 7 - (x % 8);
+                let lo = (self.buffer[offset] >> bit) & 1;
+                let hi = (self.buffer[offset + 1] >> bit) & 1;
+                let color = (hi << 1) | lo;
+                let shade = (palette >> (color * 2)) & 0x03;
+                self.sheet.push(SHADES[shade as usize]);
+            }
+        }
+    }
+
+    fn finish_sheet(&mut self) {
+        if self.sheet.is_empty() {
+            return;
+        }
+
+        let sheet = std::mem::take(&mut self.sheet);
+        self.print_count += 1;
+        let path = self.output_dir.join(format!("print_{:04}.png", self.print_count));
+        let rgb: Vec<u8> = sheet.iter().flat_map(|&shade| [shade; 3]).collect();
+        let height = (sheet.len() / PRINT_WIDTH) as u32;
+
+        match png::save_rgb(&path.to_string_lossy(), PRINT_WIDTH as u32, height, &rgb) {
+            Ok(_) => Logger::log(format!("Printed {} \n", path.display())),
+            Err(e) => Logger::log(format!("Failed to save print {}: {:?} \n", path.display(), e)),
+        }
+    }
+}
+
+impl LinkCable for Printer {
+    fn sync(&mut self, local: LinkPacket) -> Result<LinkPacket, LinkError> {
+        // The printer never drives the clock, it answers each byte as soon as it is sent.
+        let sb = match local.transfer {
+            Some(byte) => self.transfer(byte),
+            None => 0x00,
+        };
+        Ok(LinkPacket { transfer: None, sb, armed: true, ir: 0 })
+    }
+
+    fn quantum(&self) -> u32 {
+        4
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send_packet(printer: &mut Printer, command: u8, compressed: bool, data: &[u8]) -> (u8, u8) {
+        let length = (data.len() as u16).to_le_bytes();
+        let mut body = vec![command, compressed as u8, length[0], length[1]];
+        body.extend_from_slice(data);
+        let checksum = body.iter().fold(0u16, |sum, &b| sum.wrapping_add(b as u16)).to_le_bytes();
+
+        for &byte in [MAGIC_1, MAGIC_2].iter().chain(body.iter()).chain(checksum.iter()) {
+            assert_eq!(printer.transfer(byte), 0x00);
+        }
+        (printer.transfer(0x00), printer.transfer(0x00))
+    }
+
+    #[test]
+    fn test_packets_and_status_replies() {
+        let dir = std::env::temp_dir().join("gb_printer_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut printer = Printer::new(&dir.to_string_lossy());
+
+        assert_eq!(send_packet(&mut printer, CMD_INIT, false, &[]), (ALIVE, 0x00));
+
+        // 640 bytes of 0xFF compressed as runs of 128 bytes.
+        let data = [0xFE, 0xFF, 0xFE, 0xFF, 0xFE, 0xFF, 0xFE, 0xFF, 0xFE, 0xFF];
+        assert_eq!(send_packet(&mut printer, CMD_DATA, true, &data), (ALIVE, STATUS_UNPROCESSED));
+        assert_eq!(printer.buffer.len(), 640);
+
+        assert_eq!(send_packet(&mut printer, CMD_PRINT, false, &[1, 0x01, 0xE4, 0x40]), (ALIVE, STATUS_BUSY));
+        assert!(printer.sheet.is_empty());
+        assert!(dir.join("print_0001.png").exists());
+
+        for _ in 1..PRINT_BUSY_POLLS {
+            assert_eq!(send_packet(&mut printer, CMD_STATUS, false, &[]), (ALIVE, STATUS_BUSY));
+        }
+        assert_eq!(send_packet(&mut printer, CMD_STATUS, false, &[]), (ALIVE, 0x00));
+    }
+}
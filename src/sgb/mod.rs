@@ -0,0 +1,628 @@
+/*
+    SGB command packets, sent through P1 (0xFF00) bits 5-4:
+    00: reset, starts a packet
+    10: a 0 bit (P14 low)
+    01: a 1 bit (P15 low)
+    11: idle between bits
+    A packet is 16 bytes sent LSB first, followed by a 0 stop bit. The first byte of a
+    command is command << 3 | number of packets (1-7).
+
+    *_TRN commands read 4 KiB from the screen on the next frame: the first 256 tiles of
+    the background map, 20 per row.
+
+    Output, 256x224:
+    0x00 - 0xFF x 0x00 - 0xDF: border from CHR_TRN tiles and the PCT_TRN map
+    0x30 - 0xCF x 0x28 - 0xB7: game screen, each 8x8 cell coloured with its palette
+*/
+
+use crate::ppu::{PPU, XRES, YRES};
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+
+pub const SGB_WIDTH: u32 = 256;
+pub const SGB_HEIGHT: u32 = 224;
+
+const GAME_X: usize = 48;
+const GAME_Y: usize = 40;
+const PACKET_SIZE: usize = 16;
+const CELLS_X: usize = 20;
+const CELLS_Y: usize = 18;
+const ATTR_FILE_SIZE: usize = 90;
+const ATTR_FILES: usize = 45;
+const SYSTEM_PALETTES: usize = 512;
+const BORDER_TILES: usize = 256;
+const BORDER_MAP_SIZE: usize = 32 * 32;
+
+const PAL01: u8 = 0x00;
+const PAL23: u8 = 0x01;
+const PAL03: u8 = 0x02;
+const PAL12: u8 = 0x03;
+const ATTR_BLK: u8 = 0x04;
+const ATTR_LIN: u8 = 0x05;
+const ATTR_DIV: u8 = 0x06;
+const ATTR_CHR: u8 = 0x07;
+const PAL_SET: u8 = 0x0A;
+const PAL_TRN: u8 = 0x0B;
+const MLT_REQ: u8 = 0x11;
+const CHR_TRN: u8 = 0x13;
+const PCT_TRN: u8 = 0x14;
+const ATTR_TRN: u8 = 0x15;
+const ATTR_SET: u8 = 0x16;
+const MASK_EN: u8 = 0x17;
+
+/// DMG greys as BGR555, until the game sends its own palettes.
+const DEFAULT_PALETTE: [u16; 4] = [0x7FFF, 0x5294, 0x294A, 0x0000];
+
+/// What MASK_EN does to the game screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mask {
+    Off,
+    Freeze,
+    Black,
+    Color0,
+}
+
+/// A *_TRN command waiting for the next frame.
+#[derive(Debug, Clone, Copy)]
+enum Transfer {
+    Palettes,
+    /// Border tiles 0x00-0x7F or 0x80-0xFF.
+    Tiles(usize),
+    Border,
+    Attributes,
+}
+
+pub struct SGB {
+    packet: [u8; PACKET_SIZE],
+    bits: usize,
+    receiving: bool,
+    /// Packets of a command sent over several.
+    command: Vec<u8>,
+    p1: u8,
+
+    /// Palettes 0-3 of the game screen, colour 0 is shared.
+    palettes: [[u16; 4]; 4],
+    system_palettes: Vec<[u16; 4]>,
+    /// Palette of each 8x8 cell of the game screen.
+    attributes: [u8; CELLS_X * CELLS_Y],
+    attribute_files: Vec<u8>,
+    mask: Mask,
+    pending: Option<Transfer>,
+
+    /// 4bpp SNES tiles.
+    border_tiles: Vec<u8>,
+    border_map: Vec<u16>,
+    /// Border palettes 4-7.
+    border_palettes: [[u16; 16]; 4],
+
+    players: u8,
+    player: u8,
+    frame: Vec<u32>,
+}
+
+impl SGB {
+    pub fn new() -> SGB {
+        let mut sgb = SGB {
+            packet: [0; PACKET_SIZE],
+            bits: 0,
+            receiving: false,
+            command: Vec::new(),
+            p1: 0x30,
+            palettes: [DEFAULT_PALETTE; 4],
+            system_palettes: vec![DEFAULT_PALETTE; SYSTEM_PALETTES],
+            attributes: [0; CELLS_X * CELLS_Y],
+            attribute_files: vec![0; ATTR_FILES * ATTR_FILE_SIZE],
+            mask: Mask::Off,
+            pending: None,
+            border_tiles: vec![0; BORDER_TILES * 32],
+            border_map: vec![0; BORDER_MAP_SIZE],
+            border_palettes: [[0; 16]; 4],
+            players: 1,
+            player: 0,
+            frame: vec![0; (SGB_WIDTH * SGB_HEIGHT) as usize],
+        };
+        sgb.render_border();
+        sgb
+    }
+
+    /// Last finished frame, `SGB_WIDTH` x `SGB_HEIGHT` pixels as 0xAARRGGBB.
+    pub fn frame(&self) -> &[u32] {
+        &self.frame
+    }
+
+    /// Watches P1 writes for packet bits. With several players, each rising edge of P15
+    /// moves on to the next joypad.
+    pub fn write_p1(&mut self, data: u8) {
+        let previous = self.p1 & 0x30;
+        let lines = data & 0x30;
+        self.p1 = data;
+
+        match lines {
+            0x00 => {
+                self.packet = [0; PACKET_SIZE];
+                self.bits = 0;
+                self.receiving = true;
+            }
+            0x30 if previous == 0x10 && !self.receiving => {
+                self.player = (self.player + 1) % self.players;
+            }
+            0x30 => {}
+            _ if self.receiving && previous == 0x30 => self.receive_bit(lines == 0x10),
+            _ => {}
+        }
+    }
+
+    /// With several players, P1 reads the joypad ID while nothing is selected. The other
+    /// joypads are not connected, so they never show a pressed button.
+    pub fn read_p1(&self, value: u8) -> u8 {
+        if self.players == 1 {
+            return value;
+        }
+        match value & 0x30 {
+            0x30 => (value & 0xF0) | (0x0F - self.player),
+            _ if self.player != 0 => value | 0x0F,
+            _ => value,
+        }
+    }
+
+    /// Runs a pending transfer with what the game has on screen, then colours the frame.
+    pub fn frame_done(&mut self, ppu: &PPU) {
+        if let Some(transfer) = self.pending.take() {
+            let data = Self::screen_data(ppu);
+            self.apply_transfer(transfer, &data);
+        }
+        self.render_game(ppu.shade_buffer());
+    }
+
+    fn receive_bit(&mut self, one: bool) {
+        if self.bits < PACKET_SIZE * 8 {
+            if one {
+                self.packet[self.bits / 8] |= 1 << (self.bits % 8);
+            }
+            self.bits += 1;
+            return;
+        }
+
+        // Stop bit, the next packet starts with a reset.
+        self.receiving = false;
+        self.command.extend_from_slice(&self.packet);
+        let packets = (self.command[0] & 0x07).max(1) as usize;
+        if self.command.len() >= packets * PACKET_SIZE {
+            let command = std::mem::take(&mut self.command);
+            self.execute(&command);
+        }
+    }
+
+    fn execute(&mut self, data: &[u8]) {
+        let backdrop = self.palettes[0][0];
+        match data[0] >> 3 {
+            command @ (PAL01 | PAL23 | PAL03 | PAL12) => {
+                let (first, second) = match command {
+                    PAL01 => (0, 1),
+                    PAL23 => (2, 3),
+                    PAL03 => (0, 3),
+                    _ => (1, 2),
+                };
+                let color = |i: usize| u16::from_le_bytes([data[1 + i * 2], data[2 + i * 2]]);
+                for palette in self.palettes.iter_mut() {
+                    palette[0] = color(0);
+                }
+                for i in 1..4 {
+                    self.palettes[first][i] = color(i);
+                    self.palettes[second][i] = color(i + 3);
+                }
+            }
+            ATTR_BLK => self.attr_blk(data),
+            ATTR_LIN => self.attr_lin(data),
+            ATTR_DIV => self.attr_div(data),
+            ATTR_CHR => self.attr_chr(data),
+            PAL_SET => {
+                for i in 0..4 {
+                    let number = u16::from_le_bytes([data[1 + i * 2], data[2 + i * 2]]) as usize;
+                    self.palettes[i] = self.system_palettes[number % SYSTEM_PALETTES];
+                }
+                let shared = self.palettes[0][0];
+                for palette in self.palettes.iter_mut() {
+                    palette[0] = shared;
+                }
+                if data[9] & 0x80 != 0 {
+                    self.apply_attribute_file(data[9] & 0x3F);
+                }
+                if data[9] & 0x40 != 0 {
+                    self.mask = Mask::Off;
+                }
+            }
+            PAL_TRN => self.pending = Some(Transfer::Palettes),
+            MLT_REQ => {
+                self.players = match data[1] & 0x03 {
+                    1 => 2,
+                    3 => 4,
+                    _ => 1,
+                };
+                self.player = 0;
+            }
+            CHR_TRN => self.pending = Some(Transfer::Tiles((data[1] & 1) as usize)),
+            PCT_TRN => self.pending = Some(Transfer::Border),
+            ATTR_TRN => self.pending = Some(Transfer::Attributes),
+            ATTR_SET => {
+                self.apply_attribute_file(data[1] & 0x3F);
+                if data[1] & 0x40 != 0 {
+                    self.mask = Mask::Off;
+                }
+            }
+            MASK_EN => {
+                self.mask = match data[1] & 0x03 {
+                    1 => Mask::Freeze,
+                    2 => Mask::Black,
+                    3 => Mask::Color0,
+                    _ => Mask::Off,
+                };
+            }
+            _ => {}
+        }
+
+        // Transparent border pixels show colour 0.
+        if self.palettes[0][0] != backdrop {
+            self.render_border();
+        }
+    }
+
+    /// Rectangles with a palette for the cells inside, on the edge and outside of each.
+    fn attr_blk(&mut self, data: &[u8]) {
+        let count = data[1] as usize;
+        for set in data[2..].chunks_exact(6).take(count) {
+            let control = set[0] & 0x07;
+            let (inside, border, outside) = (set[1] & 0x03, (set[1] >> 2) & 0x03, (set[1] >> 4) & 0x03);
+            let (x1, y1, x2, y2) = (set[2] as usize, set[3] as usize, set[4] as usize, set[5] as usize);
+            // With only one of inside and outside, the edge takes the same palette.
+            let edge_palette = match control {
+                1 => Some(inside),
+                4 => Some(outside),
+                _ if control & 0x02 != 0 => Some(border),
+                _ => None,
+            };
+
+            for y in 0..CELLS_Y {
+                for x in 0..CELLS_X {
+                    let within = (x1..=x2).contains(&x) && (y1..=y2).contains(&y);
+                    let edge = within && (x == x1 || x == x2 || y == y1 || y == y2);
+                    let palette = match (within, edge) {
+                        (_, true) => edge_palette,
+                        (true, false) => (control & 0x01 != 0).then_some(inside),
+                        (false, _) => (control & 0x04 != 0).then_some(outside),
+                    };
+                    if let Some(palette) = palette {
+                        self.attributes[y * CELLS_X + x] = palette;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whole rows or columns of cells.
+    fn attr_lin(&mut self, data: &[u8]) {
+        let count = data[1] as usize;
+        for &line in data[2..].iter().take(count) {
+            let (number, palette) = ((line & 0x1F) as usize, (line >> 5) & 0x03);
+            match line & 0x80 != 0 {
+                true if number < CELLS_Y => self.attributes[number * CELLS_X..(number + 1) * CELLS_X].fill(palette),
+                false if number < CELLS_X => {
+                    for y in 0..CELLS_Y {
+                        self.attributes[y * CELLS_X + number] = palette;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Splits the screen at a row or column, with a palette for each side and the line.
+    fn attr_div(&mut self, data: &[u8]) {
+        let (after, before, on) = (data[1] & 0x03, (data[1] >> 2) & 0x03, (data[1] >> 4) & 0x03);
+        let rows = data[1] & 0x40 != 0;
+        let split = data[2] as usize;
+        for y in 0..CELLS_Y {
+            for x in 0..CELLS_X {
+                let position = if rows { y } else { x };
+                self.attributes[y * CELLS_X + x] = match position.cmp(&split) {
+                    std::cmp::Ordering::Less => before,
+                    std::cmp::Ordering::Equal => on,
+                    std::cmp::Ordering::Greater => after,
+                };
+            }
+        }
+    }
+
+    /// One palette per cell from a starting cell, 4 cells per byte.
+    fn attr_chr(&mut self, data: &[u8]) {
+        let (mut x, mut y) = (data[1] as usize, data[2] as usize);
+        let count = u16::from_le_bytes([data[3], data[4]]) as usize;
+        let columns = data[5] & 0x01 != 0;
+        for i in 0..count.min(CELLS_X * CELLS_Y) {
+            let Some(&byte) = data.get(6 + i / 4) else {
+                break;
+            };
+            if x < CELLS_X && y < CELLS_Y {
+                self.attributes[y * CELLS_X + x] = (byte >> (6 - (i % 4) * 2)) & 0x03;
+            }
+            match columns {
+                true => {
+                    y += 1;
+                    if y == CELLS_Y {
+                        (x, y) = (x + 1, 0);
+                    }
+                }
+                false => {
+                    x += 1;
+                    if x == CELLS_X {
+                        (x, y) = (0, y + 1);
+                    }
+                }
+            }
+        }
+    }
+
+    fn apply_attribute_file(&mut self, file: u8) {
+        let file = file as usize;
+        if file >= ATTR_FILES {
+            return;
+        }
+        let data = &self.attribute_files[file * ATTR_FILE_SIZE..(file + 1) * ATTR_FILE_SIZE];
+        for (i, attribute) in self.attributes.iter_mut().enumerate() {
+            *attribute = (data[i / 4] >> (6 - (i % 4) * 2)) & 0x03;
+        }
+    }
+
+    /// The 4 KiB a transfer reads from the background tiles on screen.
+    fn screen_data(ppu: &PPU) -> Vec<u8> {
+        let map = ppu.lcd.lcdc_bg_map_area() - 0x8000;
+        let mut data = Vec::with_capacity(0x1000);
+        for i in 0..256 {
+            let tile = ppu.vram_read(map + (i / CELLS_X * 32 + i % CELLS_X) as u16);
+            let address = ppu.bgw_tile_addr(tile);
+            data.extend((0..16).map(|offset| ppu.vram_read(address + offset)));
+        }
+        data
+    }
+
+    fn apply_transfer(&mut self, transfer: Transfer, data: &[u8]) {
+        let word = |offset: usize| u16::from_le_bytes([data[offset], data[offset + 1]]);
+        match transfer {
+            Transfer::Palettes => {
+                for (i, palette) in self.system_palettes.iter_mut().enumerate() {
+                    *palette = [word(i * 8), word(i * 8 + 2), word(i * 8 + 4), word(i * 8 + 6)];
+                }
+            }
+            Transfer::Tiles(half) => {
+                self.border_tiles[half * 0x1000..(half + 1) * 0x1000].copy_from_slice(data);
+                self.render_border();
+            }
+            Transfer::Border => {
+                for (i, entry) in self.border_map.iter_mut().enumerate() {
+                    *entry = word(i * 2);
+                }
+                for (p, palette) in self.border_palettes.iter_mut().enumerate() {
+                    for (c, color) in palette.iter_mut().enumerate() {
+                        *color = word(0x800 + p * 32 + c * 2);
+                    }
+                }
+                self.render_border();
+            }
+            Transfer::Attributes => {
+                self.attribute_files.copy_from_slice(&data[..ATTR_FILES * ATTR_FILE_SIZE]);
+            }
+        }
+    }
+
+    /// Redraws the 32x28 border tiles, only needed after a border transfer or a change
+    /// of colour 0.
+    fn render_border(&mut self) {
+        let backdrop = to_argb(self.palettes[0][0]);
+        for ty in 0..(SGB_HEIGHT / 8) as usize {
+            for tx in 0..(SGB_WIDTH / 8) as usize {
+                let entry = self.border_map[ty * 32 + tx];
+                let tile = &self.border_tiles[(entry & 0xFF) as usize * 32..][..32];
+                let palette = &self.border_palettes[((entry >> 10) & 0x03) as usize];
+                let (x_flip, y_flip) = (entry & 0x4000 != 0, entry & 0x8000 != 0);
+
+                for row in 0..8 {
+                    for col in 0..8 {
+                        let x = if x_flip { 7 - col } else { col };
+                        let y = if y_flip { 7 - row } else { row };
+                        let index = snes_tile_pixel(tile, x, y);
+                        self.frame[(ty * 8 + row) * SGB_WIDTH as usize + tx * 8 + col] = match index {
+                            0 => backdrop,
+                            _ => to_argb(palette[index]),
+                        };
+                    }
+                }
+            }
+        }
+    }
+
+    fn render_game(&mut self, shades: &[u8]) {
+        if self.mask == Mask::Freeze {
+            return;
+        }
+        for y in 0..YRES as usize {
+            for x in 0..XRES as usize {
+                let palette = &self.palettes[self.attributes[y / 8 * CELLS_X + x / 8] as usize];
+                let color = match self.mask {
+                    Mask::Black => 0,
+                    Mask::Color0 => palette[0],
+                    _ => palette[shades[y * XRES as usize + x] as usize],
+                };
+                self.frame[(GAME_Y + y) * SGB_WIDTH as usize + GAME_X + x] = to_argb(color);
+            }
+        }
+    }
+}
+
+/// The frame is drawn again from the border and the next game screen, only a frozen
+/// game screen is saved as it is.
+impl SaveState for SGB {
+    const TAG: [u8; 4] = *b"SGB ";
+    const VERSION: u16 = 1;
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.bytes(&self.packet);
+        writer.u8(self.bits as u8);
+        writer.bool(self.receiving);
+        writer.block(&self.command);
+        writer.u8(self.p1);
+
+        self.palettes.iter().flatten().for_each(|&color| writer.u16(color));
+        self.system_palettes.iter().flatten().for_each(|&color| writer.u16(color));
+        writer.bytes(&self.attributes);
+        writer.bytes(&self.attribute_files);
+        writer.u8(self.mask as u8);
+        writer.u8(match self.pending {
+            None => 0,
+            Some(Transfer::Palettes) => 1,
+            Some(Transfer::Tiles(half)) => 2 + half as u8,
+            Some(Transfer::Border) => 4,
+            Some(Transfer::Attributes) => 5,
+        });
+
+        writer.bytes(&self.border_tiles);
+        self.border_map.iter().for_each(|&entry| writer.u16(entry));
+        self.border_palettes.iter().flatten().for_each(|&color| writer.u16(color));
+        writer.u8(self.players);
+        writer.u8(self.player);
+
+        if self.mask == Mask::Freeze {
+            for y in GAME_Y..GAME_Y + YRES as usize {
+                let row = &self.frame[y * SGB_WIDTH as usize + GAME_X..][..XRES as usize];
+                row.iter().for_each(|&pixel| writer.u32(pixel));
+            }
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        reader.bytes(&mut self.packet)?;
+        self.bits = (reader.u8()? as usize).min(PACKET_SIZE * 8);
+        self.receiving = reader.bool()?;
+        let len = reader.u32()? as usize;
+        if len >= 7 * PACKET_SIZE {
+            return Err(SaveStateError::InvalidData(format!("SGB command of {} bytes", len)));
+        }
+        self.command = vec![0; len];
+        reader.bytes(&mut self.command)?;
+        self.p1 = reader.u8()?;
+
+        for color in self.palettes.iter_mut().flatten() {
+            *color = reader.u16()?;
+        }
+        for color in self.system_palettes.iter_mut().flatten() {
+            *color = reader.u16()?;
+        }
+        reader.bytes(&mut self.attributes)?;
+        reader.bytes(&mut self.attribute_files)?;
+        for palette in self.attributes.iter_mut() {
+            *palette &= 0x03;
+        }
+        self.mask = match reader.u8()? {
+            1 => Mask::Freeze,
+            2 => Mask::Black,
+            3 => Mask::Color0,
+            _ => Mask::Off,
+        };
+        self.pending = match reader.u8()? {
+            1 => Some(Transfer::Palettes),
+            code @ (2 | 3) => Some(Transfer::Tiles(code as usize - 2)),
+            4 => Some(Transfer::Border),
+            5 => Some(Transfer::Attributes),
+            _ => None,
+        };
+
+        reader.bytes(&mut self.border_tiles)?;
+        for entry in self.border_map.iter_mut() {
+            *entry = reader.u16()?;
+        }
+        for color in self.border_palettes.iter_mut().flatten() {
+            *color = reader.u16()?;
+        }
+        self.players = reader.u8()?.clamp(1, 4);
+        self.player = reader.u8()? % self.players;
+
+        self.render_border();
+        if self.mask == Mask::Freeze {
+            for y in GAME_Y..GAME_Y + YRES as usize {
+                for pixel in &mut self.frame[y * SGB_WIDTH as usize + GAME_X..][..XRES as usize] {
+                    *pixel = reader.u32()?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Colour index 0-15 of a pixel in a 4bpp SNES tile: planes 0-1 interleaved by row,
+/// then planes 2-3.
+fn snes_tile_pixel(tile: &[u8], x: usize, y: usize) -> usize {
+    let bit = 7 - x;
+    let plane = |offset: usize| ((tile[offset] >> bit) & 1) as usize;
+    plane(y * 2) | plane(y * 2 + 1) << 1 | plane(16 + y * 2) << 2 | plane(17 + y * 2) << 3
+}
+
+/// BGR555 to 0xAARRGGBB.
+fn to_argb(color: u16) -> u32 {
+    let channel = |shift: u16| {
+        let value = ((color >> shift) & 0x1F) as u32;
+        (value << 3) | (value >> 2)
+    };
+    0xFF_00_00_00 | channel(0) << 16 | channel(5) << 8 | channel(10)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send_packet(sgb: &mut SGB, packet: &[u8; PACKET_SIZE]) {
+        sgb.write_p1(0x00);
+        sgb.write_p1(0x30);
+        for i in 0..PACKET_SIZE * 8 {
+            let one = packet[i / 8] & (1 << (i % 8)) != 0;
+            sgb.write_p1(if one { 0x10 } else { 0x20 });
+            sgb.write_p1(0x30);
+        }
+        sgb.write_p1(0x20);
+        sgb.write_p1(0x30);
+    }
+
+    #[test]
+    fn test_packets_and_colours() {
+        let mut sgb = SGB::new();
+        let mut packet = [0; PACKET_SIZE];
+        packet[0] = PAL01 << 3 | 1;
+        // Colour 0 white, palette 0 colour 1 green, palette 1 colour 1 red.
+        packet[1..3].copy_from_slice(&0x7FFFu16.to_le_bytes());
+        packet[3..5].copy_from_slice(&0x03E0u16.to_le_bytes());
+        packet[9..11].copy_from_slice(&0x001Fu16.to_le_bytes());
+        send_packet(&mut sgb, &packet);
+        assert_eq!(sgb.palettes[1][1], 0x001F);
+        assert_eq!(sgb.palettes[3][0], 0x7FFF);
+        assert_eq!(sgb.frame()[0], 0xFF_FF_FF_FF);
+
+        // Right half of the screen in palette 1.
+        let mut packet = [0; PACKET_SIZE];
+        packet[0] = ATTR_DIV << 3 | 1;
+        packet[1] = 0x01 | (0x01 << 4);
+        packet[2] = 10;
+        send_packet(&mut sgb, &packet);
+
+        let shades = [1u8; (XRES * YRES) as usize];
+        sgb.render_game(&shades);
+        let pixel = |x: usize, y: usize| sgb.frame()[(GAME_Y + y) * SGB_WIDTH as usize + GAME_X + x];
+        assert_eq!(pixel(159, 0), 0xFF_FF_00_00);
+        assert_eq!(pixel(0, 0), 0xFF_00_FF_00);
+
+        let mut packet = [0; PACKET_SIZE];
+        packet[0] = MLT_REQ << 3 | 1;
+        packet[1] = 0x01;
+        send_packet(&mut sgb, &packet);
+        assert_eq!(sgb.read_p1(0xFF), 0xFF);
+        sgb.write_p1(0x10);
+        sgb.write_p1(0x30);
+        assert_eq!(sgb.read_p1(0xFF), 0xFE);
+    }
+}
@@ -1,72 +1,48 @@
-use std::sync::{Arc, Mutex, MutexGuard};
-use crate::dma::DMA;
-use crate::emu::GlobalContext;
-use crate::ppu::PPU;
-use crate::timer::Timer;
-
-#[derive(Debug)]
-pub enum TickError {
-    GetTicksError,
-}
-
-#[derive(Clone)]
+/// Emulated time in T-cycles, and how far the components behind the bus have been caught up
+/// to it. Components only run when an event is due or one of their registers is accessed,
+/// in between the CPU just counts cycles.
 pub struct TickManager {
-    pub ticks: Arc<Mutex<u64>>,
-    pub timer: Arc<Mutex<Timer>>,
-    pub dma : Arc<Mutex<DMA>>,
-    pub ppu: Arc<Mutex<PPU>>,
+    ticks: u64,
+    synced_at: u64,
+    /// Tick at which a component next does something the CPU can observe without going
+    /// through the IO registers, like raising an interrupt.
+    next_event: u64,
 }
 
 impl TickManager {
-    pub fn new(timer: Arc<Mutex<Timer>>, global_context: GlobalContext) -> TickManager {
+    pub fn new() -> TickManager {
         TickManager {
-            ticks: Arc::new(Mutex::new(0)),
-            timer,
-            dma: global_context.dma.unwrap(),
-            ppu: global_context.ppu.unwrap()
+            ticks: 0,
+            synced_at: 0,
+            next_event: 0,
         }
     }
 
-    pub fn cycle(&self, _cycles: u32) {
-        //self.ticks += 1;
-        let n = _cycles;
-        let mut ticks = self.get_ticks_ref().unwrap();
-        for _ in 0..n {
-            for _ in 0..4 {
-                *ticks += 1;
-                self.timer.lock().unwrap().tick();
-            }
-
-            self.dma.lock().unwrap().dma_tick();
-
-        }
+    /// Counts `cycles` M-cycles. Returns true once the components have to be caught up.
+    pub fn cycle(&mut self, cycles: u32) -> bool {
+        self.ticks += cycles as u64 * 4;
+        self.ticks >= self.next_event
     }
 
-    fn get_ticks_ref(&self) -> Result<MutexGuard<u64>, TickError> {
-        let ticks = match self.ticks.lock() {
-            Ok(ticks) => ticks,
-            Err(_) => return Err(TickError::GetTicksError),
-        };
-
-        Ok(ticks)
+    /// T-cycles the components are behind.
+    pub fn lag(&self) -> u32 {
+        (self.ticks - self.synced_at).min(u32::MAX as u64) as u32
     }
 
-    #[allow(dead_code)]
-    pub fn increment_ticks(&self) -> Result<(), TickError>{
-        let mut ticks = self.get_ticks_ref()?;
-        *ticks += 1;
-        Ok(())
+    /// Marks the components as caught up, with the next event `ticks_to_event` from now.
+    pub fn synced(&mut self, ticks_to_event: u32) {
+        self.synced_at = self.ticks;
+        self.next_event = self.ticks + ticks_to_event as u64;
     }
 
-    pub fn get_ticks(&self) -> Result<u64, TickError> {
-        let ticks = self.get_ticks_ref()?;
-        Ok(*ticks)
+    pub fn get_ticks(&self) -> u64 {
+        self.ticks
     }
 
-    pub fn set_ticks(&self, new_ticks: u64) -> Result<(), TickError> {
-        let mut ticks = self.get_ticks_ref()?;
-        *ticks = new_ticks;
-        Ok(())
+    /// Also restarts the schedule, the components are taken as being in sync at `ticks`.
+    pub fn set_ticks(&mut self, ticks: u64) {
+        self.ticks = ticks;
+        self.synced_at = ticks;
+        self.next_event = ticks;
     }
 }
-
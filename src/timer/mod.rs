@@ -1,85 +1,161 @@
-use std::sync::{Arc, Mutex};
 use crate::cpu::interrupts::{IFlagsRegister, InterruptType};
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+
+const TAC_ENABLE: u8 = 1 << 2;
+
+/// T-cycles TIMA reads as 0x00 after an overflow before TMA is reloaded, and the
+/// length of the reload cycle itself.
+const RELOAD_DELAY: u8 = 4;
 
 pub struct Timer {
     div: u16,
     tima: u8,
     tma: u8,
     tac: u8,
-    int_flags: Arc<Mutex<IFlagsRegister>>
+    overflow_delay: u8,
+    reload_cycle: u8,
 }
 
 impl Timer {
-    pub fn new(int_flags: Arc<Mutex<IFlagsRegister>>) -> Self {
+    pub fn new() -> Self {
         Timer {
             div: 0xAC00,
             tima: 0,
             tma: 0,
             tac: 0,
-            int_flags
+            overflow_delay: 0,
+            reload_cycle: 0,
         }
     }
 
     #[allow(dead_code)]
     pub fn reset(&mut self) {
-        let mut new_timer = Timer::new(self.int_flags.clone());
-        std::mem::swap(self, &mut new_timer);
+        *self = Timer::new();
     }
 
-    pub fn tick(&mut self) {
-        let prev_div = self.div;
-        self.div = self.div.wrapping_add(1);
-
-        let mut timer_update = false;
+    fn selected_div_bit(&self) -> u16 {
         match self.tac & 0x3 {
-            0 => {
-                if (prev_div & (1 << 9)) != (self.div & (1 << 9)) {
-                    timer_update = true;
-                }
-            }
-            1 => {
-                if (prev_div & (1 << 3)) != (self.div & (1 << 3)) {
-                    timer_update = true;
-                }
-            }
-            2 => {
-                if (prev_div & (1 << 5)) != (self.div & (1 << 5)) {
-                    timer_update = true;
-                }
-            }
-            3 => {
-                if (prev_div & (1 << 7)) != (self.div & (1 << 7)) {
-                    timer_update = true;
-                }
-            }
-            _ => {}
+            0 => 1 << 9,
+            1 => 1 << 3,
+            2 => 1 << 5,
+            _ => 1 << 7,
+        }
+    }
+
+    /// The signal whose falling edge increments TIMA: the selected DIV bit ANDed with the enable bit.
+    fn timer_signal(&self) -> bool {
+        (self.tac & TAC_ENABLE) != 0 && (self.div & self.selected_div_bit()) != 0
+    }
+
+    fn increment_tima(&mut self) {
+        self.tima = self.tima.wrapping_add(1);
+        if self.tima == 0 {
+            self.overflow_delay = RELOAD_DELAY;
+        }
+    }
+
+    pub fn tick(&mut self, int_flags: &mut IFlagsRegister) {
+        if self.reload_cycle > 0 {
+            self.reload_cycle -= 1;
         }
 
-        if timer_update && ((self.tac & (1 << 2)) != 0){
-            self.tima = self.tima.wrapping_add(1);
-            if self.tima == 0xFF {
+        if self.overflow_delay > 0 {
+            self.overflow_delay -= 1;
+            if self.overflow_delay == 0 {
                 self.tima = self.tma;
-                {
-                    self.int_flags.lock().unwrap().add_interrupt(InterruptType::Timer);
+                self.reload_cycle = RELOAD_DELAY;
+                int_flags.add_interrupt(InterruptType::Timer);
+            }
+        }
+
+        let prev_signal = self.timer_signal();
+        self.div = self.div.wrapping_add(1);
+        if prev_signal && !self.timer_signal() {
+            self.increment_tima();
+        }
+    }
+
+    /// T-cycles until the next tick that does more than count: the one that overflows TIMA,
+    /// or every tick while an overflow is being handled.
+    pub fn ticks_to_event(&self) -> u32 {
+        if self.overflow_delay > 0 || self.reload_cycle > 0 {
+            return 1;
+        }
+        if (self.tac & TAC_ENABLE) == 0 {
+            return u32::MAX;
+        }
+
+        let period = (self.selected_div_bit() as u32) << 1;
+        let first_edge = period - (self.div as u32 % period);
+        first_edge + (0xFF - self.tima as u32) * period
+    }
+
+    /// Runs `cycles` T-cycles, counting in bulk up to each event.
+    pub fn advance(&mut self, mut cycles: u32, int_flags: &mut IFlagsRegister) {
+        while cycles > 0 {
+            let skip = (self.ticks_to_event() - 1).min(cycles);
+            if skip > 0 {
+                if (self.tac & TAC_ENABLE) != 0 {
+                    let period = (self.selected_div_bit() as u32) << 1;
+                    let edges = (self.div as u32 + skip) / period - self.div as u32 / period;
+                    // `skip` stops short of the edge that overflows TIMA, so at most
+                    // 0xFF - tima edges are counted here.
+                    debug_assert!(self.tima as u32 + edges <= 0xFF);
+                    self.tima += edges as u8;
                 }
+                self.div = self.div.wrapping_add(skip as u16);
+                cycles -= skip;
+            }
+
+            if cycles > 0 {
+                self.tick(int_flags);
+                cycles -= 1;
             }
         }
     }
 
     pub fn clear_divider(&mut self) {
+        let prev_signal = self.timer_signal();
         self.div = 0;
+        if prev_signal {
+            self.increment_tima();
+        }
     }
 
     pub fn set_tima(&mut self, data: u8) {
+        // Writes during the reload cycle are overwritten by TMA.
+        if self.reload_cycle > 0 {
+            return;
+        }
+
+        // Writing while TIMA reads 0x00 after an overflow cancels the reload and the interrupt.
+        self.overflow_delay = 0;
         self.tima = data;
     }
 
     pub fn set_tma(&mut self, data: u8) {
         self.tma = data;
+        if self.reload_cycle > 0 {
+            self.tima = data;
+        }
     }
 
     pub fn set_tac(&mut self, data: u8) {
+        let prev_signal = self.timer_signal();
         self.tac = data;
+        if prev_signal && !self.timer_signal() {
+            self.increment_tima();
+        }
+    }
+
+    /// Sets every register at once, without the side effects of bus writes.
+    pub fn load_registers(&mut self, div: u16, tima: u8, tma: u8, tac: u8) {
+        self.div = div;
+        self.tima = tima;
+        self.tma = tma;
+        self.tac = tac & 0x07;
+        self.overflow_delay = 0;
+        self.reload_cycle = 0;
     }
 
     pub fn get_divider(&self) -> u16 {
@@ -95,6 +171,125 @@ impl Timer {
     }
 
     pub fn get_tac(&self) -> u8 {
-        self.tac
+        self.tac | 0xF8
+    }
+}
+
+impl SaveState for Timer {
+    const TAG: [u8; 4] = *b"TIMR";
+    const VERSION: u16 = 1;
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.u16(self.div);
+        writer.bytes(&[self.tima, self.tma, self.tac, self.overflow_delay, self.reload_cycle]);
     }
-}
\ No newline at end of file
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.div = reader.u16()?;
+        self.tima = reader.u8()?;
+        self.tma = reader.u8()?;
+        self.tac = reader.u8()?;
+        self.overflow_delay = reader.u8()?;
+        self.reload_cycle = reader.u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timer() -> (Timer, IFlagsRegister) {
+        let mut timer = Timer::new();
+        timer.div = 0;
+        (timer, IFlagsRegister::new())
+    }
+
+    fn tick(timer: &mut Timer, int_flags: &mut IFlagsRegister, cycles: u32) {
+        for _ in 0..cycles {
+            timer.tick(int_flags);
+        }
+    }
+
+    #[test]
+    fn test_overflow_reads_zero_before_reload() {
+        let (mut timer, mut int_flags) = timer();
+        timer.set_tac(0x05);
+        timer.set_tma(0x42);
+        timer.set_tima(0xFF);
+
+        tick(&mut timer, &mut int_flags, 16);
+        assert_eq!(timer.get_tima(), 0x00);
+        assert_eq!(int_flags.int_flags, 0);
+
+        tick(&mut timer, &mut int_flags, 4);
+        assert_eq!(timer.get_tima(), 0x42);
+        assert!(int_flags.has_interrupt(InterruptType::Timer));
+    }
+
+    #[test]
+    fn test_tima_write_cancels_pending_reload() {
+        let (mut timer, mut int_flags) = timer();
+        timer.set_tac(0x05);
+        timer.set_tma(0x42);
+        timer.set_tima(0xFF);
+
+        tick(&mut timer, &mut int_flags, 16);
+        timer.set_tima(0x10);
+        tick(&mut timer, &mut int_flags, 4);
+        assert_eq!(timer.get_tima(), 0x10);
+        assert_eq!(int_flags.int_flags, 0);
+    }
+
+    #[test]
+    fn test_tma_write_during_reload_cycle() {
+        let (mut timer, mut int_flags) = timer();
+        timer.set_tac(0x05);
+        timer.set_tma(0x42);
+        timer.set_tima(0xFF);
+
+        tick(&mut timer, &mut int_flags, 20);
+        timer.set_tima(0x10);
+        assert_eq!(timer.get_tima(), 0x42);
+        timer.set_tma(0x99);
+        assert_eq!(timer.get_tima(), 0x99);
+    }
+
+    #[test]
+    fn test_advance_matches_ticking() {
+        let (mut ticked, mut ticked_flags) = timer();
+        let (mut advanced, mut advanced_flags) = timer();
+        for timer in [&mut ticked, &mut advanced] {
+            timer.set_tac(0x05);
+            timer.set_tma(0xF0);
+            timer.set_tima(0xF8);
+        }
+
+        for cycles in [3, 100, 1, 517, 4096, 28] {
+            tick(&mut ticked, &mut ticked_flags, cycles);
+            advanced.advance(cycles, &mut advanced_flags);
+            assert_eq!((advanced.div, advanced.tima), (ticked.div, ticked.tima));
+            assert_eq!(advanced_flags.int_flags, ticked_flags.int_flags);
+        }
+    }
+
+    #[test]
+    fn test_div_reset_glitches_increment() {
+        let (mut timer, mut int_flags) = timer();
+        timer.set_tac(0x05);
+        tick(&mut timer, &mut int_flags, 8);
+        assert_eq!(timer.get_tima(), 0);
+
+        timer.clear_divider();
+        assert_eq!(timer.get_tima(), 1);
+    }
+
+    #[test]
+    fn test_tac_disable_glitches_increment() {
+        let (mut timer, mut int_flags) = timer();
+        timer.set_tac(0x05);
+        tick(&mut timer, &mut int_flags, 8);
+        timer.set_tac(0x01);
+        assert_eq!(timer.get_tima(), 1);
+    }
+}
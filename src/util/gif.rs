@@ -0,0 +1,342 @@
+/*
+    GIF89a animation:
+    "GIF89a", the logical screen descriptor and the global colour table, a NETSCAPE2.0
+    extension that loops the animation, then for every image a graphic control extension
+    (delay in 1/100 s, disposal: keep the image in place), an image descriptor, an optional
+    local colour table and the LZW-compressed colour indices in blocks of up to 255 bytes.
+    0x3B ends the file.
+
+    Each image only covers the rectangle that changed since the previous one, and frames
+    identical to the previous one extend its delay instead. Delays are rounded against
+    the total running time, so the animation keeps the frame rate on average.
+*/
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+const MAX_COLORS: usize = 256;
+const MAX_CODE_SIZE: u8 = 12;
+const DISPOSAL_KEEP: u8 = 1 << 2;
+/// Shortest delay players honour, in hundredths of a second.
+const MIN_DELAY: u64 = 2;
+
+/// Rectangle of a frame waiting for the delay to be known.
+struct PendingImage {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    pixels: Vec<u32>,
+    /// Frame number it was shown at.
+    start: u32,
+}
+
+pub struct GifWriter {
+    writer: BufWriter<File>,
+    width: u32,
+    height: u32,
+    palette: Vec<u32>,
+    rate_num: u32,
+    rate_den: u32,
+    frames: u32,
+    /// What the animation shows before and after the pending image is drawn.
+    base: Vec<u32>,
+    shown: Vec<u32>,
+    pending: Option<PendingImage>,
+}
+
+impl GifWriter {
+    /// Frames are `width` x `height` 0xAARRGGBB pixels, pushed at `rate_num / rate_den`
+    /// per second. `palette` becomes the global colour table, frames with other colours
+    /// carry their own.
+    pub fn create(path: &str, width: u32, height: u32, palette: &[u32], rate_num: u32, rate_den: u32) -> std::io::Result<GifWriter> {
+        let mut palette = local_palette(palette);
+        if palette.is_empty() {
+            palette.push(0);
+        }
+
+        let mut gif = GifWriter {
+            writer: BufWriter::new(File::create(path)?),
+            width,
+            height,
+            palette,
+            rate_num,
+            rate_den,
+            frames: 0,
+            base: vec![0; (width * height) as usize],
+            shown: vec![0; (width * height) as usize],
+            pending: None,
+        };
+        gif.write_header()?;
+        Ok(gif)
+    }
+
+    fn write_header(&mut self) -> std::io::Result<()> {
+        let bits = table_bits(self.palette.len());
+        self.writer.write_all(b"GIF89a")?;
+        self.writer.write_all(&(self.width as u16).to_le_bytes())?;
+        self.writer.write_all(&(self.height as u16).to_le_bytes())?;
+        self.writer.write_all(&[0x80 | ((bits - 1) << 4) | (bits - 1), 0, 0])?;
+        write_color_table(&mut self.writer, &self.palette, bits)?;
+        self.writer.write_all(&[0x21, 0xFF, 0x0B])?;
+        self.writer.write_all(b"NETSCAPE2.0")?;
+        self.writer.write_all(&[0x03, 0x01, 0x00, 0x00, 0x00])
+    }
+
+    /// Appends a frame of 0xAARRGGBB pixels.
+    pub fn write_frame(&mut self, argb: &[u32]) -> std::io::Result<()> {
+        let frame = self.frames;
+        self.frames += 1;
+        if frame > 0 && argb[..self.shown.len()] == self.shown[..] {
+            return Ok(());
+        }
+
+        // Players slow images shorter than MIN_DELAY down, the next frame replaces those.
+        let start = match self.pending.as_ref() {
+            Some(image) if self.time(frame) - self.time(image.start) < MIN_DELAY => image.start,
+            _ => {
+                self.flush_pending(frame)?;
+                self.base.copy_from_slice(&self.shown);
+                frame
+            }
+        };
+        let (x, y, width, height) = match start {
+            0 => (0, 0, self.width, self.height),
+            _ => changed_rect(&self.base, argb, self.width).unwrap_or((0, 0, 1, 1)),
+        };
+        let pixels = (y..y + height)
+            .flat_map(|row| {
+                let offset = (row * self.width + x) as usize;
+                argb[offset..offset + width as usize].iter().copied()
+            })
+            .collect();
+        self.pending = Some(PendingImage { x, y, width, height, pixels, start });
+        let len = self.shown.len();
+        self.shown.copy_from_slice(&argb[..len]);
+        Ok(())
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    pub fn finish(mut self) -> std::io::Result<()> {
+        self.flush_pending(self.frames)?;
+        self.writer.write_all(&[0x3B])?;
+        self.writer.flush()
+    }
+
+    /// Hundredths of a second from the first frame to frame `frame`.
+    fn time(&self, frame: u32) -> u64 {
+        (frame as u64 * 100 * self.rate_den as u64 + self.rate_num as u64 / 2) / self.rate_num as u64
+    }
+
+    /// Writes the pending image, shown until frame `end`.
+    fn flush_pending(&mut self, end: u32) -> std::io::Result<()> {
+        let Some(image) = self.pending.take() else {
+            return Ok(());
+        };
+        let delay = (self.time(end) - self.time(image.start)).clamp(MIN_DELAY, u16::MAX as u64) as u16;
+
+        // The global table when it has every colour, otherwise one for this image.
+        let global = image.pixels.iter().all(|pixel| self.palette.contains(pixel));
+        let palette = match global {
+            true => self.palette.clone(),
+            false => local_palette(&image.pixels),
+        };
+        let lookup: HashMap<u32, u8> = palette.iter().enumerate().map(|(i, &color)| (color, i as u8)).collect();
+        let indices: Vec<u8> = image
+            .pixels
+            .iter()
+            .map(|pixel| lookup.get(pixel).copied().unwrap_or_else(|| nearest(&palette, *pixel)))
+            .collect();
+
+        let bits = table_bits(palette.len());
+        self.writer.write_all(&[0x21, 0xF9, 0x04, DISPOSAL_KEEP])?;
+        self.writer.write_all(&delay.to_le_bytes())?;
+        self.writer.write_all(&[0x00, 0x00])?;
+        self.writer.write_all(&[0x2C])?;
+        for value in [image.x, image.y, image.width, image.height] {
+            self.writer.write_all(&(value as u16).to_le_bytes())?;
+        }
+        match global {
+            true => self.writer.write_all(&[0x00])?,
+            false => {
+                self.writer.write_all(&[0x80 | (bits - 1)])?;
+                write_color_table(&mut self.writer, &palette, bits)?;
+            }
+        }
+
+        let min_code_size = bits.max(2);
+        self.writer.write_all(&[min_code_size])?;
+        for block in lzw_encode(&indices, min_code_size).chunks(255) {
+            self.writer.write_all(&[block.len() as u8])?;
+            self.writer.write_all(block)?;
+        }
+        self.writer.write_all(&[0x00])
+    }
+}
+
+/// Bounding box of the pixels that differ, as x, y, width and height.
+fn changed_rect(old: &[u32], new: &[u32], width: u32) -> Option<(u32, u32, u32, u32)> {
+    let (mut left, mut top, mut right, mut bottom) = (u32::MAX, u32::MAX, 0, 0);
+    for (i, _) in old.iter().zip(new).enumerate().filter(|(_, (old, new))| old != new) {
+        let (x, y) = (i as u32 % width, i as u32 / width);
+        left = left.min(x);
+        right = right.max(x);
+        top = top.min(y);
+        bottom = bottom.max(y);
+    }
+    match left {
+        u32::MAX => None,
+        _ => Some((left, top, right - left + 1, bottom - top + 1)),
+    }
+}
+
+/// Bits per entry of a colour table holding `colors` entries, 1 to 8.
+fn table_bits(colors: usize) -> u8 {
+    let mut bits = 1;
+    while (1 << bits) < colors {
+        bits += 1;
+    }
+    bits
+}
+
+/// Writes the colours as RGB, padded to `1 << bits` entries.
+fn write_color_table(writer: &mut impl Write, palette: &[u32], bits: u8) -> std::io::Result<()> {
+    for i in 0..1usize << bits {
+        let color = palette.get(i).copied().unwrap_or(0);
+        writer.write_all(&color.to_be_bytes()[1..])?;
+    }
+    Ok(())
+}
+
+/// The first 256 distinct colours.
+fn local_palette(pixels: &[u32]) -> Vec<u32> {
+    let mut palette = Vec::new();
+    for &pixel in pixels {
+        if palette.len() == MAX_COLORS {
+            break;
+        }
+        if !palette.contains(&pixel) {
+            palette.push(pixel);
+        }
+    }
+    palette
+}
+
+/// Index of the closest colour, for images with more colours than fit a table.
+fn nearest(palette: &[u32], color: u32) -> u8 {
+    let distance = |other: u32| {
+        (0..3)
+            .map(|shift| {
+                let a = ((color >> (shift * 8)) & 0xFF) as i32;
+                let b = ((other >> (shift * 8)) & 0xFF) as i32;
+                (a - b) * (a - b)
+            })
+            .sum::<i32>()
+    };
+    (0..palette.len()).min_by_key(|&i| distance(palette[i])).unwrap_or(0) as u8
+}
+
+/// Writes codes of growing width, least significant bit first.
+struct BitWriter {
+    out: Vec<u8>,
+    buffer: u32,
+    bits: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u8) {
+        self.buffer |= (code as u32) << self.bits;
+        self.bits += size;
+        while self.bits >= 8 {
+            self.out.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.out.push(self.buffer as u8);
+        }
+        self.out
+    }
+}
+
+/// Variable-width LZW as GIF uses it: starts with a clear code, clears again once the
+/// 4096 codes are used up.
+fn lzw_encode(indices: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+    let mut codes: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = end + 1;
+    let mut size = min_code_size + 1;
+    let mut writer = BitWriter { out: Vec::new(), buffer: 0, bits: 0 };
+
+    writer.write(clear, size);
+    let mut prefix = None;
+    for &index in indices {
+        let Some(current) = prefix else {
+            prefix = Some(index as u16);
+            continue;
+        };
+        if let Some(&code) = codes.get(&(current, index)) {
+            prefix = Some(code);
+            continue;
+        }
+
+        writer.write(current, size);
+        if next < 1 << MAX_CODE_SIZE {
+            codes.insert((current, index), next);
+            next += 1;
+            if next > (1 << size) && size < MAX_CODE_SIZE {
+                size += 1;
+            }
+        } else {
+            writer.write(clear, size);
+            codes.clear();
+            next = end + 1;
+            size = min_code_size + 1;
+        }
+        prefix = Some(index as u16);
+    }
+    if let Some(current) = prefix {
+        writer.write(current, size);
+    }
+    writer.write(end, size);
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_are_cropped_and_merged() {
+        let path = std::env::temp_dir().join("gbc_rs_gif_test.gif");
+        let path = path.to_string_lossy().to_string();
+        let (white, black) = (0xFF_FF_FF_FF, 0xFF_00_00_00);
+        let mut gif = GifWriter::create(&path, 4, 4, &[white, black], 4_194_304, 70224).unwrap();
+        let mut frame = [white; 16];
+        gif.write_frame(&frame).unwrap();
+        gif.write_frame(&frame).unwrap();
+        frame[6] = black;
+        gif.write_frame(&frame).unwrap();
+        assert_eq!(gif.frames(), 3);
+        gif.finish().unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(&data[..10], b"GIF89a\x04\x00\x04\x00");
+        let images: Vec<usize> = (0..data.len() - 4).filter(|&i| data[i..i + 4] == [0x21, 0xF9, 0x04, DISPOSAL_KEEP]).collect();
+        assert_eq!(images.len(), 2);
+        // The two identical frames share one image of 3.3 hundredths, the next only
+        // covers the changed pixel and is held for at least MIN_DELAY.
+        assert_eq!(&data[images[0] + 4..images[0] + 6], &[3, 0]);
+        assert_eq!(&data[images[1] + 9..images[1] + 17], &[2, 0, 1, 0, 1, 0, 1, 0]);
+        assert_eq!(data.last(), Some(&0x3B));
+    }
+}
@@ -1,3 +1,22 @@
+pub mod gif;
+pub mod png;
+pub mod y4m;
+
+/// CRC-32 as used by PNG and zlib (polynomial 0xEDB88320).
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0xEDB8_8320,
+                _ => crc >> 1,
+            };
+        }
+    }
+    !crc
+}
+
 pub fn modify_bit(val: u8, bit: u8, set: bool) -> u8 {
     if set {
         val | (1 << bit)
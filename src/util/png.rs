@@ -0,0 +1,452 @@
+use std::fs::File;
+use std::io::Write;
+use crate::util::crc32;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+const COLOR_TYPE_RGB: u8 = 2;
+const STORED_BLOCK_MAX: usize = 0xFFFF;
+/// Largest image `decode_rgb` accepts, 32M pixels.
+const MAX_PIXELS: u64 = 1 << 25;
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+/// zlib stream made of uncompressed deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut chunks = data.chunks(STORED_BLOCK_MAX).peekable();
+    if chunks.peek().is_none() {
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(chunk) = chunks.next() {
+        out.push(chunks.peek().is_none() as u8);
+        let len = chunk.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(chunk);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Encodes 8-bit RGB pixels (`width * height * 3` bytes, row major) as a PNG file.
+pub fn encode_rgb(width: u32, height: u32, rgb: &[u8]) -> Vec<u8> {
+    let stride = width as usize * 3;
+    let mut raw = Vec::with_capacity((stride + 1) * height as usize);
+    for row in rgb.chunks(stride).take(height as usize) {
+        raw.push(0); // filter: none
+        raw.extend_from_slice(row);
+    }
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    header.extend_from_slice(&[8, COLOR_TYPE_RGB, 0, 0, 0]);
+
+    let mut out = PNG_SIGNATURE.to_vec();
+    write_chunk(&mut out, b"IHDR", &header);
+    write_chunk(&mut out, b"IDAT", &zlib_stored(&raw));
+    write_chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+pub fn save_rgb(path: &str, width: u32, height: u32, rgb: &[u8]) -> std::io::Result<()> {
+    File::create(path)?.write_all(&encode_rgb(width, height, rgb))
+}
+
+/// Encodes 0xAARRGGBB pixels as a PNG, every pixel blown up to `scale` x `scale`.
+pub fn encode_argb(width: u32, height: u32, argb: &[u32], scale: u32) -> Vec<u8> {
+    let scale = scale.max(1) as usize;
+    let mut rgb = Vec::with_capacity(argb.len() * scale * scale * 3);
+    for row in argb.chunks(width as usize).take(height as usize) {
+        let line: Vec<u8> = row
+            .iter()
+            .flat_map(|pixel| pixel.to_be_bytes()[1..].repeat(scale))
+            .collect();
+        for _ in 0..scale {
+            rgb.extend_from_slice(&line);
+        }
+    }
+    encode_rgb(width * scale as u32, height * scale as u32, &rgb)
+}
+
+#[derive(Debug)]
+pub enum PngError {
+    InvalidSignature,
+    InvalidData,
+    Unsupported(String),
+    IoError(std::io::Error),
+}
+
+impl From<std::io::Error> for PngError {
+    fn from(e: std::io::Error) -> PngError {
+        PngError::IoError(e)
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163,
+    195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073,
+    4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u8,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, count: u8) -> Result<u32, PngError> {
+        let mut value = 0;
+        for i in 0..count {
+            let byte = *self.data.get(self.pos).ok_or(PngError::InvalidData)?;
+            value |= (((byte >> self.bit) & 1) as u32) << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.pos += 1;
+            }
+        }
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+}
+
+/// Canonical Huffman table: number of codes per length and the symbols in code order.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, PngError> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(PngError::InvalidData)
+    }
+}
+
+fn inflate_block(reader: &mut BitReader, out: &mut Vec<u8>, limit: usize, lit: &Huffman, dist: &Huffman) -> Result<(), PngError> {
+    while out.len() < limit {
+        let symbol = lit.decode(reader)? as usize;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let i = symbol - 257;
+                if i >= LENGTH_BASE.len() {
+                    return Err(PngError::InvalidData);
+                }
+                let length = LENGTH_BASE[i] as usize + reader.bits(LENGTH_EXTRA[i])? as usize;
+                let d = dist.decode(reader)? as usize;
+                if d >= DIST_BASE.len() {
+                    return Err(PngError::InvalidData);
+                }
+                let distance = DIST_BASE[d] as usize + reader.bits(DIST_EXTRA[d])? as usize;
+                if distance > out.len() {
+                    return Err(PngError::InvalidData);
+                }
+                let start = out.len() - distance;
+                for i in 0..length.min(limit - out.len()) {
+                    out.push(out[start + i]);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn dynamic_tables(reader: &mut BitReader) -> Result<(Huffman, Huffman), PngError> {
+    let hlit = reader.bits(5)? as usize + 257;
+    let hdist = reader.bits(5)? as usize + 1;
+    let hclen = reader.bits(4)? as usize + 4;
+
+    let mut code_lengths = [0u8; 19];
+    for &i in CODE_LENGTH_ORDER.iter().take(hclen) {
+        code_lengths[i] = reader.bits(3)? as u8;
+    }
+    let code_table = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::with_capacity(hlit + hdist);
+    while lengths.len() < hlit + hdist {
+        let (value, repeat) = match code_table.decode(reader)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => (*lengths.last().ok_or(PngError::InvalidData)?, 3 + reader.bits(2)?),
+            17 => (0, 3 + reader.bits(3)?),
+            _ => (0, 11 + reader.bits(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(value, repeat as usize));
+    }
+    if lengths.len() != hlit + hdist {
+        return Err(PngError::InvalidData);
+    }
+
+    Ok((Huffman::new(&lengths[..hlit]), Huffman::new(&lengths[hlit..])))
+}
+
+/// Decompresses a zlib stream, stopping after `limit` bytes.
+fn inflate(data: &[u8], limit: usize) -> Result<Vec<u8>, PngError> {
+    if data.len() < 2 || (data[0] & 0x0F) != 8 {
+        return Err(PngError::InvalidData);
+    }
+
+    let mut reader = BitReader { data: &data[2..], pos: 0, bit: 0 };
+    let mut out = Vec::new();
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                reader.align();
+                let header = reader.data.get(reader.pos..reader.pos + 4).ok_or(PngError::InvalidData)?;
+                let len = u16::from_le_bytes([header[0], header[1]]) as usize;
+                reader.pos += 4;
+                let block = reader.data.get(reader.pos..reader.pos + len).ok_or(PngError::InvalidData)?;
+                out.extend_from_slice(&block[..len.min(limit - out.len())]);
+                reader.pos += len;
+            }
+            1 => {
+                let mut lengths = [8u8; 288];
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                inflate_block(&mut reader, &mut out, limit, &Huffman::new(&lengths), &Huffman::new(&[5; 30]))?;
+            }
+            2 => {
+                let (lit, dist) = dynamic_tables(&mut reader)?;
+                inflate_block(&mut reader, &mut out, limit, &lit, &dist)?;
+            }
+            _ => return Err(PngError::InvalidData),
+        }
+        if last || out.len() >= limit {
+            return Ok(out);
+        }
+    }
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Decodes a non-interlaced PNG into 8-bit RGB pixels. Alpha is dropped.
+pub fn decode_rgb(bytes: &[u8]) -> Result<(u32, u32, Vec<u8>), PngError> {
+    if bytes.len() < 8 || bytes[..8] != PNG_SIGNATURE {
+        return Err(PngError::InvalidSignature);
+    }
+
+    let mut header = None;
+    let mut palette = Vec::new();
+    let mut idat = Vec::new();
+    let mut pos = 8;
+    while pos + 8 <= bytes.len() {
+        let len = u32::from_be_bytes(bytes[pos..pos + 4].try_into().unwrap()) as usize;
+        let kind = &bytes[pos + 4..pos + 8];
+        let data = bytes.get(pos + 8..pos + 8 + len).ok_or(PngError::InvalidData)?;
+        match kind {
+            b"IHDR" if len == 13 => header = Some(data.to_vec()),
+            b"PLTE" => palette = data.to_vec(),
+            b"IDAT" => idat.extend_from_slice(data),
+            b"IEND" => break,
+            _ => {}
+        }
+        pos += 12 + len;
+    }
+
+    let header = header.ok_or(PngError::InvalidData)?;
+    let width = u32::from_be_bytes(header[0..4].try_into().unwrap());
+    let height = u32::from_be_bytes(header[4..8].try_into().unwrap());
+    let (depth, color_type, interlace) = (header[8] as usize, header[9], header[12]);
+    if interlace != 0 {
+        return Err(PngError::Unsupported("interlaced image".to_string()));
+    }
+    let channels = match color_type {
+        0 | 3 => 1,
+        2 => 3,
+        4 => 2,
+        6 => 4,
+        _ => return Err(PngError::Unsupported(format!("color type {}", color_type))),
+    };
+    let depth_allowed = match color_type {
+        0 => matches!(depth, 1 | 2 | 4 | 8 | 16),
+        3 => matches!(depth, 1 | 2 | 4 | 8),
+        _ => matches!(depth, 8 | 16),
+    };
+    if width == 0 || height == 0 || width as u64 * height as u64 > MAX_PIXELS || !depth_allowed {
+        return Err(PngError::InvalidData);
+    }
+
+    let stride = (width as usize * channels * depth).div_ceil(8);
+    let raw = inflate(&idat, (stride + 1) * height as usize)?;
+    let bpp = (channels * depth).div_ceil(8);
+    if raw.len() < (stride + 1) * height as usize {
+        return Err(PngError::InvalidData);
+    }
+
+    // Undo the per-row filters.
+    let mut pixels = vec![0u8; stride * height as usize];
+    for y in 0..height as usize {
+        let filter = raw[y * (stride + 1)];
+        let src = &raw[y * (stride + 1) + 1..(y + 1) * (stride + 1)];
+        for x in 0..stride {
+            let a = if x >= bpp { pixels[y * stride + x - bpp] } else { 0 };
+            let b = if y > 0 { pixels[(y - 1) * stride + x] } else { 0 };
+            let c = if x >= bpp && y > 0 { pixels[(y - 1) * stride + x - bpp] } else { 0 };
+            pixels[y * stride + x] = src[x].wrapping_add(match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err(PngError::InvalidData),
+            });
+        }
+    }
+
+    let sample = |row: &[u8], index: usize| -> u8 {
+        match depth {
+            16 => row[index * 2],
+            8 => row[index],
+            _ => {
+                let bit = index * depth;
+                let mask = (1u16 << depth) - 1;
+                ((row[bit / 8] as u16 >> (8 - depth - bit % 8)) & mask) as u8
+            }
+        }
+    };
+    let scale = |value: u8| -> u8 {
+        match depth {
+            1 | 2 | 4 => (value as u16 * 255 / ((1u16 << depth) - 1)) as u8,
+            _ => value,
+        }
+    };
+
+    let mut rgb = Vec::with_capacity(width as usize * height as usize * 3);
+    for row in pixels.chunks(stride) {
+        for x in 0..width as usize {
+            match color_type {
+                3 => {
+                    let index = sample(row, x) as usize * 3;
+                    let color = palette.get(index..index + 3).ok_or(PngError::InvalidData)?;
+                    rgb.extend_from_slice(color);
+                }
+                0 | 4 => rgb.extend_from_slice(&[scale(sample(row, x * channels)); 3]),
+                _ => (0..3).for_each(|c| rgb.push(sample(row, x * channels + c))),
+            }
+        }
+    }
+
+    Ok((width, height, rgb))
+}
+
+pub fn load_rgb(path: &str) -> Result<(u32, u32, Vec<u8>), PngError> {
+    decode_rgb(&std::fs::read(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scaled_argb_round_trip() {
+        let pixels = [0xFF_11_22_33, 0xFF_44_55_66];
+        let (width, height, rgb) = decode_rgb(&encode_argb(2, 1, &pixels, 2)).unwrap();
+        assert_eq!((width, height), (4, 2));
+        assert_eq!(&rgb[0..6], &[0x11, 0x22, 0x33, 0x11, 0x22, 0x33]);
+        assert_eq!(&rgb[12..18], &rgb[0..6]);
+        assert_eq!(&rgb[21..24], &[0x44, 0x55, 0x66]);
+    }
+
+    #[test]
+    fn test_invalid_header_is_rejected() {
+        let png = encode_rgb(2, 2, &[0; 12]);
+        let patched = |offset: usize, value: &[u8]| {
+            let mut png = png.clone();
+            png[16 + offset..16 + offset + value.len()].copy_from_slice(value);
+            decode_rgb(&png)
+        };
+        // Zero width or height, 65536 x 65536 pixels.
+        assert!(matches!(patched(0, &[0; 4]), Err(PngError::InvalidData)));
+        assert!(matches!(patched(4, &[0; 4]), Err(PngError::InvalidData)));
+        assert!(matches!(patched(0, &[0, 1, 0, 0, 0, 1, 0, 0]), Err(PngError::InvalidData)));
+        // Depths 3 and 4 for RGB, 16 for a palette.
+        assert!(matches!(patched(8, &[3]), Err(PngError::InvalidData)));
+        assert!(matches!(patched(8, &[4]), Err(PngError::InvalidData)));
+        assert!(matches!(patched(8, &[16, 3]), Err(PngError::InvalidData)));
+        assert!(patched(8, &[8]).is_ok());
+        // Rows past the height are not decompressed.
+        assert!(matches!(patched(4, &[0, 0, 0, 1]), Ok((2, 1, rgb)) if rgb.len() == 6));
+    }
+
+    #[test]
+    fn test_inflate_stops_at_limit() {
+        let stored = [0x78, 0x01, 0x01, 0x08, 0x00, 0xF7, 0xFF, 1, 2, 3, 4, 5, 6, 7, 8];
+        assert_eq!(inflate(&stored, 4).unwrap(), [1, 2, 3, 4]);
+        assert_eq!(inflate(&stored, 16).unwrap().len(), 8);
+    }
+}
@@ -0,0 +1,87 @@
+/*
+    YUV4MPEG2, the raw video stream ffmpeg and most encoders read:
+    "YUV4MPEG2 W<width> H<height> F<num>:<den> Ip A1:1 C444\n" once, then for every frame
+    "FRAME\n" followed by the Y, U and V planes, one byte per pixel each.
+
+    Colours are converted with the BT.601 limited range matrix, what players assume for
+    Y4M without further tags.
+*/
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+pub struct Y4mWriter {
+    writer: BufWriter<File>,
+    pixels: usize,
+    frames: u32,
+}
+
+impl Y4mWriter {
+    /// Frames are `width` x `height`, shown at `rate_num / rate_den` per second.
+    pub fn create(path: &str, width: u32, height: u32, rate_num: u32, rate_den: u32) -> std::io::Result<Y4mWriter> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444", width, height, rate_num, rate_den)?;
+        Ok(Y4mWriter {
+            writer,
+            pixels: (width * height) as usize,
+            frames: 0,
+        })
+    }
+
+    /// Appends a frame of 0xAARRGGBB pixels.
+    pub fn write_frame(&mut self, argb: &[u32]) -> std::io::Result<()> {
+        let mut planes = vec![0u8; self.pixels * 3];
+        for (i, &pixel) in argb.iter().take(self.pixels).enumerate() {
+            let (y, u, v) = rgb_to_yuv(pixel);
+            planes[i] = y;
+            planes[self.pixels + i] = u;
+            planes[self.pixels * 2 + i] = v;
+        }
+        self.writer.write_all(b"FRAME\n")?;
+        self.writer.write_all(&planes)?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    pub fn finish(mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+fn rgb_to_yuv(pixel: u32) -> (u8, u8, u8) {
+    let r = ((pixel >> 16) & 0xFF) as i32;
+    let g = ((pixel >> 8) & 0xFF) as i32;
+    let b = (pixel & 0xFF) as i32;
+    let y = ((66 * r + 129 * g + 25 * b + 128) >> 8) + 16;
+    let u = ((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128;
+    let v = ((112 * r - 94 * g - 18 * b + 128) >> 8) + 128;
+    (y as u8, u as u8, v as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_and_frame_layout() {
+        let path = std::env::temp_dir().join("gbc_rs_y4m_test.y4m");
+        let path = path.to_string_lossy().to_string();
+        let mut writer = Y4mWriter::create(&path, 2, 1, 4_194_304, 70224).unwrap();
+        writer.write_frame(&[0xFF_FF_FF_FF, 0xFF_00_00_00]).unwrap();
+        writer.write_frame(&[0xFF_00_00_00, 0xFF_FF_FF_FF]).unwrap();
+        assert_eq!(writer.frames(), 2);
+        writer.finish().unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        let header = b"YUV4MPEG2 W2 H1 F4194304:70224 Ip A1:1 C444\n";
+        assert_eq!(&data[..header.len()], header);
+        let frame = &data[header.len()..];
+        assert_eq!(frame.len(), 2 * (6 + 2 * 3));
+        assert_eq!(&frame[..12], b"FRAME\n\xEB\x10\x80\x80\x80\x80");
+        std::fs::remove_file(&path).unwrap();
+    }
+}